@@ -0,0 +1,211 @@
+use crate::control::{self, ControlRequest};
+use crate::metrics;
+use crate::state::ListenerState;
+use anyhow::{Context, Result};
+use std::net::SocketAddr;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, warn};
+
+/// Header lines read before a request is rejected
+const MAX_HEADERS: usize = 100;
+
+/// Answer to an admin request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    fn text(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("{}\n", body),
+        }
+    }
+
+    fn json(value: &impl serde::Serialize) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => Self {
+                status: 200,
+                content_type: "application/json",
+                body,
+            },
+            Err(err) => Self::text(500, &err.to_string()),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        }
+    }
+
+    fn encode(&self) -> String {
+        format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            self.reason(),
+            self.content_type,
+            self.body.len(),
+            self.body
+        )
+    }
+}
+
+/// Answer a request to the admin API
+///
+/// Control endpoints take POST and answer with the listener status, as the
+/// control socket does.
+pub fn route(state: &ListenerState, method: &str, path: &str) -> Response {
+    let path = path.split('?').next().unwrap_or(path);
+    let control = match path {
+        "/pause" => Some(ControlRequest::Pause),
+        "/continue" => Some(ControlRequest::Continue),
+        "/flush" => Some(ControlRequest::Flush),
+        _ => None,
+    };
+
+    match (method, path, control) {
+        ("POST", _, Some(request)) => Response::json(&control::handle(state, request)),
+        (_, _, Some(_)) => Response::text(405, "Use POST"),
+        ("GET", "/healthz", _) => Response::text(200, "ok"),
+        ("GET", "/readyz", _) if state.is_ready() => Response::text(200, "ready"),
+        ("GET", "/readyz", _) => Response::text(503, "not ready"),
+        ("GET", "/status", _) => Response::json(&state.snapshot()),
+        ("GET", "/metrics", _) => Response {
+            status: 200,
+            content_type: metrics::CONTENT_TYPE,
+            body: metrics::render(state),
+        },
+        (_, "/healthz" | "/readyz" | "/status" | "/metrics", _) => Response::text(405, "Use GET"),
+        _ => Response::text(404, "Not found"),
+    }
+}
+
+/// HTTP admin API served for the lifetime of a listener
+pub struct AdminServer {
+    address: SocketAddr,
+    task: tokio::task::JoinHandle<()>,
+}
+
+#[allow(dead_code)]
+impl AdminServer {
+    /// Bind `address` and serve it on the current runtime
+    pub async fn start(address: &str, state: ListenerState) -> Result<Self> {
+        let listener = TcpListener::bind(address)
+            .await
+            .with_context(|| format!("Failed to bind admin API to {}", address))?;
+        let address = listener.local_addr()?;
+        info!("Admin API listening on http://{}", address);
+
+        let task = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(Self::serve_connection(stream, state.clone()));
+                    }
+                    Err(err) => warn!("Admin API accept failed: {}", err),
+                }
+            }
+        });
+
+        Ok(Self { address, task })
+    }
+
+    /// Address actually bound, which differs from the configured one for port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Answer one request and close the connection
+    async fn serve_connection(stream: TcpStream, state: ListenerState) {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        let response = match lines.next_line().await {
+            Ok(Some(request_line)) => {
+                // The request body, if any, is never needed
+                let mut headers = 0;
+                loop {
+                    match lines.next_line().await {
+                        Ok(Some(line)) if !line.is_empty() && headers < MAX_HEADERS => headers += 1,
+                        _ => break,
+                    }
+                }
+
+                let mut parts = request_line.split_whitespace();
+                match (parts.next(), parts.next()) {
+                    (Some(method), Some(path)) => {
+                        debug!("Admin request: {} {}", method, path);
+                        route(&state, method, path)
+                    }
+                    _ => Response::text(400, "Malformed request line"),
+                }
+            }
+            _ => return,
+        };
+
+        let _ = writer.write_all(response.encode().as_bytes()).await;
+        let _ = writer.shutdown().await;
+    }
+}
+
+impl Drop for AdminServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn test_route() {
+        let state = ListenerState::new();
+
+        assert_eq!(route(&state, "GET", "/healthz").status, 200);
+        assert_eq!(route(&state, "GET", "/readyz").status, 503);
+        state.set_ready();
+        assert_eq!(route(&state, "GET", "/readyz").status, 200);
+
+        let response = route(&state, "POST", "/pause");
+        assert_eq!(response.status, 200);
+        assert!(response.body.contains("\"PAUSED\""));
+        assert!(state.is_paused());
+
+        assert_eq!(route(&state, "GET", "/flush").status, 405);
+        assert!(!state.take_flush_request());
+        assert_eq!(route(&state, "POST", "/metrics").status, 405);
+        assert_eq!(route(&state, "GET", "/nope").status, 404);
+        assert_eq!(route(&state, "GET", "/metrics?name[]=x").content_type, metrics::CONTENT_TYPE);
+    }
+
+    #[tokio::test]
+    async fn test_http_round_trip() {
+        let state = ListenerState::new();
+        let server = AdminServer::start("127.0.0.1:0", state.clone()).await.unwrap();
+
+        let mut stream = TcpStream::connect(server.local_addr()).await.unwrap();
+        stream
+            .write_all(b"POST /flush HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\"pending_changes\":0}}"));
+        assert!(state.take_flush_request());
+    }
+}
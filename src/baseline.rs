@@ -0,0 +1,103 @@
+use crate::db::DbConnection;
+use crate::filter::SchemaFilter;
+use crate::model::SchemaModel;
+use crate::naming;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// File in the output directory recording where the baseline was taken
+pub const MARKER_FILE: &str = "repligrate.baseline.json";
+
+/// Where a baseline migration was taken, so that `listen` carries on from there
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BaselineMarker {
+    pub slot_name: String,
+    /// WAL position the baseline schema corresponds to
+    pub lsn: String,
+    /// Files holding the baseline migration
+    pub migrations: Vec<String>,
+}
+
+#[allow(dead_code)]
+impl BaselineMarker {
+    pub fn path(output_dir: &Path) -> PathBuf {
+        output_dir.join(MARKER_FILE)
+    }
+
+    /// The marker of `output_dir`, if a baseline was taken there
+    pub fn read(output_dir: &Path) -> Result<Option<Self>> {
+        let path = Self::path(output_dir);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = std::fs::read_to_string(&path)?;
+        let marker = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid baseline marker {}", path.display()))?;
+        Ok(Some(marker))
+    }
+
+    /// Write the marker through a rename, so a crash never leaves it half written
+    pub fn write(&self, output_dir: &Path) -> Result<PathBuf> {
+        std::fs::create_dir_all(output_dir)?;
+        let path = Self::path(output_dir);
+        naming::replace_atomic(&path, &format!("{}\n", serde_json::to_string_pretty(self)?))?;
+        Ok(path)
+    }
+}
+
+/// Read the schema `filter` keeps and the WAL position it corresponds to
+///
+/// The capture trigger is installed first, then the slot is created with an
+/// exported snapshot: the schema is read in that snapshot and the position is
+/// the slot's consistent point, so the baseline and the stream are contiguous.
+/// A slot that already exists has no snapshot to offer, and the schema is read
+/// along with the current position instead.
+pub fn take(db: &mut DbConnection, slot_name: &str, filter: &SchemaFilter) -> Result<(SchemaModel, String)> {
+    db.install_capture()?;
+    let slot = db.create_exported_slot(slot_name)?;
+
+    let mut transaction = db.snapshot_transaction(slot.as_ref().map(|slot| slot.snapshot_name.as_str()))?;
+    let lsn = match &slot {
+        Some(slot) => slot.consistent_point.clone(),
+        None => {
+            warn!(
+                "Replication slot {} already exists, the baseline position is not exact",
+                slot_name
+            );
+            transaction
+                .query_one("SELECT pg_current_wal_lsn()::text", &[])?
+                .get(0)
+        }
+    };
+    let mut model = SchemaModel::load(&mut transaction)?;
+    transaction.commit()?;
+    drop(slot);
+
+    model.retain(filter);
+    model.normalize();
+    info!("Baseline taken at {}", lsn);
+    Ok((model, lsn))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marker_round_trip() {
+        let dir = std::env::temp_dir().join(format!("repligrate-baseline-{}", std::process::id()));
+        assert_eq!(BaselineMarker::read(&dir).unwrap(), None);
+
+        let marker = BaselineMarker {
+            slot_name: "repligrate_slot".to_string(),
+            lsn: "0/1A2B3C".to_string(),
+            migrations: vec!["0001_baseline.json".to_string()],
+        };
+        marker.write(&dir).unwrap();
+        let read = BaselineMarker::read(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(read, Some(marker));
+    }
+}
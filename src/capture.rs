@@ -0,0 +1,223 @@
+use crate::ddl;
+use crate::schema::{SchemaChange, SchemaChangeParser};
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::debug;
+
+/// Schema holding repligrate's server-side helpers
+pub const HELPER_SCHEMA: &str = "repligrate";
+
+/// Event trigger forwarding DDL to the replication stream
+pub const EVENT_TRIGGER: &str = "repligrate_ddl_capture";
+
+/// Function of the event trigger, as `regprocedure` prints it
+pub const CAPTURE_FUNCTION: &str = "repligrate.capture_ddl()";
+
+/// Prefix of the logical decoding messages emitted by the event trigger
+pub const MESSAGE_PREFIX: &str = "repligrate";
+
+/// `application_name` of repligrate's own sessions, whose DDL is never captured
+pub const APPLICATION_NAME: &str = "repligrate";
+
+/// Emits one transactional logical message per DDL command, so that captured
+/// statements reach the slot in commit order alongside the data changes
+const CAPTURE_FUNCTION_SQL: &str = r#"
+CREATE OR REPLACE FUNCTION repligrate.capture_ddl() RETURNS event_trigger
+LANGUAGE plpgsql AS $$
+DECLARE
+    commands json;
+BEGIN
+    IF current_setting('application_name') = 'repligrate' THEN
+        RETURN;
+    END IF;
+
+    SELECT coalesce(json_agg(json_build_object(
+               'object_type', object_type,
+               'schema', schema_name,
+               'identity', object_identity)), '[]'::json)
+      INTO commands
+      FROM pg_event_trigger_ddl_commands();
+
+    PERFORM pg_logical_emit_message(true, 'repligrate', json_build_object(
+        'tag', tg_tag,
+        'query', current_query(),
+        'application_name', current_setting('application_name'),
+        'current_schema', current_schema(),
+        'commands', commands)::text);
+END;
+$$"#;
+
+/// Statements creating the helper schema and capture function
+pub fn install_sql() -> Vec<String> {
+    vec![
+        format!("CREATE SCHEMA IF NOT EXISTS {}", HELPER_SCHEMA),
+        CAPTURE_FUNCTION_SQL.trim().to_string(),
+    ]
+}
+
+/// Statement creating the event trigger (it has no IF NOT EXISTS form)
+pub fn event_trigger_sql() -> String {
+    format!(
+        "CREATE EVENT TRIGGER {} ON ddl_command_end EXECUTE FUNCTION {}.capture_ddl()",
+        EVENT_TRIGGER, HELPER_SCHEMA
+    )
+}
+
+/// Payload of a capture message
+#[derive(Debug, Clone, Deserialize)]
+pub struct CapturedDdl {
+    pub tag: String,
+    pub query: String,
+    pub application_name: Option<String>,
+    pub current_schema: Option<String>,
+    #[serde(default)]
+    pub commands: Vec<CapturedCommand>,
+}
+
+/// Object touched by a captured DDL command, as reported by `pg_event_trigger_ddl_commands()`
+#[derive(Debug, Clone, Deserialize)]
+pub struct CapturedCommand {
+    pub object_type: Option<String>,
+    pub schema: Option<String>,
+    pub identity: Option<String>,
+}
+
+impl CapturedDdl {
+    pub fn decode(content: &str) -> Result<Self> {
+        Ok(serde_json::from_str(content)?)
+    }
+}
+
+/// Turns capture messages into schema changes
+///
+/// `current_query()` returns the whole query string, so a multi-statement
+/// query emits one message per DDL statement with the same text. The decoder
+/// tracks how far into each query string a transaction has got, and picks
+/// the statement that matches the command tag.
+#[derive(Default)]
+pub struct CaptureDecoder {
+    xid: String,
+    cursors: HashMap<String, usize>,
+}
+
+impl CaptureDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn decode(&mut self, lsn: &str, xid: &str, ddl: &CapturedDdl) -> Option<SchemaChange> {
+        if self.xid != xid {
+            self.xid = xid.to_string();
+            self.cursors.clear();
+        }
+
+        let statement = self.next_statement(ddl);
+        let (change_type, object_name) = SchemaChangeParser::parse(&statement)?;
+
+        let (qualified_schema, _) = ddl::split_qualified_name(&object_name);
+        let schema_name = qualified_schema
+            .or_else(|| ddl.commands.iter().find_map(|command| command.schema.clone()))
+            .or_else(|| ddl.current_schema.clone())
+            .unwrap_or_else(|| "public".to_string());
+
+        debug!("Captured {} at {}: {}", ddl.tag, lsn, statement);
+
+        let mut change = SchemaChange::new(change_type, schema_name, object_name, statement)
+            .with_metadata("command_tag".to_string(), ddl.tag.clone())
+            .with_metadata("lsn".to_string(), lsn.to_string())
+            .with_metadata("xid".to_string(), xid.to_string());
+        if let Some(application_name) = &ddl.application_name {
+            change = change.with_metadata("application_name".to_string(), application_name.clone());
+        }
+        if let Some(command) = ddl.commands.first() {
+            if let Some(object_type) = &command.object_type {
+                change = change.with_metadata("object_type".to_string(), object_type.clone());
+            }
+            if let Some(identity) = &command.identity {
+                change = change.with_metadata("object_identity".to_string(), identity.clone());
+            }
+        }
+
+        Some(change)
+    }
+
+    /// The statement of the query string this message was emitted for
+    fn next_statement(&mut self, ddl: &CapturedDdl) -> String {
+        let statements: Vec<String> = ddl::split_statements(&ddl.query)
+            .into_iter()
+            .filter(|statement| ddl::is_ddl_statement(statement))
+            .collect();
+        if statements.len() <= 1 {
+            return statements
+                .into_iter()
+                .next()
+                .unwrap_or_else(|| ddl.query.trim().to_string());
+        }
+
+        let verb = ddl.tag.split_whitespace().next().unwrap_or_default();
+        let cursor = self.cursors.entry(ddl.query.clone()).or_insert(0);
+        let position = statements[(*cursor).min(statements.len())..]
+            .iter()
+            .position(|statement| {
+                statement
+                    .split_whitespace()
+                    .next()
+                    .is_some_and(|word| word.eq_ignore_ascii_case(verb))
+            })
+            .map(|offset| *cursor + offset)
+            .unwrap_or(*cursor)
+            .min(statements.len() - 1);
+
+        *cursor = position + 1;
+        statements[position].clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::ChangeType;
+
+    fn captured(tag: &str, query: &str) -> CapturedDdl {
+        CapturedDdl {
+            tag: tag.to_string(),
+            query: query.to_string(),
+            application_name: Some("psql".to_string()),
+            current_schema: Some("app".to_string()),
+            commands: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_decode_message() {
+        let ddl = CapturedDdl::decode(
+            r#"{"tag": "CREATE TABLE", "query": "CREATE TABLE t (id int)", "application_name": "psql",
+                "current_schema": "public", "commands": [{"object_type": "table", "schema": "public", "identity": "public.t"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(ddl.commands.len(), 1);
+
+        let change = CaptureDecoder::new().decode("0/1", "7", &ddl).unwrap();
+        assert_eq!(change.change_type, ChangeType::CreateTable);
+        assert_eq!(change.schema_name, "public");
+        assert_eq!(change.details.metadata["application_name"], "psql");
+        assert_eq!(change.details.metadata["object_identity"], "public.t");
+    }
+
+    #[test]
+    fn test_multi_statement_query() {
+        let query = "CREATE TABLE a (id int); INSERT INTO a VALUES (1); ALTER TABLE a ADD COLUMN b int;";
+        let mut decoder = CaptureDecoder::new();
+
+        let first = decoder.decode("0/1", "7", &captured("CREATE TABLE", query)).unwrap();
+        let second = decoder.decode("0/2", "7", &captured("ALTER TABLE", query)).unwrap();
+        assert_eq!(first.details.sql, "CREATE TABLE a (id int)");
+        assert_eq!(second.details.sql, "ALTER TABLE a ADD COLUMN b int");
+        assert_eq!(second.schema_name, "app");
+
+        // A new transaction running the same text starts from the beginning again
+        let again = decoder.decode("0/3", "8", &captured("CREATE TABLE", query)).unwrap();
+        assert_eq!(again.details.sql, "CREATE TABLE a (id int)");
+    }
+}
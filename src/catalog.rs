@@ -0,0 +1,383 @@
+use crate::ddl;
+use crate::ident::QualifiedName;
+use crate::schema::{ChangeType, ColumnInfo, ConstraintInfo, SchemaChange};
+use anyhow::Result;
+use postgres::GenericClient;
+use serde::{Deserialize, Serialize};
+
+/// Index of a table as PostgreSQL reports it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexDefinition {
+    pub name: String,
+    /// `CREATE INDEX` statement from `pg_get_indexdef`
+    pub definition: String,
+    pub unique: bool,
+    pub primary: bool,
+}
+
+/// Columns of `relation`, in table order
+///
+/// `relation` is any name `to_regclass` resolves. Columns backed by an owned
+/// sequence are reported with their `serial` type and no default, and
+/// identity and generated columns carry the clause that defines them.
+pub fn columns(client: &mut impl GenericClient, relation: &str) -> Result<Vec<ColumnInfo>> {
+    let rows = client.query(
+        "SELECT a.attname::text,
+                format_type(a.atttypid, a.atttypmod),
+                NOT a.attnotnull,
+                pg_get_expr(d.adbin, d.adrelid),
+                a.attidentity::text,
+                a.attgenerated::text,
+                pg_get_serial_sequence(a.attrelid::regclass::text, a.attname) IS NOT NULL
+         FROM pg_attribute a
+         LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+         WHERE a.attrelid = to_regclass($1) AND a.attnum > 0 AND NOT a.attisdropped
+         ORDER BY a.attnum",
+        &[&relation],
+    )?;
+
+    Ok(rows
+        .iter()
+        .map(|row| {
+            column_from_catalog(CatalogColumn {
+                name: row.get(0),
+                data_type: row.get(1),
+                nullable: row.get(2),
+                default: row.get(3),
+                identity: row.get(4),
+                generated: row.get(5),
+                owns_sequence: row.get(6),
+            })
+        })
+        .collect())
+}
+
+/// A row of the `columns` query
+struct CatalogColumn {
+    name: String,
+    data_type: String,
+    nullable: bool,
+    default: Option<String>,
+    /// `pg_attribute.attidentity`
+    identity: String,
+    /// `pg_attribute.attgenerated`
+    generated: String,
+    owns_sequence: bool,
+}
+
+/// Column as written in a statement: serial types for columns owning their
+/// sequence, and identity and generated columns with their clause
+fn column_from_catalog(row: CatalogColumn) -> ColumnInfo {
+    let mut column = ColumnInfo {
+        name: row.name,
+        data_type: row.data_type,
+        nullable: row.nullable,
+        default: row.default,
+        constraints: Vec::new(),
+    };
+
+    match (row.identity.as_str(), row.generated.as_str()) {
+        ("a", _) => column.constraints.push("GENERATED ALWAYS AS IDENTITY".to_string()),
+        ("d", _) => column.constraints.push("GENERATED BY DEFAULT AS IDENTITY".to_string()),
+        (_, "s") => {
+            let expression = column.default.take().unwrap_or_default();
+            column
+                .constraints
+                .push(format!("GENERATED ALWAYS AS ({}) STORED", expression));
+        }
+        _ if row.owns_sequence && is_nextval(column.default.as_deref()) => {
+            if let Some(serial) = serial_type(&column.data_type) {
+                column.data_type = serial.to_string();
+                column.default = None;
+            }
+        }
+        _ => {}
+    }
+    column
+}
+
+/// Constraints defined on `relation` itself, in creation order
+///
+/// Inherited constraints and NOT NULL constraints (reported through
+/// `ColumnInfo::nullable`) are left out.
+pub fn constraints(client: &mut impl GenericClient, relation: &str) -> Result<Vec<ConstraintInfo>> {
+    let rows = client.query(
+        "SELECT c.conname::text,
+                pg_get_constraintdef(c.oid),
+                array(SELECT a.attname::text
+                      FROM unnest(c.conkey) WITH ORDINALITY AS k(attnum, position)
+                      JOIN pg_attribute a ON a.attrelid = c.conrelid AND a.attnum = k.attnum
+                      ORDER BY k.position)
+         FROM pg_constraint c
+         WHERE c.conrelid = to_regclass($1) AND c.conislocal AND c.contype IN ('p', 'u', 'c', 'f', 'x')
+         ORDER BY c.oid",
+        &[&relation],
+    )?;
+
+    Ok(rows
+        .iter()
+        .filter_map(|row| {
+            let definition: String = row.get(1);
+            Some(ConstraintInfo {
+                name: row.get(0),
+                constraint_type: ddl::constraint_type(&definition)?.to_string(),
+                definition,
+                columns: row.get(2),
+            })
+        })
+        .collect())
+}
+
+/// Indexes of the table `relation`
+#[allow(dead_code)]
+pub fn indexes(client: &mut impl GenericClient, relation: &str) -> Result<Vec<IndexDefinition>> {
+    let rows = client.query(
+        "SELECT c.relname::text, pg_get_indexdef(i.indexrelid), i.indisunique, i.indisprimary
+         FROM pg_index i
+         JOIN pg_class c ON c.oid = i.indexrelid
+         WHERE i.indrelid = to_regclass($1)
+         ORDER BY c.relname",
+        &[&relation],
+    )?;
+    Ok(rows.iter().map(index_from_row).collect())
+}
+
+/// The index named `index`, if it exists
+pub fn index(client: &mut impl GenericClient, index: &str) -> Result<Option<IndexDefinition>> {
+    let row = client.query_opt(
+        "SELECT c.relname::text, pg_get_indexdef(i.indexrelid), i.indisunique, i.indisprimary
+         FROM pg_index i
+         JOIN pg_class c ON c.oid = i.indexrelid
+         WHERE i.indexrelid = to_regclass($1)",
+        &[&index],
+    )?;
+    Ok(row.as_ref().map(index_from_row))
+}
+
+fn index_from_row(row: &postgres::Row) -> IndexDefinition {
+    IndexDefinition {
+        name: row.get(0),
+        definition: row.get(1),
+        unique: row.get(2),
+        primary: row.get(3),
+    }
+}
+
+fn is_nextval(default: Option<&str>) -> bool {
+    default.is_some_and(|default| default.starts_with("nextval("))
+}
+
+fn serial_type(data_type: &str) -> Option<&'static str> {
+    match data_type {
+        "smallint" => Some("smallserial"),
+        "integer" => Some("serial"),
+        "bigint" => Some("bigserial"),
+        _ => None,
+    }
+}
+
+/// Relation a change is about, as text `to_regclass` resolves
+pub fn relation_of(change: &SchemaChange) -> Option<String> {
+    let metadata = &change.details.metadata;
+    if matches!(metadata.get("object_type").map(String::as_str), Some("table" | "index")) {
+        if let Some(identity) = metadata.get("object_identity") {
+            return Some(identity.clone());
+        }
+    }
+
+    let (schema, name) = ddl::split_qualified_name(&change.object_name);
+    if name.is_empty() {
+        return None;
+    }
+    Some(QualifiedName::new(Some(schema.unwrap_or_else(|| change.schema_name.clone())), name).to_string())
+}
+
+/// Attach the catalog's view of the objects a change created
+///
+/// Only what the change itself introduced is attached: the whole table for
+/// CREATE TABLE, the added columns or constraints for ALTER TABLE, and the
+/// index definition for CREATE INDEX. Nothing is attached when the catalog no
+/// longer matches the statement, for instance because the object was dropped.
+pub fn enrich(client: &mut impl GenericClient, change: SchemaChange) -> Result<SchemaChange> {
+    let Some(relation) = relation_of(&change) else {
+        return Ok(change);
+    };
+    let sql = change.details.sql.clone();
+
+    match change.change_type {
+        ChangeType::CreateTable => {
+            let found = columns(client, &relation)?;
+            if !created_table_matches(&sql, &found) {
+                return Ok(change);
+            }
+            let found_constraints = constraints(client, &relation)?;
+            Ok(change.with_columns(found).with_constraints(found_constraints))
+        }
+        ChangeType::AddColumn => {
+            let Some(found) = added_columns(&sql, columns(client, &relation)?) else {
+                return Ok(change);
+            };
+            let found_constraints = column_constraints(&found, constraints(client, &relation)?);
+            Ok(change.with_columns(found).with_constraints(found_constraints))
+        }
+        ChangeType::AddConstraint => match added_constraints(&sql, constraints(client, &relation)?) {
+            Some(found) => Ok(change.with_constraints(found)),
+            None => Ok(change),
+        },
+        ChangeType::CreateIndex => match index(client, &relation)? {
+            Some(index) => Ok(change.with_metadata("index_definition".to_string(), index.definition)),
+            None => Ok(change),
+        },
+        _ => Ok(change),
+    }
+}
+
+/// Whether the columns `found` are the ones a CREATE TABLE statement declares,
+/// in order; any columns do when the statement cannot be parsed
+fn created_table_matches(sql: &str, found: &[ColumnInfo]) -> bool {
+    match ddl::parse_create_table(sql) {
+        Some((parsed, _)) => parsed.iter().map(|c| &c.name).eq(found.iter().map(|c| &c.name)),
+        None => !found.is_empty(),
+    }
+}
+
+/// The columns of the table an ADD COLUMN statement added, unless one is missing
+fn added_columns(sql: &str, table_columns: Vec<ColumnInfo>) -> Option<Vec<ColumnInfo>> {
+    let added = ddl::added_column_names(sql);
+    let found: Vec<ColumnInfo> = table_columns
+        .into_iter()
+        .filter(|column| added.contains(&column.name))
+        .collect();
+    (!added.is_empty() && found.len() == added.len()).then_some(found)
+}
+
+/// Constraints declared along with new columns: those on them alone
+fn column_constraints(columns: &[ColumnInfo], table_constraints: Vec<ConstraintInfo>) -> Vec<ConstraintInfo> {
+    table_constraints
+        .into_iter()
+        .filter(|constraint| {
+            !constraint.columns.is_empty()
+                && constraint
+                    .columns
+                    .iter()
+                    .all(|name| columns.iter().any(|column| column.name == *name))
+        })
+        .collect()
+}
+
+/// The constraints of the table an ADD CONSTRAINT statement added, unless one is missing
+fn added_constraints(sql: &str, table_constraints: Vec<ConstraintInfo>) -> Option<Vec<ConstraintInfo>> {
+    let names: Vec<String> = ddl::parse_add_constraints(sql)
+        .into_iter()
+        .map(|constraint| constraint.name)
+        .collect();
+    let found: Vec<ConstraintInfo> = table_constraints
+        .into_iter()
+        .filter(|constraint| names.contains(&constraint.name))
+        .collect();
+    (!names.is_empty() && found.len() == names.len()).then_some(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalog_column(name: &str, data_type: &str, default: Option<&str>) -> CatalogColumn {
+        CatalogColumn {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            nullable: true,
+            default: default.map(str::to_string),
+            identity: String::new(),
+            generated: String::new(),
+            owns_sequence: false,
+        }
+    }
+
+    fn column(name: &str) -> ColumnInfo {
+        column_from_catalog(catalog_column(name, "integer", None))
+    }
+
+    fn constraint(name: &str, definition: &str, columns: &[&str]) -> ConstraintInfo {
+        ConstraintInfo {
+            name: name.to_string(),
+            constraint_type: ddl::constraint_type(definition).unwrap().to_string(),
+            definition: definition.to_string(),
+            columns: columns.iter().map(|column| column.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_column_from_catalog() {
+        let serial = column_from_catalog(CatalogColumn {
+            owns_sequence: true,
+            ..catalog_column("id", "bigint", Some("nextval('users_id_seq'::regclass)"))
+        });
+        assert_eq!(serial.data_type, "bigserial");
+        assert_eq!(serial.default, None);
+
+        // A nextval default on a sequence the column does not own stays as is
+        let shared = column_from_catalog(catalog_column("id", "integer", Some("nextval('shared'::regclass)")));
+        assert_eq!(shared.data_type, "integer");
+        assert!(shared.default.is_some());
+
+        let identity = column_from_catalog(CatalogColumn {
+            identity: "d".to_string(),
+            ..catalog_column("id", "integer", None)
+        });
+        assert_eq!(identity.constraints, vec!["GENERATED BY DEFAULT AS IDENTITY"]);
+
+        let generated = column_from_catalog(CatalogColumn {
+            generated: "s".to_string(),
+            ..catalog_column("total", "numeric", Some("(price * quantity)"))
+        });
+        assert_eq!(generated.default, None);
+        assert_eq!(generated.constraints, vec!["GENERATED ALWAYS AS ((price * quantity)) STORED"]);
+    }
+
+    #[test]
+    fn test_created_table_matches() {
+        let found = [column("id"), column("email")];
+        assert!(created_table_matches("CREATE TABLE users (id int, email text)", &found));
+        // Dropped or recreated since the statement ran
+        assert!(!created_table_matches("CREATE TABLE users (id int)", &found));
+        assert!(!created_table_matches("CREATE TABLE users (email text, id int)", &found));
+        assert!(created_table_matches("CREATE TABLE users (LIKE template)", &found));
+        assert!(!created_table_matches("CREATE TABLE users (LIKE template)", &[]));
+    }
+
+    #[test]
+    fn test_added_columns_and_their_constraints() {
+        let table = vec![column("id"), column("email"), column("age")];
+        let sql = "ALTER TABLE users ADD COLUMN email text UNIQUE, ADD COLUMN age int";
+        let found = added_columns(sql, table.clone()).unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(added_columns("ALTER TABLE users ADD COLUMN phone text", table.clone()).is_none());
+        assert!(added_columns("ALTER TABLE users ADD CONSTRAINT c CHECK (id > 0)", table).is_none());
+
+        let constraints = column_constraints(
+            &found,
+            vec![
+                constraint("users_pkey", "PRIMARY KEY (id)", &["id"]),
+                constraint("users_email_key", "UNIQUE (email)", &["email"]),
+                constraint("users_email_age_key", "UNIQUE (email, age)", &["email", "age"]),
+                constraint("users_id_email_key", "UNIQUE (id, email)", &["id", "email"]),
+                constraint("users_check", "CHECK (true)", &[]),
+            ],
+        );
+        let names: Vec<&str> = constraints.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["users_email_key", "users_email_age_key"]);
+    }
+
+    #[test]
+    fn test_added_constraints() {
+        let table = vec![
+            constraint("users_pkey", "PRIMARY KEY (id)", &["id"]),
+            constraint("uk_email", "UNIQUE (email)", &["email"]),
+        ];
+        let found = added_constraints("ALTER TABLE users ADD CONSTRAINT uk_email UNIQUE (email)", table.clone());
+        assert_eq!(found.map(|found| found.len()), Some(1));
+        assert!(added_constraints("ALTER TABLE users ADD CONSTRAINT uk_phone UNIQUE (phone)", table.clone()).is_none());
+        assert!(added_constraints("ALTER TABLE users ADD COLUMN phone text", table).is_none());
+    }
+}
@@ -0,0 +1,316 @@
+use crate::capture::{CAPTURE_FUNCTION, HELPER_SCHEMA};
+use crate::db::DbConnection;
+use crate::ident::{quote_ident, quote_literal};
+use anyhow::{bail, Result};
+use tracing::info;
+
+/// Milliseconds to wait for a terminated slot consumer to exit
+const TERMINATE_TIMEOUT_MS: u32 = 5000;
+
+/// One statement removing a server-side object
+#[derive(Debug, Clone)]
+pub struct CleanupStep {
+    pub description: String,
+    pub sql: String,
+}
+
+/// Statements removing everything repligrate created on the server, in the
+/// order they have to run
+#[derive(Debug, Clone, Default)]
+pub struct CleanupPlan {
+    pub steps: Vec<CleanupStep>,
+}
+
+/// repligrate's objects that exist on the server
+#[derive(Debug, Clone, Default)]
+pub struct ServerObjects {
+    pub slot: bool,
+    /// Backend holding the slot, when it is active
+    pub slot_active_pid: Option<i32>,
+    pub publication: bool,
+    pub helper_schema: bool,
+    /// Event triggers calling functions of the helper schema, with the function
+    pub event_triggers: Vec<(String, String)>,
+    /// Functions of the helper schema, as `regprocedure` text
+    pub functions: Vec<String>,
+}
+
+impl ServerObjects {
+    /// Look up the slot, publication, event triggers and helper schema
+    pub fn find(db: &mut DbConnection, slot_name: &str, publication_name: &str) -> Result<Self> {
+        let mut objects = Self::default();
+        if let Some(slot) = db.get_slot_info(slot_name)? {
+            objects.slot = true;
+            objects.slot_active_pid = slot.active_pid;
+        }
+        objects.publication = db.get_publication_info(publication_name)?.is_some();
+        if db.schema_exists(HELPER_SCHEMA)? {
+            objects.helper_schema = true;
+            objects.event_triggers = db.event_triggers_in_schema(HELPER_SCHEMA)?;
+            objects.functions = db.functions_in_schema(HELPER_SCHEMA)?;
+        }
+        Ok(objects)
+    }
+}
+
+/// What running a cleanup plan did
+#[derive(Debug, Clone, PartialEq)]
+pub enum CleanupOutcome {
+    /// None of repligrate's objects exist
+    NothingFound,
+    /// The script a dry run printed instead of running it
+    DryRun(String),
+    /// Descriptions of the steps that ran
+    Done(Vec<String>),
+}
+
+impl CleanupOutcome {
+    /// Exit code of the `cleanup` command
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CleanupOutcome::NothingFound => crate::cli::EXIT_NOTHING_FOUND,
+            _ => 0,
+        }
+    }
+}
+
+impl CleanupPlan {
+    /// Find the slot, publication, event triggers and helper schema that exist
+    ///
+    /// An active slot is only dropped with `force`, after terminating the
+    /// backend that holds it.
+    pub fn discover(
+        db: &mut DbConnection,
+        slot_name: &str,
+        publication_name: &str,
+        force: bool,
+    ) -> Result<Self> {
+        let objects = ServerObjects::find(db, slot_name, publication_name)?;
+        Self::build(&objects, slot_name, publication_name, force)
+    }
+
+    /// The steps removing `objects`
+    pub fn build(
+        objects: &ServerObjects,
+        slot_name: &str,
+        publication_name: &str,
+        force: bool,
+    ) -> Result<Self> {
+        let mut plan = Self::default();
+
+        if objects.slot {
+            if let Some(pid) = objects.slot_active_pid {
+                if !force {
+                    bail!(
+                        "Replication slot {} is in use by backend {}; stop its consumer or pass --force",
+                        slot_name,
+                        pid
+                    );
+                }
+                plan.push(
+                    format!("Terminated backend {} holding slot {}", pid, slot_name),
+                    format!("SELECT pg_terminate_backend({}, {})", pid, TERMINATE_TIMEOUT_MS),
+                );
+            }
+            plan.push(
+                format!("Dropped replication slot {}", slot_name),
+                format!("SELECT pg_drop_replication_slot({})", quote_literal(slot_name)),
+            );
+        }
+
+        if objects.publication {
+            plan.push(
+                format!("Dropped publication {}", publication_name),
+                format!("DROP PUBLICATION {}", quote_ident(publication_name)),
+            );
+        }
+
+        if objects.helper_schema {
+            for (trigger, _) in &objects.event_triggers {
+                plan.push(
+                    format!("Dropped event trigger {}", trigger),
+                    format!("DROP EVENT TRIGGER {}", quote_ident(trigger)),
+                );
+            }
+            // Only the functions repligrate installs; regprocedure output is
+            // already quoted where needed
+            let installed = |function: &&String| {
+                *function == CAPTURE_FUNCTION
+                    || objects.event_triggers.iter().any(|(_, used)| used == *function)
+            };
+            for function in objects.functions.iter().filter(installed) {
+                plan.push(
+                    format!("Dropped function {}", function),
+                    format!("DROP FUNCTION {}", function),
+                );
+            }
+            // Without CASCADE, so objects someone else put there are never removed
+            plan.push(
+                format!("Dropped schema {}", HELPER_SCHEMA),
+                format!("DROP SCHEMA {}", quote_ident(HELPER_SCHEMA)),
+            );
+        }
+
+        Ok(plan)
+    }
+
+    fn push(&mut self, description: String, sql: String) {
+        self.steps.push(CleanupStep { description, sql });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// The plan as a SQL script
+    pub fn to_sql(&self) -> String {
+        self.steps
+            .iter()
+            .map(|step| format!("{};\n", step.sql))
+            .collect()
+    }
+
+    /// Run every step through `execute`, stopping at the first failure, or
+    /// only render the script with `dry_run`
+    pub fn run(
+        &self,
+        dry_run: bool,
+        mut execute: impl FnMut(&str) -> Result<()>,
+    ) -> Result<CleanupOutcome> {
+        if self.is_empty() {
+            return Ok(CleanupOutcome::NothingFound);
+        }
+        if dry_run {
+            return Ok(CleanupOutcome::DryRun(self.to_sql()));
+        }
+
+        let mut done = Vec::new();
+        for step in &self.steps {
+            execute(&step.sql)?;
+            info!("{}", step.description);
+            done.push(step.description.clone());
+        }
+        Ok(CleanupOutcome::Done(done))
+    }
+
+    /// Run every step, stopping at the first failure
+    pub fn execute(&self, db: &mut DbConnection) -> Result<Vec<String>> {
+        match self.run(false, |sql| db.execute_sql(sql))? {
+            CleanupOutcome::Done(done) => Ok(done),
+            _ => Ok(Vec::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn everything() -> ServerObjects {
+        ServerObjects {
+            slot: true,
+            slot_active_pid: Some(4242),
+            publication: true,
+            helper_schema: true,
+            event_triggers: vec![(
+                "repligrate_ddl_capture".to_string(),
+                "repligrate.capture_ddl()".to_string(),
+            )],
+            functions: vec!["repligrate.capture_ddl()".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_to_sql_drops_in_dependency_order() {
+        let plan = CleanupPlan::build(&everything(), "my slot", "repligrate_pub", true).unwrap();
+        assert_eq!(
+            plan.to_sql(),
+            "SELECT pg_terminate_backend(4242, 5000);\n\
+             SELECT pg_drop_replication_slot('my slot');\n\
+             DROP PUBLICATION \"repligrate_pub\";\n\
+             DROP EVENT TRIGGER \"repligrate_ddl_capture\";\n\
+             DROP FUNCTION repligrate.capture_ddl();\n\
+             DROP SCHEMA \"repligrate\";\n"
+        );
+    }
+
+    #[test]
+    fn test_only_installed_functions_are_dropped() {
+        let objects = ServerObjects {
+            event_triggers: vec![(
+                "repligrate_ddl_capture_v2".to_string(),
+                "repligrate.capture_ddl_v2()".to_string(),
+            )],
+            functions: vec![
+                "repligrate.capture_ddl()".to_string(),
+                "repligrate.capture_ddl_v2()".to_string(),
+                "repligrate.audit(integer)".to_string(),
+            ],
+            ..everything()
+        };
+        let sql = CleanupPlan::build(&objects, "repligrate_slot", "repligrate_pub", true)
+            .unwrap()
+            .to_sql();
+
+        assert!(sql.contains("DROP FUNCTION repligrate.capture_ddl();\n"));
+        assert!(sql.contains("DROP FUNCTION repligrate.capture_ddl_v2();\n"));
+        // Left for DROP SCHEMA to refuse
+        assert!(!sql.contains("audit"));
+        assert!(sql.ends_with("DROP SCHEMA \"repligrate\";\n"));
+    }
+
+    #[test]
+    fn test_active_slot_needs_force() {
+        let refused = CleanupPlan::build(&everything(), "repligrate_slot", "repligrate_pub", false);
+        let error = refused.unwrap_err().to_string();
+        assert!(error.contains("in use by backend 4242"), "{}", error);
+
+        let idle = ServerObjects {
+            slot_active_pid: None,
+            ..everything()
+        };
+        let plan = CleanupPlan::build(&idle, "repligrate_slot", "repligrate_pub", false).unwrap();
+        assert!(!plan.to_sql().contains("pg_terminate_backend"));
+    }
+
+    #[test]
+    fn test_dry_run_executes_nothing() {
+        let plan =
+            CleanupPlan::build(&everything(), "repligrate_slot", "repligrate_pub", true).unwrap();
+        let mut executed = Vec::new();
+
+        let outcome = plan
+            .run(true, |sql| {
+                executed.push(sql.to_string());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(outcome, CleanupOutcome::DryRun(plan.to_sql()));
+        assert_eq!(outcome.exit_code(), 0);
+        assert!(executed.is_empty());
+
+        let outcome = plan
+            .run(false, |sql| {
+                executed.push(sql.to_string());
+                Ok(())
+            })
+            .unwrap();
+        assert!(matches!(&outcome, CleanupOutcome::Done(done) if done.len() == 6));
+        assert_eq!(executed.len(), 6);
+    }
+
+    #[test]
+    fn test_nothing_found() {
+        let plan = CleanupPlan::build(
+            &ServerObjects::default(),
+            "repligrate_slot",
+            "repligrate_pub",
+            false,
+        )
+        .unwrap();
+        let outcome = plan.run(false, |_| panic!("nothing to run")).unwrap();
+
+        assert_eq!(outcome, CleanupOutcome::NothingFound);
+        assert_eq!(outcome.exit_code(), crate::cli::EXIT_NOTHING_FOUND);
+    }
+}
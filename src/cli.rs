@@ -1,12 +1,31 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use tracing::info;
 
-use crate::config::Config;
+use crate::admin::AdminServer;
+use crate::baseline::{self, BaselineMarker};
+use crate::cleanup::{CleanupOutcome, CleanupPlan};
+use crate::config::{Config, ConfigSource};
+#[cfg(unix)]
+use crate::control::ControlServer;
+use crate::control::{self, ControlRequest};
+use crate::db::DbConnection;
+use crate::diff;
+use crate::drift::{self, Drift};
+use crate::filter::SchemaFilter;
+use crate::loader;
 use crate::migration::{MigrationGenerator, MigrationWriter};
+use crate::model::SchemaModel;
+use crate::naming::MigrationNamer;
+use crate::optimizer::MigrationOptimizer;
 use crate::replication::ReplicationListener;
-use crate::schema::{ChangeType, SchemaChange};
+use crate::schema::{SchemaChange, SchemaChangeParser};
+use crate::sink::OutputBackend;
+use crate::signals;
+use crate::snapshot::Snapshot;
+use crate::status::{StatusFormat, StatusReport};
 
 #[derive(Parser)]
 #[command(name = "repligrate")]
@@ -50,10 +69,26 @@ pub enum Commands {
         /// Tables to monitor (comma-separated, empty = all)
         #[arg(long)]
         tables: Option<String>,
+
+        /// Serve the admin API on this address (overrides `[admin]`)
+        #[arg(long)]
+        admin_address: Option<String>,
     },
 
     /// Show current replication status
-    Status,
+    Status {
+        /// Replication slot name
+        #[arg(long, default_value = "repligrate_slot")]
+        slot_name: String,
+
+        /// Publication name
+        #[arg(long, default_value = "repligrate_pub")]
+        publication_name: String,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: StatusFormat,
+    },
 
     /// Pause the replication listener
     Pause {
@@ -69,35 +104,104 @@ pub enum Commands {
         slot_name: String,
     },
 
-    /// Clean up replication slot
+    /// Remove the replication slot, publication, event triggers and helper schema
     Cleanup {
         /// Replication slot name
         #[arg(long, default_value = "repligrate_slot")]
         slot_name: String,
+
+        /// Publication name
+        #[arg(long, default_value = "repligrate_pub")]
+        publication_name: String,
+
+        /// Terminate the backend holding an active slot instead of refusing
+        #[arg(long)]
+        force: bool,
+
+        /// Print the SQL instead of running it
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Dump the schema of the filtered schemas as a JSON snapshot
+    Snapshot {
+        /// File to write the snapshot to (default: standard output)
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+
+    /// Generate an initial migration creating the current schema
+    Baseline {
+        /// Replication slot `listen` will carry on with
+        #[arg(long, default_value = "repligrate_slot")]
+        slot_name: String,
+
+        /// Migration name for the `{name}` placeholder
+        #[arg(long, default_value = "baseline")]
+        name: String,
+
+        /// Print the pgroll migration instead of writing it
+        #[arg(long)]
+        stdout: bool,
     },
 
-    /// Generate a test migration
-    Test,
+    /// Generate the migration turning one schema into another
+    Diff {
+        /// Database URL, migrations directory or snapshot file of the current schema
+        #[arg(long)]
+        from: String,
+
+        /// Database URL, migrations directory or snapshot file of the target schema
+        #[arg(long)]
+        to: String,
+
+        /// Migration name for the `{name}` placeholder
+        #[arg(long, default_value = "diff")]
+        name: String,
+
+        /// Print the pgroll migration instead of writing it
+        #[arg(long)]
+        stdout: bool,
+    },
+
+    /// Compare the schema the migrations in the output directory build with the database
+    Drift,
+
+    /// Generate a migration from a SQL script, without a database connection
+    Generate {
+        /// SQL file to read, or `-` for standard input
+        #[arg(long)]
+        from: PathBuf,
+
+        /// Migration name for the `{name}` placeholder (default: the file name)
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Print the pgroll migration instead of writing it
+        #[arg(long)]
+        stdout: bool,
+    },
 }
 
-pub async fn run(args: Args) -> Result<()> {
-    // Load configuration (optional for test command)
-    let mut config = if let Some(config_path) = args.config {
-        Config::from_file(&config_path)?
-    } else if matches!(args.command, Commands::Test) {
-        // For test command, use default config
-        Config::default()
-    } else {
-        Config::from_env()?
+/// Exit code of `cleanup` when none of repligrate's objects exist
+pub const EXIT_NOTHING_FOUND: u8 = 3;
+
+/// Exit code of `drift` when the database departs from the migrations
+pub const EXIT_DRIFT: u8 = 4;
+
+pub async fn run(args: Args) -> Result<ExitCode> {
+    let source = ConfigSource {
+        path: args.config,
+        database_url: args.database_url,
+        output_dir: args.output,
     };
 
-    // Override with CLI arguments
-    if let Some(db_url) = args.database_url {
-        config.database_url = db_url;
-    }
-    if let Some(output) = args.output {
-        config.output_dir = output;
-    }
+    // These commands work offline or bring their own URLs
+    let config = if matches!(args.command, Commands::Generate { .. } | Commands::Diff { .. }) {
+        source.load_offline()?
+    } else {
+        source.load()?
+    };
 
     info!("Configuration loaded: {:?}", config);
 
@@ -106,84 +210,235 @@ pub async fn run(args: Args) -> Result<()> {
             slot_name,
             publication_name,
             tables,
+            admin_address,
         } => {
             info!("Starting replication listener");
-            let mut listener =
-                ReplicationListener::new(config, slot_name, publication_name).await?;
-            listener.listen(tables).await?;
+            let config_admin = config.admin.clone();
+            // The listener drives a blocking PostgreSQL client
+            let mut listener = tokio::task::block_in_place(|| {
+                ReplicationListener::new(config, slot_name.clone(), publication_name)
+            })?;
+            listener.set_config_source(source);
+            let _signals = signals::spawn(listener.get_state().clone())?;
+            #[cfg(unix)]
+            let _control = ControlServer::start(
+                &control::socket_path(&slot_name),
+                listener.get_state().clone(),
+            )?;
+            let admin_address = admin_address
+                .or_else(|| config_admin.enabled.then_some(config_admin.listen_address));
+            let _admin = match admin_address {
+                Some(address) => Some(AdminServer::start(&address, listener.get_state().clone()).await?),
+                None => None,
+            };
+            // Moved in so the client is also dropped outside the runtime
+            tokio::task::block_in_place(move || listener.listen(tables))?;
         }
-        Commands::Status => {
+        Commands::Status {
+            slot_name,
+            publication_name,
+            format,
+        } => {
             info!("Checking replication status");
-            // TODO: Implement status check
-            println!("Status check not yet implemented");
+            let report = tokio::task::block_in_place(|| {
+                let mut db = DbConnection::new(&config.database_url)?;
+                StatusReport::collect(&mut db, &slot_name, &publication_name)
+            })?;
+            print!("{}", report.render(format)?);
         }
-        Commands::Pause { slot_name: _ } => {
+        Commands::Pause { slot_name } => {
             info!("Pause command received");
-            println!(
-                "Note: Pause/Unpause commands require the listener to be running in another process"
-            );
-            println!(
-                "Use a signal handler or IPC mechanism to communicate with the running listener"
-            );
-            println!("For now, you can use Ctrl+C to stop the listener");
+            let status = tokio::task::block_in_place(|| control::send(&slot_name, ControlRequest::Pause))?;
+            println!("✓ Listener {} (pid {})", status.state, status.pid);
         }
-        Commands::Continue { slot_name: _ } => {
+        Commands::Continue { slot_name } => {
             info!("Continue command received");
-            println!(
-                "Note: Pause/Continue commands require the listener to be running in another process"
-            );
-            println!(
-                "Use a signal handler or IPC mechanism to communicate with the running listener"
-            );
-            println!("For now, you can use Ctrl+C to stop the listener");
+            let status =
+                tokio::task::block_in_place(|| control::send(&slot_name, ControlRequest::Continue))?;
+            println!("✓ Listener {} (pid {})", status.state, status.pid);
         }
-        Commands::Cleanup { slot_name } => {
+        Commands::Cleanup {
+            slot_name,
+            publication_name,
+            force,
+            dry_run,
+        } => {
             info!("Cleaning up replication slot: {}", slot_name);
-            // TODO: Implement cleanup
-            println!("Cleanup not yet implemented");
+            let outcome = tokio::task::block_in_place(|| -> Result<CleanupOutcome> {
+                let mut db = DbConnection::new(&config.database_url)?;
+                let plan = CleanupPlan::discover(&mut db, &slot_name, &publication_name, force)?;
+                plan.run(dry_run, |sql| db.execute_sql(sql))
+            })?;
+
+            match &outcome {
+                CleanupOutcome::NothingFound => println!("Nothing to clean up"),
+                CleanupOutcome::DryRun(sql) => print!("{}", sql),
+                CleanupOutcome::Done(done) => {
+                    for description in done {
+                        println!("✓ {}", description);
+                    }
+                }
+            }
+            if outcome.exit_code() != 0 {
+                return Ok(ExitCode::from(outcome.exit_code()));
+            }
+        }
+        Commands::Snapshot { file } => {
+            info!("Taking a schema snapshot");
+            let snapshot = tokio::task::block_in_place(|| -> Result<Snapshot> {
+                let mut db = DbConnection::new(&config.database_url)?;
+                let filter = SchemaFilter::new(&config.schema_filter)?;
+                Snapshot::capture(db.client(), &filter)
+            })?;
+            let json = snapshot.to_json()?;
+            match file {
+                Some(file) => {
+                    std::fs::write(&file, json)
+                        .with_context(|| format!("Failed to write {}", file.display()))?;
+                    println!("✓ Snapshot written: {}", file.display());
+                }
+                None => print!("{}", json),
+            }
         }
-        Commands::Test => {
-            info!("Generating test migration");
-            generate_test_migration(&config)?;
+        Commands::Baseline {
+            slot_name,
+            name,
+            stdout,
+        } => {
+            info!("Taking a baseline of the current schema");
+            let (model, lsn) = tokio::task::block_in_place(|| -> Result<(SchemaModel, String)> {
+                let mut db = DbConnection::new(&config.database_url)?;
+                let filter = SchemaFilter::new(&config.schema_filter)?;
+                baseline::take(&mut db, &slot_name, &filter)
+            })?;
+
+            // Already in dependency order, with nothing to merge
+            let changes = diff::diff(&SchemaModel::default(), &model);
+            let written = write_changes(&config, changes, name, stdout)?;
+            if stdout {
+                eprintln!("Baseline LSN: {}", lsn);
+            } else {
+                let marker = BaselineMarker {
+                    slot_name,
+                    migrations: written
+                        .iter()
+                        .filter_map(|path| path.file_name())
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .collect(),
+                    lsn,
+                };
+                let path = marker.write(&config.output_dir)?;
+                println!("✓ Baseline at {} recorded in {}", marker.lsn, path.display());
+            }
+        }
+        Commands::Diff {
+            from,
+            to,
+            name,
+            stdout,
+        } => {
+            info!("Comparing {} with {}", from, to);
+            let changes = tokio::task::block_in_place(|| -> Result<Vec<SchemaChange>> {
+                let filter = SchemaFilter::new(&config.schema_filter)?;
+                let from = Snapshot::open(&from, &filter)?;
+                let to = Snapshot::open(&to, &filter)?;
+                Ok(diff::diff(&from.model, &to.model))
+            })?;
+            if changes.is_empty() {
+                println!("No differences");
+            } else {
+                write_changes(&config, MigrationOptimizer::optimize(changes), name, stdout)?;
+            }
+        }
+        Commands::Drift => {
+            info!(
+                "Checking {} against the database",
+                config.output_dir.display()
+            );
+            if config.output_backend != OutputBackend::Pgroll {
+                bail!(
+                    "drift replays pgroll migrations, the output backend is {:?}",
+                    config.output_backend
+                );
+            }
+            let drifts = tokio::task::block_in_place(|| -> Result<Vec<Drift>> {
+                let filter = SchemaFilter::new(&config.schema_filter)?;
+                let mut expected = loader::load(&config.output_dir)?;
+                expected.retain(&filter);
+                let mut db = DbConnection::new(&config.database_url)?;
+                let actual = Snapshot::capture(db.client(), &filter)?;
+                Ok(drift::compare(&expected, &actual.model))
+            })?;
+
+            if drifts.is_empty() {
+                println!("No drift");
+            } else {
+                for drift in &drifts {
+                    println!("{}", drift);
+                }
+                println!(
+                    "{} difference(s) between the migrations and the database",
+                    drifts.len()
+                );
+                return Ok(ExitCode::from(EXIT_DRIFT));
+            }
+        }
+        Commands::Generate { from, name, stdout } => {
+            info!("Generating migration from {}", from.display());
+            generate_from_script(&config, &from, name, stdout)?;
         }
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
 
-/// Generate a test migration for demonstration purposes
-fn generate_test_migration(config: &Config) -> Result<()> {
-    info!("Creating test schema changes");
-
-    // Create sample schema changes
-    let changes = vec![
-        SchemaChange::new(
-            ChangeType::CreateTable,
-            "public".to_string(),
-            "users".to_string(),
-            "CREATE TABLE users (id SERIAL PRIMARY KEY, name VARCHAR(255) NOT NULL)".to_string(),
-        ),
-        SchemaChange::new(
-            ChangeType::AddColumn,
-            "public".to_string(),
-            "users".to_string(),
-            "ALTER TABLE users ADD COLUMN email VARCHAR(255) UNIQUE".to_string(),
-        ),
-        SchemaChange::new(
-            ChangeType::CreateIndex,
-            "public".to_string(),
-            "idx_users_email".to_string(),
-            "CREATE INDEX idx_users_email ON users(email)".to_string(),
-        ),
-    ];
-
-    info!("Generating migration from {} schema changes", changes.len());
-    let migration = MigrationGenerator::generate("test_migration".to_string(), changes)?;
-
-    let filepath = MigrationWriter::write(&migration, &config.output_dir)?;
-    println!("✓ Test migration generated: {}", filepath.display());
-    println!("✓ Migration content:");
-    println!("{}", migration.to_json()?);
+/// Turn the DDL of a SQL script into a migration
+fn generate_from_script(config: &Config, from: &Path, name: Option<String>, stdout: bool) -> Result<()> {
+    let script = if from == Path::new("-") {
+        std::io::read_to_string(std::io::stdin())?
+    } else {
+        std::fs::read_to_string(from).with_context(|| format!("Failed to read {}", from.display()))?
+    };
 
+    let changes = SchemaChangeParser::parse_script(&script, "public");
+    if changes.is_empty() {
+        bail!("No DDL statements found in {}", from.display());
+    }
+    // Objects the existing migrations or the script create are known, for
+    // rollbacks of later drops
+    let mut model = match config.output_backend {
+        OutputBackend::Pgroll => loader::load(&config.output_dir)?,
+        _ => SchemaModel::default(),
+    };
+    let changes = changes.into_iter().map(|change| model.apply(change)).collect();
+
+    let name = name.unwrap_or_else(|| match from.file_stem().and_then(|stem| stem.to_str()) {
+        Some(stem) if from != Path::new("-") => stem.to_string(),
+        _ => "migration".to_string(),
+    });
+    let changes = MigrationOptimizer::optimize(changes);
+    if changes.is_empty() {
+        println!("The statements of {} cancel each other out", from.display());
+        return Ok(());
+    }
+    write_changes(config, changes, name, stdout)?;
     Ok(())
 }
+
+/// Write `changes` as a migration, or print it with `stdout`, returning the files written
+fn write_changes(config: &Config, changes: Vec<SchemaChange>, name: String, stdout: bool) -> Result<Vec<PathBuf>> {
+    if stdout {
+        // Named as it would be written to the output directory
+        let existing = MigrationWriter::list_migrations(&config.output_dir)?;
+        let name = MigrationNamer::new(config.naming.template.clone()).render(&name, &changes, &existing)?;
+        let migration = MigrationGenerator::generate(name, changes, config.pgroll_version)?;
+        println!("{}", migration.render(config.output_format)?);
+        return Ok(Vec::new());
+    }
+
+    let written = MigrationWriter::sink(config).write(&name, &changes)?;
+    for filepath in &written {
+        println!("✓ Migration written: {}", filepath.display());
+    }
+    Ok(written)
+}
@@ -1,3 +1,6 @@
+use crate::migration::OutputFormat;
+use crate::pgroll::PgrollVersion;
+use crate::sink::{ChangelogFormat, OutputBackend};
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
@@ -10,11 +13,43 @@ pub struct Config {
     /// Output directory for generated migrations
     pub output_dir: PathBuf,
 
+    /// File format of generated migrations (`json` or `yaml`)
+    #[serde(default)]
+    pub output_format: OutputFormat,
+
+    /// Migration tool to generate files for (`pgroll`, `sql`, `liquibase` or `sqitch`)
+    #[serde(default)]
+    pub output_backend: OutputBackend,
+
+    /// Liquibase changelog settings
+    #[serde(default)]
+    pub liquibase: LiquibaseConfig,
+
+    /// Sqitch project settings
+    #[serde(default)]
+    pub sqitch: SqitchConfig,
+
+    /// Migration file naming
+    #[serde(default)]
+    pub naming: NamingConfig,
+
     /// Replication settings
     pub replication: ReplicationConfig,
 
     /// Schema filtering
     pub schema_filter: SchemaFilterConfig,
+
+    /// Recognition of the DDL pgroll runs while applying migrations
+    #[serde(default)]
+    pub pgroll_filter: PgrollFilterConfig,
+
+    /// pgroll release generated migrations must be compatible with
+    #[serde(default)]
+    pub pgroll_version: PgrollVersion,
+
+    /// HTTP admin API of the listener
+    #[serde(default)]
+    pub admin: AdminConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -47,13 +82,122 @@ pub struct SchemaFilterConfig {
     pub exclude_tables: Vec<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PgrollFilterConfig {
+    /// Drop pgroll's own DDL before generating migrations
+    pub enabled: bool,
+
+    /// Schema where pgroll keeps its state
+    pub state_schema: String,
+
+    /// Regexes matching the names of pgroll's internal objects
+    pub object_patterns: Vec<String>,
+
+    /// `application_name` values of pgroll sessions
+    pub application_names: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    /// Serve the admin API while listening
+    pub enabled: bool,
+
+    /// Address to bind, such as `127.0.0.1:9187`
+    pub listen_address: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LiquibaseConfig {
+    /// Changelog format (`yaml` or `xml`)
+    pub format: ChangelogFormat,
+
+    /// Author recorded on every changeSet
+    pub author: String,
+
+    /// Master changelog file name, relative to the output directory
+    /// (defaults to `db.changelog-master.<format>`)
+    pub master_changelog: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SqitchConfig {
+    /// Project name written to `sqitch.plan`
+    pub project: String,
+
+    /// Planner recorded on every plan entry
+    pub planner_name: String,
+
+    /// Planner email recorded on every plan entry
+    pub planner_email: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NamingConfig {
+    /// File name template; supports `{seq}`, `{timestamp}` (UTC), `{slug}` and `{name}`
+    pub template: String,
+}
+
+/// Where the configuration comes from, with command-line overrides, so it
+/// can be loaded again
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSource {
+    /// Configuration file; the environment is used without one
+    pub path: Option<PathBuf>,
+    pub database_url: Option<String>,
+    pub output_dir: Option<PathBuf>,
+}
+
+impl ConfigSource {
+    /// Read the configuration file or the environment, then apply the overrides
+    pub fn load(&self) -> Result<Config> {
+        let config = match &self.path {
+            Some(path) => Config::from_file(path)?,
+            None => Config::from_env()?,
+        };
+        Ok(self.apply(config))
+    }
+
+    /// Like `load`, for commands that never connect to the database
+    pub fn load_offline(&self) -> Result<Config> {
+        let config = match &self.path {
+            Some(path) => Config::from_file(path)?,
+            None => Config::from_env_offline()?,
+        };
+        Ok(self.apply(config))
+    }
+
+    /// Apply the command-line overrides to `config`
+    pub fn apply(&self, mut config: Config) -> Config {
+        if let Some(database_url) = &self.database_url {
+            config.database_url = database_url.clone();
+        }
+        if let Some(output_dir) = &self.output_dir {
+            config.output_dir = output_dir.clone();
+        }
+        config
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             database_url: String::new(),
             output_dir: PathBuf::from("./migrations"),
+            output_format: OutputFormat::default(),
+            output_backend: OutputBackend::default(),
+            liquibase: LiquibaseConfig::default(),
+            sqitch: SqitchConfig::default(),
+            naming: NamingConfig::default(),
             replication: ReplicationConfig::default(),
             schema_filter: SchemaFilterConfig::default(),
+            pgroll_filter: PgrollFilterConfig::default(),
+            pgroll_version: PgrollVersion::default(),
+            admin: AdminConfig::default(),
         }
     }
 }
@@ -80,17 +224,102 @@ impl Default for SchemaFilterConfig {
     }
 }
 
+impl Default for PgrollFilterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            state_schema: "pgroll".to_string(),
+            object_patterns: vec!["^_pgroll_".to_string()],
+            application_names: vec!["pgroll".to_string()],
+        }
+    }
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_address: "127.0.0.1:9187".to_string(),
+        }
+    }
+}
+
+impl Default for LiquibaseConfig {
+    fn default() -> Self {
+        Self {
+            format: ChangelogFormat::default(),
+            author: "repligrate".to_string(),
+            master_changelog: None,
+        }
+    }
+}
+
+impl Default for SqitchConfig {
+    fn default() -> Self {
+        Self {
+            project: "repligrate".to_string(),
+            planner_name: "repligrate".to_string(),
+            planner_email: "repligrate@localhost".to_string(),
+        }
+    }
+}
+
+impl Default for NamingConfig {
+    fn default() -> Self {
+        Self {
+            template: "{seq}_{timestamp}_{slug}".to_string(),
+        }
+    }
+}
+
+impl LiquibaseConfig {
+    /// Master changelog file name, falling back to the conventional one for the format
+    pub fn master_changelog(&self) -> String {
+        self.master_changelog.clone().unwrap_or_else(|| match self.format {
+            ChangelogFormat::Yaml => "db.changelog-master.yaml".to_string(),
+            ChangelogFormat::Xml => "db.changelog-master.xml".to_string(),
+        })
+    }
+}
+
 impl Config {
     /// Load configuration from environment variables
     pub fn from_env() -> Result<Self> {
         let database_url = std::env::var("DATABASE_URL")
             .map_err(|_| anyhow!("DATABASE_URL environment variable not set"))?;
+        Self::from_env_with(database_url)
+    }
 
+    /// Load configuration from environment variables for commands that never
+    /// connect, where `DATABASE_URL` is optional
+    pub fn from_env_offline() -> Result<Self> {
+        Self::from_env_with(std::env::var("DATABASE_URL").unwrap_or_default())
+    }
+
+    fn from_env_with(database_url: String) -> Result<Self> {
         let output_dir = std::env::var("OUTPUT_DIR").unwrap_or_else(|_| "./migrations".to_string());
 
+        let output_format = match std::env::var("OUTPUT_FORMAT") {
+            Ok(format) => format.parse()?,
+            Err(_) => OutputFormat::default(),
+        };
+
+        let output_backend = match std::env::var("OUTPUT_BACKEND") {
+            Ok(backend) => backend.parse()?,
+            Err(_) => OutputBackend::default(),
+        };
+
+        let pgroll_version = match std::env::var("PGROLL_VERSION") {
+            Ok(version) => version.parse()?,
+            Err(_) => PgrollVersion::default(),
+        };
+
         Ok(Self {
             database_url,
             output_dir: PathBuf::from(output_dir),
+            output_format,
+            output_backend,
+            pgroll_version,
             ..Default::default()
         })
     }
@@ -0,0 +1,283 @@
+use crate::state::{ListenerState, ListenerStatus};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use {
+    anyhow::Context,
+    std::io::{BufRead, BufReader, Write},
+    std::os::unix::fs::{MetadataExt, PermissionsExt},
+    std::time::Duration,
+    tokio::io::{AsyncBufReadExt, AsyncWriteExt},
+    tracing::{debug, info, warn},
+};
+
+/// How long clients wait for the listener to answer
+#[cfg(unix)]
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Control socket of the listener consuming `slot_name`
+pub fn socket_path(slot_name: &str) -> PathBuf {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    runtime_dir.join(format!("repligrate-{}.sock", slot_name))
+}
+
+/// Request sent to the control socket, one JSON object per line, such as
+/// `{"command": "pause"}`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum ControlRequest {
+    Pause,
+    Continue,
+    Flush,
+    Status,
+    Shutdown,
+}
+
+/// Answer to a control request, one JSON object per line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ControlResponse {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<ListenerStatus>,
+}
+
+impl ControlResponse {
+    fn status(state: &ListenerState) -> Self {
+        Self {
+            ok: true,
+            error: None,
+            status: Some(state.snapshot()),
+        }
+    }
+
+    fn error(message: String) -> Self {
+        Self {
+            ok: false,
+            error: Some(message),
+            status: None,
+        }
+    }
+}
+
+/// Apply a request to the listener state
+///
+/// Flush and shutdown are picked up by the listener on its next poll.
+pub fn handle(state: &ListenerState, request: ControlRequest) -> ControlResponse {
+    match request {
+        ControlRequest::Pause => state.pause(),
+        ControlRequest::Continue => state.continue_listening(),
+        ControlRequest::Flush => state.request_flush(),
+        ControlRequest::Status => {}
+        ControlRequest::Shutdown => state.request_shutdown(),
+    }
+    ControlResponse::status(state)
+}
+
+/// Control socket served for the lifetime of a listener; the socket file is
+/// removed when this is dropped
+#[cfg(unix)]
+pub struct ControlServer {
+    path: PathBuf,
+    task: tokio::task::JoinHandle<()>,
+}
+
+#[cfg(unix)]
+impl ControlServer {
+    /// Bind the socket and serve it on the current runtime
+    ///
+    /// Only the current user can connect. The socket is bound under a
+    /// temporary name and renamed into place once restricted, replacing a
+    /// stale socket only when the current user owns it.
+    pub fn start(path: &Path, state: ListenerState) -> Result<Self> {
+        let existing = std::fs::symlink_metadata(path).ok();
+        if existing.is_some() && std::os::unix::net::UnixStream::connect(path).is_ok() {
+            bail!("Another listener is already serving {}", path.display());
+        }
+
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp_path = path.with_file_name(format!(".{}.{}", file_name, std::process::id()));
+        let _ = std::fs::remove_file(&temp_path);
+        let listener = tokio::net::UnixListener::bind(&temp_path)
+            .with_context(|| format!("Failed to bind control socket {}", temp_path.display()))?;
+        let published = Self::publish(&temp_path, path, existing);
+        if published.is_err() {
+            let _ = std::fs::remove_file(&temp_path);
+        }
+        published?;
+        info!("Control socket listening on {}", path.display());
+
+        let task = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(Self::serve_connection(stream, state.clone()));
+                    }
+                    Err(err) => warn!("Control socket accept failed: {}", err),
+                }
+            }
+        });
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            task,
+        })
+    }
+
+    /// Restrict the socket bound at `temp_path` to its owner and move it to
+    /// `path`, over a stale socket `existing` left by the same user
+    fn publish(temp_path: &Path, path: &Path, existing: Option<std::fs::Metadata>) -> Result<()> {
+        std::fs::set_permissions(temp_path, std::fs::Permissions::from_mode(0o600))?;
+        if let Some(existing) = existing {
+            let uid = std::fs::metadata(temp_path)?.uid();
+            if existing.uid() != uid {
+                bail!(
+                    "{} belongs to another user; remove it or set XDG_RUNTIME_DIR",
+                    path.display()
+                );
+            }
+            // Left behind by a listener that did not exit cleanly
+            debug!("Replacing stale control socket {}", path.display());
+        }
+        std::fs::rename(temp_path, path)
+            .with_context(|| format!("Failed to bind control socket {}", path.display()))?;
+        Ok(())
+    }
+
+    async fn serve_connection(stream: tokio::net::UnixStream, state: ListenerState) {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = tokio::io::BufReader::new(reader).lines();
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            if line.trim().is_empty() {
+                continue;
+            }
+            debug!("Control request: {}", line);
+            let response = match serde_json::from_str::<ControlRequest>(&line) {
+                Ok(request) => handle(&state, request),
+                Err(err) => ControlResponse::error(format!("Invalid request: {}", err)),
+            };
+
+            let mut encoded = match serde_json::to_string(&response) {
+                Ok(encoded) => encoded,
+                Err(err) => {
+                    warn!("Failed to encode control response: {}", err);
+                    return;
+                }
+            };
+            encoded.push('\n');
+            if writer.write_all(encoded.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        self.task.abort();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Send one request to the listener consuming `slot_name`
+pub fn send(slot_name: &str, request: ControlRequest) -> Result<ListenerStatus> {
+    let response = self::request(&socket_path(slot_name), request)?;
+    response
+        .status
+        .ok_or_else(|| anyhow::anyhow!("Listener did not report its status"))
+}
+
+/// Send one request to a running listener
+#[cfg(unix)]
+pub fn request(path: &Path, request: ControlRequest) -> Result<ControlResponse> {
+    let mut stream = std::os::unix::net::UnixStream::connect(path)
+        .with_context(|| format!("No listener is running (cannot connect to {})", path.display()))?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let mut encoded = serde_json::to_string(&request)?;
+    encoded.push('\n');
+    stream.write_all(encoded.as_bytes())?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let response: ControlResponse = serde_json::from_str(&line)
+        .with_context(|| format!("Invalid response from {}", path.display()))?;
+    if let Some(error) = &response.error {
+        bail!("Listener rejected {:?}: {}", request, error);
+    }
+    Ok(response)
+}
+
+#[cfg(not(unix))]
+pub fn request(_path: &Path, _request: ControlRequest) -> Result<ControlResponse> {
+    bail!("Controlling a running listener needs Unix domain sockets")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_encoding() {
+        assert_eq!(
+            serde_json::to_string(&ControlRequest::Continue).unwrap(),
+            r#"{"command":"continue"}"#
+        );
+        let request: ControlRequest = serde_json::from_str(r#"{"command": "flush"}"#).unwrap();
+        assert_eq!(request, ControlRequest::Flush);
+    }
+
+    #[test]
+    fn test_handle() {
+        let state = ListenerState::new();
+        state.set_pending(2);
+
+        let response = handle(&state, ControlRequest::Pause);
+        let status = response.status.unwrap();
+        assert_eq!(status.state, "PAUSED");
+        assert_eq!(status.pending_changes, 2);
+
+        handle(&state, ControlRequest::Flush);
+        assert!(state.take_flush_request());
+        handle(&state, ControlRequest::Shutdown);
+        assert!(state.shutdown_requested());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_socket_round_trip() {
+        let path = std::env::temp_dir().join(format!("repligrate-control-{}.sock", std::process::id()));
+        let state = ListenerState::new();
+        let server = ControlServer::start(&path, state.clone()).unwrap();
+
+        let client_path = path.clone();
+        let response = tokio::task::spawn_blocking(move || {
+            request(&client_path, ControlRequest::Pause)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert!(response.ok);
+        assert!(state.is_paused());
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(ControlServer::start(&path, state.clone()).is_err());
+
+        // A socket left behind by a listener that did not exit cleanly is replaced
+        drop(server);
+        let stale = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        drop(stale);
+        let server = ControlServer::start(&path, state).unwrap();
+
+        drop(server);
+        assert!(!path.exists());
+    }
+}
@@ -1,19 +1,33 @@
+use crate::capture;
+use crate::filter::SchemaFilter;
+use crate::ident::{quote_ident, quote_literal, QualifiedName};
+use crate::slot::{self, ExportedSlot, ReplicationConnection};
 use anyhow::Result;
-use postgres::Client;
-use tracing::{debug, info};
+use postgres::{Client, IsolationLevel, Transaction};
+use serde::Serialize;
+use tracing::{debug, info, warn};
 
 /// Database connection manager
 pub struct DbConnection {
     client: Client,
+    config: postgres::Config,
 }
 
 #[allow(dead_code)]
 impl DbConnection {
     /// Create a new database connection
     pub fn new(database_url: &str) -> Result<Self> {
-        let client = Client::connect(database_url, postgres::NoTls)?;
+        // Sessions named after repligrate are skipped by the capture trigger
+        let mut config = database_url.parse::<postgres::Config>()?;
+        config.application_name(capture::APPLICATION_NAME);
+        let client = config.connect(postgres::NoTls)?;
         info!("Connected to PostgreSQL database");
-        Ok(Self { client })
+        Ok(Self { client, config })
+    }
+
+    /// The underlying client, for the catalog queries
+    pub fn client(&mut self) -> &mut Client {
+        &mut self.client
     }
 
     /// Create a replication slot
@@ -33,22 +47,61 @@ impl DbConnection {
 
         // Create the slot
         self.client.execute(
-            &format!(
-                "SELECT * FROM pg_create_logical_replication_slot('{}', 'test_decoding')",
-                slot_name
-            ),
-            &[],
+            "SELECT * FROM pg_create_logical_replication_slot($1, 'test_decoding')",
+            &[&slot_name],
         )?;
 
         info!("Created replication slot: {}", slot_name);
         Ok(())
     }
 
-    /// Create a publication for specific tables
+    /// Create a replication slot over a replication connection, exporting a
+    /// snapshot of the database as of the slot's start
+    ///
+    /// Returns None when the slot already exists.
+    pub fn create_exported_slot(&mut self, slot_name: &str) -> Result<Option<ExportedSlot>> {
+        let rows = self.client.query(
+            "SELECT 1 FROM pg_replication_slots WHERE slot_name = $1",
+            &[&slot_name],
+        )?;
+        if !rows.is_empty() {
+            info!("Replication slot {} already exists", slot_name);
+            return Ok(None);
+        }
+
+        // With several hosts, the slot must be created where this connection is
+        let server: String = self.client.query_one(slot::SERVER_QUERY, &[])?.get(0);
+        let slot = ReplicationConnection::connect(&self.config, &server)?.create_slot(slot_name)?;
+        Ok(Some(slot))
+    }
+
+    /// Start a read only repeatable read transaction, seeing the database as
+    /// of `snapshot` when one is given
+    pub fn snapshot_transaction(&mut self, snapshot: Option<&str>) -> Result<Transaction<'_>> {
+        let mut transaction = self
+            .client
+            .build_transaction()
+            .isolation_level(IsolationLevel::RepeatableRead)
+            .read_only(true)
+            .start()?;
+        if let Some(snapshot) = snapshot {
+            transaction.batch_execute(&format!(
+                "SET TRANSACTION SNAPSHOT {}",
+                quote_literal(snapshot)
+            ))?;
+        }
+        Ok(transaction)
+    }
+
+    /// Create a publication for specific tables, or for the ones the schema filter allows
+    ///
+    /// The slot uses `test_decoding`, which ignores publications, so this only
+    /// records which tables are monitored; filtering happens in the listener.
     pub fn create_publication(
         &mut self,
         pub_name: &str,
-        tables: Option<Vec<String>>,
+        tables: Option<Vec<QualifiedName>>,
+        filter: &SchemaFilter,
     ) -> Result<()> {
         debug!("Creating publication: {}", pub_name);
 
@@ -64,30 +117,95 @@ impl DbConnection {
         }
 
         // Create publication
-        let sql = if let Some(table_list) = tables {
-            if table_list.is_empty() {
-                format!("CREATE PUBLICATION {} FOR ALL TABLES", pub_name)
-            } else {
-                let tables_str = table_list.join(", ");
-                format!("CREATE PUBLICATION {} FOR TABLE {}", pub_name, tables_str)
+        let target = match tables.filter(|table_list| !table_list.is_empty()) {
+            Some(table_list) => {
+                let table_list: Vec<String> = table_list.iter().map(ToString::to_string).collect();
+                format!("FOR TABLE {}", table_list.join(", "))
             }
-        } else {
-            format!("CREATE PUBLICATION {} FOR ALL TABLES", pub_name)
+            None => self.publication_target(filter)?,
         };
+        if target.is_empty() {
+            warn!("Schema filter excludes every table; publication {} is empty", pub_name);
+        }
 
-        self.client.execute(&sql, &[])?;
+        let sql = format!("CREATE PUBLICATION {} {}", quote_ident(pub_name), target);
+        self.client.execute(sql.trim_end(), &[])?;
         info!("Created publication: {}", pub_name);
         Ok(())
     }
 
+    /// Publication clause covering the tables the schema filter allows
+    fn publication_target(&mut self, filter: &SchemaFilter) -> Result<String> {
+        let schemas = self.user_schemas()?;
+        let included: Vec<&String> = schemas
+            .iter()
+            .filter(|schema| filter.schema_rule(schema).is_none())
+            .collect();
+
+        if !filter.has_table_rules() {
+            if included.len() == schemas.len() {
+                return Ok("FOR ALL TABLES".to_string());
+            }
+            if self.server_version_num()? >= 150000 {
+                if included.is_empty() {
+                    return Ok(String::new());
+                }
+                let schema_list: Vec<String> = included.iter().map(|s| quote_ident(s)).collect();
+                return Ok(format!("FOR TABLES IN SCHEMA {}", schema_list.join(", ")));
+            }
+        }
+
+        // Table rules, and servers before PostgreSQL 15, need every table listed
+        let tables: Vec<String> = self
+            .user_tables()?
+            .into_iter()
+            .filter(|(schema, table)| filter.table_rule(schema, table).is_none())
+            .map(|(schema, table)| QualifiedName::new(Some(schema), table).to_string())
+            .collect();
+        if tables.is_empty() {
+            return Ok(String::new());
+        }
+        Ok(format!("FOR TABLE {}", tables.join(", ")))
+    }
+
+    /// Server version as a number, such as 150004 for 15.4
+    pub fn server_version_num(&mut self) -> Result<i32> {
+        let row = self
+            .client
+            .query_one("SELECT current_setting('server_version_num')::int", &[])?;
+        Ok(row.get(0))
+    }
+
+    /// Schemas other than the system ones
+    fn user_schemas(&mut self) -> Result<Vec<String>> {
+        let rows = self.client.query(
+            "SELECT nspname::text FROM pg_namespace \
+             WHERE nspname !~ '^pg_' AND nspname <> 'information_schema' \
+             ORDER BY nspname",
+            &[],
+        )?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    /// Ordinary and partitioned tables outside the system schemas
+    fn user_tables(&mut self) -> Result<Vec<(String, String)>> {
+        let rows = self.client.query(
+            "SELECT n.nspname::text, c.relname::text FROM pg_class c \
+             JOIN pg_namespace n ON n.oid = c.relnamespace \
+             WHERE c.relkind IN ('r', 'p') \
+               AND n.nspname !~ '^pg_' AND n.nspname <> 'information_schema' \
+             ORDER BY 1, 2",
+            &[],
+        )?;
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
     /// Drop a replication slot
     pub fn drop_replication_slot(&mut self, slot_name: &str) -> Result<()> {
         debug!("Dropping replication slot: {}", slot_name);
 
-        self.client.execute(
-            &format!("SELECT pg_drop_replication_slot('{}')", slot_name),
-            &[],
-        )?;
+        self.client
+            .execute("SELECT pg_drop_replication_slot($1)", &[&slot_name])?;
 
         info!("Dropped replication slot: {}", slot_name);
         Ok(())
@@ -98,12 +216,81 @@ impl DbConnection {
         debug!("Dropping publication: {}", pub_name);
 
         self.client
-            .execute(&format!("DROP PUBLICATION IF EXISTS {}", pub_name), &[])?;
+            .execute(&format!("DROP PUBLICATION IF EXISTS {}", quote_ident(pub_name)), &[])?;
 
         info!("Dropped publication: {}", pub_name);
         Ok(())
     }
 
+    /// Install the event trigger forwarding DDL to the replication stream
+    pub fn install_capture(&mut self) -> Result<()> {
+        debug!("Installing DDL capture");
+
+        for sql in capture::install_sql() {
+            self.client.batch_execute(&sql)?;
+        }
+
+        let rows = self.client.query(
+            "SELECT 1 FROM pg_event_trigger WHERE evtname = $1",
+            &[&capture::EVENT_TRIGGER],
+        )?;
+        if rows.is_empty() {
+            self.client.batch_execute(&capture::event_trigger_sql())?;
+            info!("Created event trigger: {}", capture::EVENT_TRIGGER);
+        }
+
+        Ok(())
+    }
+
+    /// Read the capture messages and transaction ends held by a slot, without consuming them
+    ///
+    /// Decoding stops at the first commit after `limit` rows, so a slot that
+    /// is not advanced is not decoded further on every call.
+    pub fn peek_changes(&mut self, slot_name: &str, limit: i32) -> Result<Vec<DecodedRow>> {
+        let rows = self.client.query(
+            "SELECT lsn::text, xid::text, data \
+             FROM pg_logical_slot_peek_changes($1, NULL, $2) \
+             WHERE data LIKE 'message:%' OR data LIKE 'COMMIT%'",
+            &[&slot_name, &limit],
+        )?;
+
+        Ok(rows
+            .iter()
+            .map(|row| DecodedRow {
+                lsn: row.get(0),
+                xid: row.get(1),
+                data: row.get(2),
+            })
+            .collect())
+    }
+
+    /// Confirm everything up to `lsn` as processed, releasing the WAL it retains
+    pub fn advance_replication_slot(&mut self, slot_name: &str, lsn: &str) -> Result<()> {
+        debug!("Advancing replication slot {} to {}", slot_name, lsn);
+        self.client.execute(
+            "SELECT pg_replication_slot_advance($1, $2::text::pg_lsn)",
+            &[&slot_name, &lsn],
+        )?;
+        Ok(())
+    }
+
+    /// Names of the migrations recorded by pgroll, if it is installed
+    pub fn pgroll_migration_names(&mut self, state_schema: &str) -> Result<Vec<String>> {
+        let table = QualifiedName::new(Some(state_schema.to_string()), "migrations".to_string())
+            .to_string();
+        let row = self
+            .client
+            .query_one("SELECT to_regclass($1) IS NOT NULL", &[&table])?;
+        if !row.get::<_, bool>(0) {
+            return Ok(Vec::new());
+        }
+
+        let rows = self
+            .client
+            .query(&format!("SELECT name FROM {}", table), &[])?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
     /// Get the current LSN (Log Sequence Number)
     pub fn get_current_lsn(&mut self) -> Result<String> {
         let row = self
@@ -113,10 +300,51 @@ impl DbConnection {
         Ok(lsn)
     }
 
+    /// Run SQL built by the caller, such as a cleanup statement
+    pub fn execute_sql(&mut self, sql: &str) -> Result<()> {
+        self.client.batch_execute(sql)?;
+        Ok(())
+    }
+
+    /// Whether a schema exists
+    pub fn schema_exists(&mut self, schema: &str) -> Result<bool> {
+        let rows = self
+            .client
+            .query("SELECT 1 FROM pg_namespace WHERE nspname = $1", &[&schema])?;
+        Ok(!rows.is_empty())
+    }
+
+    /// Event triggers whose function lives in `schema`, with that function
+    /// as `regprocedure` text
+    pub fn event_triggers_in_schema(&mut self, schema: &str) -> Result<Vec<(String, String)>> {
+        let rows = self.client.query(
+            "SELECT e.evtname::text, p.oid::regprocedure::text FROM pg_event_trigger e \
+             JOIN pg_proc p ON p.oid = e.evtfoid \
+             JOIN pg_namespace n ON n.oid = p.pronamespace \
+             WHERE n.nspname = $1 ORDER BY 1",
+            &[&schema],
+        )?;
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+    /// Signatures of the functions in `schema`, such as `repligrate.capture_ddl()`
+    pub fn functions_in_schema(&mut self, schema: &str) -> Result<Vec<String>> {
+        let rows = self.client.query(
+            "SELECT p.oid::regprocedure::text FROM pg_proc p \
+             JOIN pg_namespace n ON n.oid = p.pronamespace \
+             WHERE n.nspname = $1 ORDER BY 1",
+            &[&schema],
+        )?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
     /// Get replication slot info
     pub fn get_slot_info(&mut self, slot_name: &str) -> Result<Option<SlotInfo>> {
         let rows = self.client.query(
-            "SELECT slot_name, slot_type, datoid, confirmed_flush_lsn FROM pg_replication_slots WHERE slot_name = $1",
+            "SELECT slot_name::text, slot_type, datoid, coalesce(confirmed_flush_lsn::text, '0/0'), \
+                    plugin::text, active, active_pid, restart_lsn::text, \
+                    pg_wal_lsn_diff(pg_current_wal_lsn(), restart_lsn)::bigint \
+             FROM pg_replication_slots WHERE slot_name = $1",
             &[&slot_name]
         )?;
 
@@ -130,15 +358,110 @@ impl DbConnection {
             slot_type: row.get(1),
             datoid: row.get(2),
             confirmed_flush_lsn: row.get(3),
+            plugin: row.get(4),
+            active: row.get(5),
+            active_pid: row.get(6),
+            restart_lsn: row.get(7),
+            retained_wal_bytes: row.get(8),
+        }))
+    }
+
+    /// Get a publication and the tables and schemas it covers
+    pub fn get_publication_info(&mut self, pub_name: &str) -> Result<Option<PublicationInfo>> {
+        let rows = self.client.query(
+            "SELECT puballtables FROM pg_publication WHERE pubname = $1",
+            &[&pub_name],
+        )?;
+        let Some(row) = rows.first() else {
+            return Ok(None);
+        };
+        let all_tables: bool = row.get(0);
+
+        let tables = self
+            .client
+            .query(
+                "SELECT format('%I.%I', schemaname, tablename) FROM pg_publication_tables \
+                 WHERE pubname = $1 ORDER BY 1",
+                &[&pub_name],
+            )?
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+
+        // Schema membership only exists from PostgreSQL 15
+        let schemas = if self.server_version_num()? >= 150000 {
+            self.client
+                .query(
+                    "SELECT n.nspname::text FROM pg_publication_namespace pn \
+                     JOIN pg_publication p ON p.oid = pn.pnpubid \
+                     JOIN pg_namespace n ON n.oid = pn.pnnspid \
+                     WHERE p.pubname = $1 ORDER BY 1",
+                    &[&pub_name],
+                )?
+                .iter()
+                .map(|row| row.get(0))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Ok(Some(PublicationInfo {
+            name: pub_name.to_string(),
+            all_tables,
+            schemas,
+            tables,
+        }))
+    }
+
+    /// Get an event trigger
+    pub fn get_event_trigger(&mut self, name: &str) -> Result<Option<EventTriggerInfo>> {
+        let rows = self.client.query(
+            "SELECT evtname::text, evtevent::text, evtenabled <> 'D' FROM pg_event_trigger WHERE evtname = $1",
+            &[&name],
+        )?;
+
+        Ok(rows.first().map(|row| EventTriggerInfo {
+            name: row.get(0),
+            event: row.get(1),
+            enabled: row.get(2),
         }))
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 #[allow(dead_code)]
 pub struct SlotInfo {
     pub slot_name: String,
     pub slot_type: String,
-    pub datoid: i32,
+    pub datoid: Option<u32>,
     pub confirmed_flush_lsn: String,
+    pub plugin: Option<String>,
+    pub active: bool,
+    pub active_pid: Option<i32>,
+    pub restart_lsn: Option<String>,
+    /// WAL the slot keeps the server from recycling
+    pub retained_wal_bytes: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PublicationInfo {
+    pub name: String,
+    pub all_tables: bool,
+    pub schemas: Vec<String>,
+    pub tables: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EventTriggerInfo {
+    pub name: String,
+    pub event: String,
+    pub enabled: bool,
+}
+
+/// Row returned by logical decoding
+#[derive(Debug, Clone)]
+pub struct DecodedRow {
+    pub lsn: String,
+    pub xid: String,
+    pub data: String,
 }
@@ -0,0 +1,990 @@
+use crate::schema::{ColumnInfo, ConstraintInfo};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref ADD_CONSTRAINT_RE: Regex =
+        Regex::new(r#"(?is)^ADD\s+CONSTRAINT\s+("(?:[^"]|"")+"|\S+)\s+(.+)$"#).unwrap();
+    static ref REFERENCES_RE: Regex =
+        Regex::new(r#"(?is)REFERENCES\s+("(?:[^"]|"")+"(?:\."(?:[^"]|"")+"|\.\w+)?|[\w.]+)\s*(\([^)]*\))?"#)
+            .unwrap();
+    static ref ON_DELETE_RE: Regex =
+        Regex::new(r"(?i)ON\s+DELETE\s+(NO\s+ACTION|RESTRICT|CASCADE|SET\s+NULL|SET\s+DEFAULT)")
+            .unwrap();
+    static ref CREATE_ENUM_RE: Regex =
+        Regex::new(r#"(?is)^\s*CREATE\s+TYPE\s+((?:"(?:[^"]|"")+"|[^\s"(])+)\s+AS\s+ENUM\s*\("#).unwrap();
+    static ref ADD_ENUM_VALUE_RE: Regex = Regex::new(
+        r#"(?is)^\s*ALTER\s+TYPE\s+((?:"(?:[^"]|"")+"|[^\s"])+)\s+ADD\s+VALUE\s+(?:IF\s+NOT\s+EXISTS\s+)?('(?:[^']|'')*')(?:\s+(BEFORE|AFTER)\s+('(?:[^']|'')*'))?"#
+    )
+    .unwrap();
+    static ref VIEW_RE: Regex = Regex::new(
+        r#"(?is)^\s*(?:CREATE|ALTER|DROP)\s+(?:OR\s+REPLACE\s+)?(?:(?:TEMP|TEMPORARY|RECURSIVE|MATERIALIZED)\s+)*VIEW\s+(?:IF\s+(?:NOT\s+)?EXISTS\s+)?((?:"(?:[^"]|"")+"|[^\s"(])+)"#
+    )
+    .unwrap();
+    static ref DROP_TYPE_RE: Regex =
+        Regex::new(r"(?is)^\s*DROP\s+TYPE\s+(?:IF\s+EXISTS\s+)?(.+?)(?:\s+(?:CASCADE|RESTRICT))?\s*;?\s*$").unwrap();
+}
+
+/// Keywords that end the type of a column definition and start its constraints
+const COLUMN_CONSTRAINT_KEYWORDS: &[&str] = &[
+    "NOT",
+    "NULL",
+    "DEFAULT",
+    "PRIMARY",
+    "UNIQUE",
+    "CHECK",
+    "REFERENCES",
+    "CONSTRAINT",
+    "GENERATED",
+    "COLLATE",
+];
+
+/// Target of a FOREIGN KEY constraint
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKeyReference {
+    pub table: String,
+    pub columns: Vec<String>,
+    pub on_delete: Option<String>,
+}
+
+/// Index created by a CREATE INDEX statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexInfo {
+    pub name: String,
+    pub table: String,
+    pub columns: Vec<String>,
+    pub unique: bool,
+}
+
+/// Label added to an enum by `ALTER TYPE ... ADD VALUE`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumValue {
+    pub type_name: String,
+    pub label: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// Split `s` on `separator`, ignoring separators nested in parentheses or quotes
+pub fn split_top_level(s: &str, separator: char) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0i32;
+    let mut quote: Option<char> = None;
+
+    for c in s.chars() {
+        match quote {
+            Some(q) => {
+                if c == q {
+                    quote = None;
+                }
+            }
+            None => match c {
+                '\'' | '"' => quote = Some(c),
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ if c == separator && depth == 0 => {
+                    parts.push(current.trim().to_string());
+                    current.clear();
+                    continue;
+                }
+                _ => {}
+            },
+        }
+        current.push(c);
+    }
+
+    if !current.trim().is_empty() {
+        parts.push(current.trim().to_string());
+    }
+
+    parts
+}
+
+/// Split a SQL script into statements on top-level semicolons, dropping comments
+///
+/// Quoted strings, quoted identifiers and dollar-quoted bodies are kept intact.
+pub fn split_statements(script: &str) -> Vec<String> {
+    let chars: Vec<char> = script.chars().collect();
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '-' if chars.get(i + 1) == Some(&'-') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                i += 2;
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                    i += 1;
+                }
+                i += 2;
+                continue;
+            }
+            '\'' | '"' => {
+                current.push(c);
+                i += 1;
+                while i < chars.len() {
+                    current.push(chars[i]);
+                    if chars[i] == c {
+                        break;
+                    }
+                    i += 1;
+                }
+            }
+            '$' => {
+                // A dollar quote tag is `$$` or `$identifier$`
+                let tag_end = chars[i + 1..]
+                    .iter()
+                    .position(|ch| !(ch.is_alphanumeric() || *ch == '_'))
+                    .map(|pos| i + 1 + pos)
+                    .filter(|end| chars[*end] == '$');
+                match tag_end {
+                    Some(end) => {
+                        let tag: String = chars[i..=end].iter().collect();
+                        current.push_str(&tag);
+                        i = end + 1;
+                        let rest: String = chars[i..].iter().collect();
+                        let body_len = rest.find(&tag).map(|pos| pos + tag.len());
+                        let taken = body_len.unwrap_or(rest.len());
+                        current.push_str(&rest[..taken]);
+                        i += rest[..taken].chars().count();
+                        continue;
+                    }
+                    None => current.push(c),
+                }
+            }
+            ';' => {
+                if !current.trim().is_empty() {
+                    statements.push(current.trim().to_string());
+                }
+                current.clear();
+            }
+            _ => current.push(c),
+        }
+        i += 1;
+    }
+
+    if !current.trim().is_empty() {
+        statements.push(current.trim().to_string());
+    }
+
+    statements
+}
+
+/// Whether a statement defines or changes database objects
+pub fn is_ddl_statement(statement: &str) -> bool {
+    const DDL_VERBS: [&str; 9] = [
+        "CREATE", "ALTER", "DROP", "COMMENT", "GRANT", "REVOKE", "SECURITY", "IMPORT", "REFRESH",
+    ];
+    statement
+        .split_whitespace()
+        .next()
+        .is_some_and(|verb| DDL_VERBS.iter().any(|ddl| verb.eq_ignore_ascii_case(ddl)))
+}
+
+/// Strip surrounding double quotes from an identifier, unescaping doubled quotes
+pub fn unquote_identifier(identifier: &str) -> String {
+    let trimmed = identifier.trim();
+    if trimmed.len() >= 2 && trimmed.starts_with('"') && trimmed.ends_with('"') {
+        trimmed[1..trimmed.len() - 1].replace("\"\"", "\"")
+    } else {
+        trimmed.trim_matches('`').to_string()
+    }
+}
+
+/// Clauses of an `ALTER TABLE <name> ...` statement, split on top-level commas
+pub fn alter_table_clauses(sql: &str) -> Vec<String> {
+    let statement = sql.trim().trim_end_matches(';');
+    let upper = statement.to_uppercase();
+    let Some(idx) = upper.find("ALTER TABLE") else {
+        return Vec::new();
+    };
+
+    let mut rest = statement[idx + "ALTER TABLE".len()..].trim_start();
+    for keyword in ["IF EXISTS", "ONLY"] {
+        if rest.to_uppercase().starts_with(keyword) {
+            rest = rest[keyword.len()..].trim_start();
+        }
+    }
+
+    // Skip the (possibly quoted) table name
+    let body = match rest.find(|c: char| c.is_whitespace()) {
+        Some(space_idx) if !rest.starts_with('"') => &rest[space_idx..],
+        _ => skip_quoted_name(rest),
+    };
+
+    split_top_level(body.trim(), ',')
+}
+
+fn skip_quoted_name(s: &str) -> &str {
+    let mut in_quotes = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            c if c.is_whitespace() && !in_quotes => return &s[i..],
+            _ => {}
+        }
+    }
+    ""
+}
+
+/// Names of the columns added by `ADD [COLUMN] [IF NOT EXISTS] name ...` clauses
+pub fn added_column_names(sql: &str) -> Vec<String> {
+    alter_table_clauses(sql)
+        .iter()
+        .filter_map(|clause| {
+            let mut words = clause.split_whitespace();
+            if !words.next()?.eq_ignore_ascii_case("ADD") {
+                return None;
+            }
+            let mut word = words.next()?;
+            if word.eq_ignore_ascii_case("CONSTRAINT") {
+                return None;
+            }
+            if word.eq_ignore_ascii_case("COLUMN") {
+                word = words.next()?;
+            }
+            if word.eq_ignore_ascii_case("IF") {
+                words.next()?; // NOT
+                words.next()?; // EXISTS
+                word = words.next()?;
+            }
+            Some(unquote_identifier(word))
+        })
+        .collect()
+}
+
+/// Names of the columns removed by `DROP [COLUMN] [IF EXISTS] name` clauses
+pub fn dropped_column_names(sql: &str) -> Vec<String> {
+    alter_table_clauses(sql)
+        .iter()
+        .filter_map(|clause| {
+            let mut words = clause.split_whitespace();
+            if !words.next()?.eq_ignore_ascii_case("DROP") {
+                return None;
+            }
+            let mut word = words.next()?;
+            if word.eq_ignore_ascii_case("CONSTRAINT") {
+                return None;
+            }
+            if word.eq_ignore_ascii_case("COLUMN") {
+                word = words.next()?;
+            }
+            if word.eq_ignore_ascii_case("IF") {
+                words.next()?; // EXISTS
+                word = words.next()?;
+            }
+            Some(unquote_identifier(word))
+        })
+        .collect()
+}
+
+/// Names of the constraints removed by `DROP CONSTRAINT [IF EXISTS] name` clauses
+pub fn dropped_constraint_names(sql: &str) -> Vec<String> {
+    alter_table_clauses(sql)
+        .iter()
+        .filter_map(|clause| {
+            let mut words = clause.split_whitespace();
+            if !words.next()?.eq_ignore_ascii_case("DROP")
+                || !words.next()?.eq_ignore_ascii_case("CONSTRAINT")
+            {
+                return None;
+            }
+            let mut word = words.next()?;
+            if word.eq_ignore_ascii_case("IF") {
+                words.next()?; // EXISTS
+                word = words.next()?;
+            }
+            Some(unquote_identifier(word))
+        })
+        .collect()
+}
+
+/// Column definitions of every `ADD [COLUMN] [IF NOT EXISTS] <definition>` clause
+pub fn parse_add_columns(sql: &str) -> Vec<ColumnInfo> {
+    alter_table_clauses(sql)
+        .iter()
+        .filter_map(|clause| parse_column_tokens(&add_column_tokens(clause)?))
+        .collect()
+}
+
+/// Text of the definition of every `ADD [COLUMN] [IF NOT EXISTS] <definition>` clause
+pub fn added_column_definitions(sql: &str) -> Vec<String> {
+    alter_table_clauses(sql)
+        .iter()
+        .filter_map(|clause| Some(add_column_tokens(clause)?.join(" ")))
+        .collect()
+}
+
+/// Tokens of the column definition an ADD COLUMN clause holds
+fn add_column_tokens(clause: &str) -> Option<Vec<String>> {
+    let tokens = tokenize(clause);
+    if !tokens.first()?.eq_ignore_ascii_case("ADD") {
+        return None;
+    }
+    let mut start = 1;
+    if tokens.get(start)?.eq_ignore_ascii_case("CONSTRAINT") {
+        return None;
+    }
+    if tokens.get(start)?.eq_ignore_ascii_case("COLUMN") {
+        start += 1;
+    }
+    if tokens.get(start)?.eq_ignore_ascii_case("IF") {
+        start += 3;
+    }
+    Some(tokens.get(start..)?.to_vec())
+}
+
+/// Column and action of an `ALTER [COLUMN] name <action>` clause
+pub fn parse_alter_column(clause: &str) -> Option<(String, String)> {
+    let tokens = tokenize(clause);
+    if !tokens.first()?.eq_ignore_ascii_case("ALTER") {
+        return None;
+    }
+    let mut start = 1;
+    if tokens.get(start)?.eq_ignore_ascii_case("COLUMN") {
+        start += 1;
+    }
+    let action = tokens.get(start + 1..)?.join(" ");
+    (!action.is_empty()).then(|| (unquote_identifier(&tokens[start]), action))
+}
+
+/// Old and new name of an `ALTER TABLE ... RENAME [COLUMN] old TO new` statement
+pub fn parse_rename_column(sql: &str) -> Option<(String, String)> {
+    alter_table_clauses(sql).iter().find_map(|clause| {
+        let tokens = tokenize(clause);
+        let words: Vec<&str> = tokens.iter().map(String::as_str).collect();
+        match words.as_slice() {
+            [rename, column, old, to, new]
+                if rename.eq_ignore_ascii_case("RENAME")
+                    && column.eq_ignore_ascii_case("COLUMN")
+                    && to.eq_ignore_ascii_case("TO") =>
+            {
+                Some((unquote_identifier(old), unquote_identifier(new)))
+            }
+            [rename, old, to, new]
+                if rename.eq_ignore_ascii_case("RENAME")
+                    && !old.eq_ignore_ascii_case("CONSTRAINT")
+                    && to.eq_ignore_ascii_case("TO") =>
+            {
+                Some((unquote_identifier(old), unquote_identifier(new)))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// New name of an `ALTER TABLE ... RENAME TO new` statement
+pub fn parse_rename_table(sql: &str) -> Option<String> {
+    alter_table_clauses(sql).iter().find_map(|clause| {
+        let tokens = tokenize(clause);
+        match tokens.as_slice() {
+            [rename, to, new]
+                if rename.eq_ignore_ascii_case("RENAME") && to.eq_ignore_ascii_case("TO") =>
+            {
+                Some(unquote_identifier(new))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Old and new name of a `RENAME CONSTRAINT old TO new` clause
+pub fn parse_rename_constraint(sql: &str) -> Option<(String, String)> {
+    alter_table_clauses(sql).iter().find_map(|clause| {
+        let tokens = tokenize(clause);
+        match tokens.as_slice() {
+            [rename, constraint, old, to, new]
+                if rename.eq_ignore_ascii_case("RENAME")
+                    && constraint.eq_ignore_ascii_case("CONSTRAINT")
+                    && to.eq_ignore_ascii_case("TO") =>
+            {
+                Some((unquote_identifier(old), unquote_identifier(new)))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Name and labels of a `CREATE TYPE ... AS ENUM` statement
+pub fn parse_create_enum(sql: &str) -> Option<(String, Vec<String>)> {
+    let captures = CREATE_ENUM_RE.captures(sql)?;
+    let labels = first_parenthesized(&sql[captures.get(0)?.end() - 1..])?;
+    let labels = split_top_level(labels, ',')
+        .iter()
+        .filter(|label| !label.is_empty())
+        .map(|label| unquote_literal(label))
+        .collect();
+    Some((captures[1].to_string(), labels))
+}
+
+/// The label an `ALTER TYPE ... ADD VALUE` statement adds
+pub fn parse_add_enum_value(sql: &str) -> Option<EnumValue> {
+    let captures = ADD_ENUM_VALUE_RE.captures(sql)?;
+    let neighbour = captures.get(4).map(|label| unquote_literal(label.as_str()));
+    let before = captures.get(3).is_some_and(|position| position.as_str().eq_ignore_ascii_case("BEFORE"));
+    Some(EnumValue {
+        type_name: captures[1].to_string(),
+        label: unquote_literal(&captures[2]),
+        before: neighbour.clone().filter(|_| before),
+        after: neighbour.filter(|_| !before),
+    })
+}
+
+/// Types dropped by a `DROP TYPE` statement
+pub fn dropped_type_names(sql: &str) -> Vec<String> {
+    DROP_TYPE_RE
+        .captures(sql)
+        .map(|captures| split_top_level(&captures[1], ','))
+        .unwrap_or_default()
+}
+
+fn unquote_literal(literal: &str) -> String {
+    let literal = literal.trim();
+    let inner = literal.strip_prefix('\'').and_then(|l| l.strip_suffix('\'')).unwrap_or(literal);
+    inner.replace("''", "'")
+}
+
+/// Columns and table-level constraints of a CREATE TABLE statement
+pub fn parse_create_table(sql: &str) -> Option<(Vec<ColumnInfo>, Vec<ConstraintInfo>)> {
+    let body = first_parenthesized(sql)?;
+    let mut columns = Vec::new();
+    let mut constraints = Vec::new();
+
+    for element in split_top_level(body, ',') {
+        let upper = element.to_uppercase();
+        if upper.starts_with("LIKE ") {
+            return None;
+        }
+
+        if let Some(captures) = ADD_CONSTRAINT_RE.captures(&format!("ADD {}", element)) {
+            let definition = captures[2].trim().to_string();
+            constraints.push(ConstraintInfo {
+                name: unquote_identifier(&captures[1]),
+                constraint_type: constraint_type(&definition)?.to_string(),
+                columns: constraint_columns(&definition),
+                definition,
+            });
+        } else if let Some(kind) = constraint_type(&element) {
+            constraints.push(ConstraintInfo {
+                name: String::new(),
+                constraint_type: kind.to_string(),
+                definition: element.clone(),
+                columns: constraint_columns(&element),
+            });
+        } else {
+            columns.push(parse_column_tokens(&tokenize(&element))?);
+        }
+    }
+
+    Some((columns, constraints))
+}
+
+/// Name, table, plain columns and uniqueness of a CREATE INDEX statement
+pub fn parse_create_index(sql: &str) -> Option<IndexInfo> {
+    let tokens = tokenize(sql.trim().trim_end_matches(';'));
+    let unique = tokens.get(1)?.eq_ignore_ascii_case("UNIQUE");
+    let on = tokens.iter().position(|t| t.eq_ignore_ascii_case("ON"))?;
+
+    let name = tokens[..on]
+        .iter()
+        .rev()
+        .find(|t| {
+            !["INDEX", "CONCURRENTLY", "EXISTS", "NOT", "IF"]
+                .iter()
+                .any(|k| t.eq_ignore_ascii_case(k))
+        })
+        .filter(|t| !t.eq_ignore_ascii_case("CREATE") && !t.eq_ignore_ascii_case("UNIQUE"))?;
+
+    let mut rest = tokens[on + 1..].iter().peekable();
+    if rest.peek()?.eq_ignore_ascii_case("ONLY") {
+        rest.next();
+    }
+    let table_token = rest.next()?;
+
+    // The column list is either attached to the table name or follows an optional USING clause
+    let (table, column_list) = match table_token.find('(') {
+        Some(idx) => (&table_token[..idx], table_token[idx..].to_string()),
+        None => {
+            let next = rest.find(|t| t.starts_with('('))?;
+            (table_token.as_str(), next.clone())
+        }
+    };
+
+    let columns: Vec<String> = split_top_level(first_parenthesized(&column_list)?, ',')
+        .iter()
+        .map(|column| unquote_identifier(column))
+        .collect();
+
+    // Expression indexes cannot be described as a plain column list
+    if columns
+        .iter()
+        .any(|column| column.is_empty() || column.contains(|c: char| "() '".contains(c)))
+    {
+        return None;
+    }
+
+    Some(IndexInfo {
+        name: unquote_identifier(name),
+        table: table.to_string(),
+        columns,
+        unique,
+    })
+}
+
+/// Table a CREATE INDEX statement is on, including expression indexes
+pub fn index_table(sql: &str) -> Option<String> {
+    let tokens = tokenize(sql.trim().trim_end_matches(';'));
+    let on = tokens.iter().position(|t| t.eq_ignore_ascii_case("ON"))?;
+    let mut rest = tokens[on + 1..].iter();
+    let mut table = rest.next()?;
+    if table.eq_ignore_ascii_case("ONLY") {
+        table = rest.next()?;
+    }
+    let table = table.split('(').next().unwrap_or(table);
+    (!table.is_empty()).then(|| table.to_string())
+}
+
+/// Split a qualified `schema.name` into its parts
+pub fn split_qualified_name(name: &str) -> (Option<String>, String) {
+    let parts = split_top_level(name, '.');
+    match parts.as_slice() {
+        [schema, object] => (
+            Some(unquote_identifier(schema)),
+            unquote_identifier(object),
+        ),
+        _ => (None, unquote_identifier(name)),
+    }
+}
+
+/// Split on whitespace outside parentheses and quotes
+fn tokenize(s: &str) -> Vec<String> {
+    let normalized: String = s
+        .chars()
+        .map(|c| if c.is_whitespace() { ' ' } else { c })
+        .collect();
+    split_top_level(&normalized, ' ')
+        .into_iter()
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+fn is_column_keyword(token: &str) -> bool {
+    COLUMN_CONSTRAINT_KEYWORDS
+        .iter()
+        .any(|keyword| token.eq_ignore_ascii_case(keyword))
+}
+
+/// Parse `name type [constraints...]` into a `ColumnInfo`
+fn parse_column_tokens(tokens: &[String]) -> Option<ColumnInfo> {
+    let (name, rest) = tokens.split_first()?;
+    let type_len = rest
+        .iter()
+        .position(|token| is_column_keyword(token))
+        .unwrap_or(rest.len());
+    if type_len == 0 {
+        return None;
+    }
+
+    let mut column = ColumnInfo {
+        name: unquote_identifier(name),
+        data_type: rest[..type_len].join(" "),
+        nullable: true,
+        default: None,
+        constraints: Vec::new(),
+    };
+
+    let mut i = type_len;
+    while i < rest.len() {
+        let token = rest[i].to_uppercase();
+        // Tokens belonging to this clause, up to the next constraint keyword
+        let clause_end = rest[i + 1..]
+            .iter()
+            .position(|t| is_column_keyword(t))
+            .map(|pos| i + 1 + pos)
+            .unwrap_or(rest.len());
+
+        match token.as_str() {
+            "NOT" => {
+                column.nullable = false;
+                i += 2;
+                continue;
+            }
+            "NULL" => column.nullable = true,
+            "CONSTRAINT" => {
+                i += 2;
+                continue;
+            }
+            "DEFAULT" => column.default = Some(rest[i + 1..clause_end].join(" ")),
+            "PRIMARY" => {
+                column.nullable = false;
+                column.constraints.push("PRIMARY KEY".to_string());
+            }
+            "COLLATE" => {}
+            _ => column.constraints.push(rest[i..clause_end].join(" ")),
+        }
+        i = clause_end;
+    }
+
+    Some(column)
+}
+
+/// Parse every `ADD CONSTRAINT name <definition>` clause of an ALTER TABLE statement
+pub fn parse_add_constraints(sql: &str) -> Vec<ConstraintInfo> {
+    alter_table_clauses(sql)
+        .iter()
+        .filter_map(|clause| {
+            let captures = ADD_CONSTRAINT_RE.captures(clause)?;
+            let definition = captures[2].trim().to_string();
+            Some(ConstraintInfo {
+                name: unquote_identifier(&captures[1]),
+                constraint_type: constraint_type(&definition)?.to_string(),
+                columns: constraint_columns(&definition),
+                definition,
+            })
+        })
+        .collect()
+}
+
+/// Classify a constraint definition using pgroll's constraint type names
+pub fn constraint_type(definition: &str) -> Option<&'static str> {
+    let upper = definition.trim_start().to_uppercase();
+    if upper.starts_with("PRIMARY KEY") {
+        Some("primary_key")
+    } else if upper.starts_with("UNIQUE") {
+        Some("unique")
+    } else if upper.starts_with("CHECK") {
+        Some("check")
+    } else if upper.starts_with("FOREIGN KEY") {
+        Some("foreign_key")
+    } else if upper.starts_with("EXCLUDE") {
+        Some("exclude")
+    } else {
+        None
+    }
+}
+
+/// Contents of the first parenthesised group in `s`, without the parentheses
+pub fn first_parenthesized(s: &str) -> Option<&str> {
+    let start = s.find('(')?;
+    let mut depth = 0;
+    for (i, c) in s[start..].char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&s[start + 1..start + i]);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Columns a constraint definition applies to (empty for CHECK constraints)
+pub fn constraint_columns(definition: &str) -> Vec<String> {
+    if constraint_type(definition) == Some("check") {
+        return Vec::new();
+    }
+
+    first_parenthesized(definition)
+        .map(|columns| {
+            split_top_level(columns, ',')
+                .iter()
+                .map(|column| unquote_identifier(column))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Expression of a CHECK constraint definition
+pub fn check_expression(definition: &str) -> Option<String> {
+    if constraint_type(definition) != Some("check") {
+        return None;
+    }
+    first_parenthesized(definition).map(|expr| expr.trim().to_string())
+}
+
+/// Referenced table, columns and ON DELETE action of a FOREIGN KEY definition
+pub fn foreign_key_reference(definition: &str) -> Option<ForeignKeyReference> {
+    let captures = REFERENCES_RE.captures(definition)?;
+    let columns = captures
+        .get(2)
+        .and_then(|group| first_parenthesized(group.as_str()))
+        .map(|columns| {
+            split_top_level(columns, ',')
+                .iter()
+                .map(|column| unquote_identifier(column))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Some(ForeignKeyReference {
+        table: captures[1].to_string(),
+        columns,
+        on_delete: ON_DELETE_RE
+            .captures(definition)
+            .map(|on_delete| on_delete[1].to_uppercase()),
+    })
+}
+
+/// Every table named by a REFERENCES clause of `sql`
+pub fn referenced_tables(sql: &str) -> Vec<String> {
+    REFERENCES_RE
+        .captures_iter(sql)
+        .map(|captures| captures[1].to_string())
+        .collect()
+}
+
+/// View created, altered or dropped by `sql`
+pub fn view_name(sql: &str) -> Option<String> {
+    VIEW_RE.captures(sql).map(|captures| captures[1].to_string())
+}
+
+/// A column type as `format_type` prints it, so that aliases such as `int4`
+/// and `integer` or `varchar(20)` and `character varying(20)` compare equal
+///
+/// Serial types become their integer type.
+pub fn canonical_type(data_type: &str) -> String {
+    let data_type = data_type
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    let base = data_type.trim_end_matches("[]");
+    let array = &data_type[base.len()..];
+    // `timestamp(3) with time zone` carries its modifier before the suffix
+    let (name, modifier, suffix) = match (base.find('('), base.find(')')) {
+        (Some(open), Some(close)) if open < close => (
+            base[..open].trim_end(),
+            base[open..=close].replace(' ', ""),
+            base[close + 1..].trim(),
+        ),
+        _ => (base, String::new(), ""),
+    };
+    let (name, suffix) = match name {
+        "int" | "int4" | "serial" | "serial4" => ("integer", suffix),
+        "int8" | "bigserial" | "serial8" => ("bigint", suffix),
+        "int2" | "smallserial" | "serial2" => ("smallint", suffix),
+        "bool" => ("boolean", suffix),
+        "float8" => ("double precision", suffix),
+        "float4" => ("real", suffix),
+        "decimal" => ("numeric", suffix),
+        "varchar" => ("character varying", suffix),
+        "char" | "bpchar" => ("character", suffix),
+        "timestamptz" => ("timestamp", "with time zone"),
+        "timetz" => ("time", "with time zone"),
+        "timestamp" | "time" if suffix.is_empty() => (name, "without time zone"),
+        name => (name, suffix),
+    };
+    match suffix {
+        "" => format!("{}{}{}", name, modifier, array),
+        suffix => format!("{}{} {}{}", name, modifier, suffix, array),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_top_level() {
+        let parts = split_top_level("a int, b numeric(10, 2), c text DEFAULT 'x,y'", ',');
+        assert_eq!(
+            parts,
+            vec!["a int", "b numeric(10, 2)", "c text DEFAULT 'x,y'"]
+        );
+    }
+
+    #[test]
+    fn test_parse_add_constraints() {
+        let sql = "ALTER TABLE users ADD CONSTRAINT uk_email UNIQUE (email), \
+                   ADD CONSTRAINT \"ck age\" CHECK (age > 0)";
+        let constraints = parse_add_constraints(sql);
+        assert_eq!(constraints.len(), 2);
+        assert_eq!(constraints[0].name, "uk_email");
+        assert_eq!(constraints[0].constraint_type, "unique");
+        assert_eq!(constraints[1].name, "ck age");
+        assert_eq!(constraints[1].constraint_type, "check");
+        assert_eq!(
+            check_expression(&constraints[1].definition).as_deref(),
+            Some("age > 0")
+        );
+    }
+
+    #[test]
+    fn test_added_column_names() {
+        let sql = "ALTER TABLE users ADD COLUMN email text, ADD IF NOT EXISTS \"Phone\" text, \
+                   ADD CONSTRAINT uk_email UNIQUE (email)";
+        assert_eq!(added_column_names(sql), vec!["email", "Phone"]);
+    }
+
+    #[test]
+    fn test_split_statements() {
+        let script = "-- create things\n\
+            CREATE TABLE a (id int, note text DEFAULT ';');\n\
+            /* block; comment */ CREATE FUNCTION f() RETURNS int AS $body$ SELECT 1; $body$ LANGUAGE sql;\n\
+            ALTER TABLE \"we;ird\" ADD COLUMN b int";
+        let statements = split_statements(script);
+        assert_eq!(statements.len(), 3);
+        assert_eq!(statements[0], "CREATE TABLE a (id int, note text DEFAULT ';')");
+        assert!(statements[1].ends_with("$body$ SELECT 1; $body$ LANGUAGE sql"));
+        assert_eq!(statements[2], "ALTER TABLE \"we;ird\" ADD COLUMN b int");
+    }
+
+    #[test]
+    fn test_parse_create_table() {
+        let sql = "CREATE TABLE orders (
+            id SERIAL PRIMARY KEY,
+            user_id integer NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            total numeric(10, 2) DEFAULT 0 NOT NULL,
+            placed_at timestamp with time zone,
+            CONSTRAINT uk_orders UNIQUE (user_id, placed_at)
+        )";
+        let (columns, constraints) = parse_create_table(sql).unwrap();
+
+        assert_eq!(columns.len(), 4);
+        assert_eq!(columns[0].name, "id");
+        assert_eq!(columns[0].data_type, "SERIAL");
+        assert!(!columns[0].nullable);
+        assert_eq!(columns[0].constraints, vec!["PRIMARY KEY"]);
+        assert_eq!(
+            columns[1].constraints,
+            vec!["REFERENCES users(id) ON DELETE CASCADE"]
+        );
+        assert_eq!(columns[2].data_type, "numeric(10, 2)");
+        assert_eq!(columns[2].default.as_deref(), Some("0"));
+        assert!(!columns[2].nullable);
+        assert_eq!(columns[3].data_type, "timestamp with time zone");
+        assert!(columns[3].nullable);
+
+        assert_eq!(constraints.len(), 1);
+        assert_eq!(constraints[0].name, "uk_orders");
+        assert_eq!(constraints[0].constraint_type, "unique");
+    }
+
+    #[test]
+    fn test_parse_add_columns_and_renames() {
+        let columns = parse_add_columns(
+            "ALTER TABLE users ADD COLUMN email varchar(255) UNIQUE, ADD age int DEFAULT 18",
+        );
+        assert_eq!(columns.len(), 2);
+        assert_eq!(columns[0].data_type, "varchar(255)");
+        assert_eq!(columns[0].constraints, vec!["UNIQUE"]);
+        assert_eq!(columns[1].default.as_deref(), Some("18"));
+        assert_eq!(
+            added_column_definitions("ALTER TABLE users ADD COLUMN IF NOT EXISTS email varchar(255)  UNIQUE"),
+            vec!["email varchar(255) UNIQUE"]
+        );
+        assert_eq!(
+            parse_alter_column("ALTER COLUMN \"Age\" SET DEFAULT 18"),
+            Some(("Age".to_string(), "SET DEFAULT 18".to_string()))
+        );
+        assert_eq!(parse_alter_column("ALTER age"), None);
+
+        assert_eq!(
+            dropped_column_names("ALTER TABLE users DROP COLUMN IF EXISTS age"),
+            vec!["age"]
+        );
+        assert_eq!(
+            dropped_constraint_names("ALTER TABLE users DROP CONSTRAINT IF EXISTS uk_email, DROP age"),
+            vec!["uk_email"]
+        );
+        assert_eq!(
+            parse_rename_column("ALTER TABLE users RENAME COLUMN name TO full_name"),
+            Some(("name".to_string(), "full_name".to_string()))
+        );
+        assert_eq!(parse_rename_column("ALTER TABLE users RENAME TO people"), None);
+        assert_eq!(
+            parse_rename_table("ALTER TABLE users RENAME TO people"),
+            Some("people".to_string())
+        );
+        assert_eq!(
+            parse_rename_constraint("ALTER TABLE users RENAME CONSTRAINT uk_email TO users_email_key"),
+            Some(("uk_email".to_string(), "users_email_key".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_create_index() {
+        let index =
+            parse_create_index("CREATE UNIQUE INDEX idx_users_email ON public.users(email)")
+                .unwrap();
+        assert_eq!(index.name, "idx_users_email");
+        assert_eq!(index.table, "public.users");
+        assert_eq!(index.columns, vec!["email"]);
+        assert!(index.unique);
+
+        let index =
+            parse_create_index("CREATE INDEX IF NOT EXISTS idx ON users USING btree (a, b)")
+                .unwrap();
+        assert_eq!(index.name, "idx");
+        assert_eq!(index.columns, vec!["a", "b"]);
+        assert!(!index.unique);
+
+        assert!(parse_create_index("CREATE INDEX idx ON users (lower(email))").is_none());
+        assert_eq!(
+            index_table("CREATE INDEX idx ON ONLY users(lower(email))").as_deref(),
+            Some("users")
+        );
+    }
+
+    #[test]
+    fn test_foreign_key_reference() {
+        let definition = "FOREIGN KEY (user_id) REFERENCES public.users(id) ON DELETE CASCADE";
+        assert_eq!(constraint_columns(definition), vec!["user_id"]);
+        let reference = foreign_key_reference(definition).unwrap();
+        assert_eq!(reference.table, "public.users");
+        assert_eq!(reference.columns, vec!["id"]);
+        assert_eq!(reference.on_delete.as_deref(), Some("CASCADE"));
+        assert_eq!(
+            referenced_tables("CREATE TABLE pets (owner int REFERENCES people, vet int REFERENCES app.vets(id))"),
+            vec!["people", "app.vets"]
+        );
+    }
+
+    #[test]
+    fn test_view_name() {
+        assert_eq!(
+            view_name("CREATE OR REPLACE MATERIALIZED VIEW app.happy AS SELECT 1").as_deref(),
+            Some("app.happy")
+        );
+        assert_eq!(view_name("DROP VIEW IF EXISTS \"Happy\" CASCADE").as_deref(), Some("\"Happy\""));
+        assert_eq!(view_name("CREATE TABLE happy (id int)"), None);
+    }
+
+    #[test]
+    fn test_enum_statements() {
+        assert_eq!(
+            parse_create_enum("CREATE TYPE app.mood AS ENUM ('sad', 'it''s ok')"),
+            Some(("app.mood".to_string(), vec!["sad".to_string(), "it's ok".to_string()]))
+        );
+        let value = parse_add_enum_value("ALTER TYPE mood ADD VALUE IF NOT EXISTS 'meh' BEFORE 'ok'").unwrap();
+        assert_eq!(value.type_name, "mood");
+        assert_eq!(value.label, "meh");
+        assert_eq!(value.before.as_deref(), Some("ok"));
+        assert_eq!(value.after, None);
+        assert_eq!(dropped_type_names("DROP TYPE IF EXISTS mood, app.size CASCADE"), vec!["mood", "app.size"]);
+    }
+
+    #[test]
+    fn test_canonical_type() {
+        assert_eq!(canonical_type("int4"), "integer");
+        assert_eq!(canonical_type("serial"), "integer");
+        assert_eq!(canonical_type("VARCHAR (20)"), "character varying(20)");
+        assert_eq!(canonical_type("numeric(10, 2)"), "numeric(10,2)");
+        assert_eq!(canonical_type("timestamptz[]"), "timestamp with time zone[]");
+        assert_eq!(canonical_type("character varying(20)"), "character varying(20)");
+        assert_eq!(canonical_type("timestamp(3)"), "timestamp(3) without time zone");
+        assert_eq!(canonical_type("timestamp with time zone"), "timestamp with time zone");
+    }
+}
@@ -0,0 +1,504 @@
+use crate::catalog::IndexDefinition;
+use crate::ddl;
+use crate::ident::{quote_literal, sql_ident};
+use crate::migration::MigrationGenerator;
+use crate::model::{SchemaModel, TableDefinition};
+use crate::schema::{ChangeType, ConstraintInfo, SchemaChange, SchemaChangeParser};
+use std::collections::{BTreeMap, BTreeSet};
+use tracing::warn;
+
+/// Schema and name of a table
+type TableKey = (String, String);
+
+/// Changes turning `from` into `to`, in an order PostgreSQL accepts
+///
+/// Changed constraints and indexes are dropped and created again. New tables
+/// are created after the tables they reference; foreign keys closing a cycle
+/// are added once all tables exist. Each change carries the definitions it
+/// removes from `from`, for its rollback.
+pub fn diff(from: &SchemaModel, to: &SchemaModel) -> Vec<SchemaChange> {
+    let from_tables = tables(from);
+    let to_tables = tables(to);
+    let dropped: BTreeSet<TableKey> = from_tables
+        .keys()
+        .filter(|key| !to_tables.contains_key(*key))
+        .cloned()
+        .collect();
+    let created: BTreeSet<TableKey> = to_tables
+        .keys()
+        .filter(|key| !from_tables.contains_key(*key))
+        .cloned()
+        .collect();
+    let kept: Vec<&TableKey> = to_tables
+        .keys()
+        .filter(|key| from_tables.contains_key(*key))
+        .collect();
+
+    let mut changes = create_enums(from, to);
+
+    // Indexes and constraints that go away or change, on tables that stay
+    for key in &kept {
+        let (old, new) = (from_tables[*key], to_tables[*key]);
+        for index in &old.indexes {
+            if !is_constraint_index(old, &index.name) && !contains_index(&new.indexes, index) {
+                changes.push(change(
+                    &key.0,
+                    format!("DROP INDEX {}", qualified(&key.0, &index.name)),
+                ));
+            }
+        }
+    }
+    let mut dropped_constraints: Vec<SchemaChange> = Vec::new();
+    for key in &kept {
+        let (old, new) = (from_tables[*key], to_tables[*key]);
+        for constraint in old
+            .constraints
+            .iter()
+            .filter(|c| !contains_constraint(&new.constraints, c))
+        {
+            let sql = format!(
+                "ALTER TABLE {} DROP CONSTRAINT {}",
+                qualified(&key.0, &key.1),
+                sql_ident(&constraint.name)
+            );
+            dropped_constraints.push(change(&key.0, sql));
+        }
+    }
+    // Foreign keys first, as they may depend on a unique constraint dropped alongside
+    dropped_constraints.sort_by_key(|change| !is_foreign_key_drop(from, change));
+    changes.extend(dropped_constraints);
+
+    let (order, _) = creation_order(&from_tables, &dropped);
+    for key in order.iter().rev() {
+        changes.push(change(
+            &key.0,
+            format!("DROP TABLE {}", qualified(&key.0, &key.1)),
+        ));
+    }
+
+    let (order, deferred) = creation_order(&to_tables, &created);
+    let mut foreign_keys = Vec::new();
+    for key in &order {
+        let table = to_tables[key];
+        let (inline, later): (Vec<&ConstraintInfo>, Vec<&ConstraintInfo>) = table
+            .constraints
+            .iter()
+            .partition(|constraint| !deferred.contains(&(key.clone(), constraint.name.clone())));
+        changes.push(create_table(
+            key,
+            table,
+            inline.into_iter().cloned().collect(),
+        ));
+        foreign_keys.extend(
+            later
+                .into_iter()
+                .map(|constraint| add_constraint(key, constraint)),
+        );
+    }
+
+    for key in &kept {
+        changes.extend(alter_columns(key, from_tables[*key], to_tables[*key]));
+    }
+
+    let mut added_constraints: Vec<SchemaChange> = Vec::new();
+    for key in &kept {
+        let (old, new) = (from_tables[*key], to_tables[*key]);
+        for constraint in new
+            .constraints
+            .iter()
+            .filter(|c| !contains_constraint(&old.constraints, c))
+        {
+            let added = add_constraint(key, constraint);
+            if constraint.constraint_type == "foreign_key" {
+                foreign_keys.push(added);
+            } else {
+                added_constraints.push(added);
+            }
+        }
+    }
+    changes.extend(added_constraints);
+    changes.extend(foreign_keys);
+
+    for (key, table) in &to_tables {
+        let old = from_tables.get(key);
+        for index in &table.indexes {
+            let exists = old.is_some_and(|old| contains_index(&old.indexes, index));
+            if !is_constraint_index(table, &index.name) && !exists {
+                changes.push(change(&key.0, index.definition.clone()));
+            }
+        }
+    }
+
+    changes.extend(drop_enums(from, to));
+
+    // Attach what each change removes, as captured changes have it
+    let mut model = from.clone();
+    changes
+        .into_iter()
+        .map(|change| model.apply(change))
+        .collect()
+}
+
+/// Every table of `model`, by schema and name
+fn tables(model: &SchemaModel) -> BTreeMap<TableKey, &TableDefinition> {
+    model
+        .schemas
+        .iter()
+        .flat_map(|(schema, definition)| {
+            definition
+                .tables
+                .iter()
+                .map(move |(name, table)| ((schema.clone(), name.clone()), table))
+        })
+        .collect()
+}
+
+/// `schema.name` as SQL, leaving `public` implicit
+fn qualified(schema: &str, name: &str) -> String {
+    if schema == "public" {
+        sql_ident(name)
+    } else {
+        format!("{}.{}", sql_ident(schema), sql_ident(name))
+    }
+}
+
+/// Schema change for a generated statement, classified as a captured one is
+fn change(schema: &str, sql: String) -> SchemaChange {
+    let (change_type, object_name) = SchemaChangeParser::parse(&sql)
+        .unwrap_or((ChangeType::Other(String::new()), String::new()));
+    SchemaChange::new(change_type, schema.to_string(), object_name, sql)
+}
+
+fn create_table(
+    key: &TableKey,
+    table: &TableDefinition,
+    constraints: Vec<ConstraintInfo>,
+) -> SchemaChange {
+    let elements: Vec<String> = table
+        .columns
+        .iter()
+        .map(MigrationGenerator::column_sql)
+        .chain(constraints.iter().map(MigrationGenerator::constraint_sql))
+        .collect();
+    let sql = format!(
+        "CREATE TABLE {} ({})",
+        qualified(&key.0, &key.1),
+        elements.join(", ")
+    );
+    change(&key.0, sql)
+        .with_columns(table.columns.clone())
+        .with_constraints(constraints)
+}
+
+fn add_constraint(key: &TableKey, constraint: &ConstraintInfo) -> SchemaChange {
+    let sql = format!(
+        "ALTER TABLE {} ADD {}",
+        qualified(&key.0, &key.1),
+        MigrationGenerator::constraint_sql(constraint)
+    );
+    change(&key.0, sql).with_constraints(vec![constraint.clone()])
+}
+
+/// Dropped, added and modified columns of a table in both schemas
+fn alter_columns(
+    key: &TableKey,
+    old: &TableDefinition,
+    new: &TableDefinition,
+) -> Vec<SchemaChange> {
+    let table = qualified(&key.0, &key.1);
+    let mut changes = Vec::new();
+
+    for column in old
+        .columns
+        .iter()
+        .filter(|c| !new.columns.iter().any(|n| n.name == c.name))
+    {
+        let sql = format!(
+            "ALTER TABLE {} DROP COLUMN {}",
+            table,
+            sql_ident(&column.name)
+        );
+        changes.push(change(&key.0, sql));
+    }
+
+    for column in new
+        .columns
+        .iter()
+        .filter(|c| !old.columns.iter().any(|o| o.name == c.name))
+    {
+        let sql = format!(
+            "ALTER TABLE {} ADD COLUMN {}",
+            table,
+            MigrationGenerator::column_sql(column)
+        );
+        changes.push(change(&key.0, sql).with_columns(vec![column.clone()]));
+    }
+
+    let mut clauses = Vec::new();
+    for column in &new.columns {
+        let Some(before) = old.columns.iter().find(|o| o.name == column.name) else {
+            continue;
+        };
+        let name = sql_ident(&column.name);
+        if base_type(&before.data_type) != base_type(&column.data_type) {
+            clauses.push(format!(
+                "ALTER COLUMN {} TYPE {}",
+                name,
+                base_type(&column.data_type)
+            ));
+        }
+        if before.nullable != column.nullable {
+            let action = if column.nullable { "DROP" } else { "SET" };
+            clauses.push(format!("ALTER COLUMN {} {} NOT NULL", name, action));
+        }
+        if before.default != column.default {
+            match &column.default {
+                Some(default) => {
+                    clauses.push(format!("ALTER COLUMN {} SET DEFAULT {}", name, default))
+                }
+                None => clauses.push(format!("ALTER COLUMN {} DROP DEFAULT", name)),
+            }
+        }
+    }
+    if !clauses.is_empty() {
+        changes.push(change(
+            &key.0,
+            format!("ALTER TABLE {} {}", table, clauses.join(", ")),
+        ));
+    }
+
+    changes
+}
+
+/// Type of a column, with `serial` types as the integer type they stand for
+fn base_type(data_type: &str) -> &str {
+    match data_type {
+        "smallserial" => "smallint",
+        "serial" => "integer",
+        "bigserial" => "bigint",
+        _ => data_type,
+    }
+}
+
+/// Tables of `subset` ordered so that referenced tables come first, and the
+/// foreign keys left out to break reference cycles
+fn creation_order(
+    tables: &BTreeMap<TableKey, &TableDefinition>,
+    subset: &BTreeSet<TableKey>,
+) -> (Vec<TableKey>, BTreeSet<(TableKey, String)>) {
+    let references = |key: &TableKey| -> Vec<(String, TableKey)> {
+        tables[key]
+            .constraints
+            .iter()
+            .filter_map(|constraint| {
+                let reference = ddl::foreign_key_reference(&constraint.definition)?;
+                let (schema, name) = ddl::split_qualified_name(&reference.table);
+                // Unqualified names are in the table's schema or on the search path
+                let schema = schema.unwrap_or_else(|| {
+                    if subset.contains(&(key.0.clone(), name.clone())) {
+                        key.0.clone()
+                    } else {
+                        "public".to_string()
+                    }
+                });
+                Some((constraint.name.clone(), (schema, name)))
+            })
+            .filter(|(_, referenced)| referenced != key && subset.contains(referenced))
+            .collect()
+    };
+
+    let mut order: Vec<TableKey> = Vec::new();
+    let mut deferred = BTreeSet::new();
+    let mut remaining: Vec<&TableKey> = subset.iter().collect();
+    while !remaining.is_empty() {
+        let ready = remaining.iter().position(|key| {
+            references(key)
+                .iter()
+                .all(|(_, referenced)| order.contains(referenced))
+        });
+        // In a cycle, the first table waits for nothing and its foreign keys come later
+        let key = remaining.remove(ready.unwrap_or(0)).clone();
+        for (constraint, referenced) in references(&key) {
+            if !order.contains(&referenced) {
+                deferred.insert((key.clone(), constraint));
+            }
+        }
+        order.push(key);
+    }
+    (order, deferred)
+}
+
+fn contains_index(indexes: &[IndexDefinition], index: &IndexDefinition) -> bool {
+    indexes
+        .iter()
+        .any(|i| i.name == index.name && i.definition == index.definition)
+}
+
+/// Whether an index is the one backing a constraint of the table
+fn is_constraint_index(table: &TableDefinition, index: &str) -> bool {
+    table
+        .constraints
+        .iter()
+        .any(|constraint| constraint.name == index)
+}
+
+fn contains_constraint(constraints: &[ConstraintInfo], constraint: &ConstraintInfo) -> bool {
+    constraints
+        .iter()
+        .any(|c| c.name == constraint.name && c.definition == constraint.definition)
+}
+
+fn is_foreign_key_drop(model: &SchemaModel, change: &SchemaChange) -> bool {
+    let (_, table) = ddl::split_qualified_name(&change.object_name);
+    let names = ddl::dropped_constraint_names(&change.details.sql);
+    model
+        .table(&change.schema_name, &table)
+        .is_some_and(|table| {
+            table
+                .constraints
+                .iter()
+                .any(|c| c.constraint_type == "foreign_key" && names.contains(&c.name))
+        })
+}
+
+/// New enums and labels added to existing ones; removed labels cannot be
+/// dropped in place and are reported
+fn create_enums(from: &SchemaModel, to: &SchemaModel) -> Vec<SchemaChange> {
+    let mut changes = Vec::new();
+    for (schema, definition) in &to.schemas {
+        for (name, new) in definition.types.iter().filter(|(_, t)| t.kind == "enum") {
+            let labels =
+                |labels: &[String]| labels.iter().map(|l| quote_literal(l)).collect::<Vec<_>>();
+            let old = from.schemas.get(schema).and_then(|s| s.types.get(name));
+            let Some(old) = old else {
+                let sql = format!(
+                    "CREATE TYPE {} AS ENUM ({})",
+                    qualified(schema, name),
+                    labels(&new.labels).join(", ")
+                );
+                changes.push(change(schema, sql));
+                continue;
+            };
+
+            if old.labels.iter().any(|label| !new.labels.contains(label)) {
+                warn!(
+                    "Enum {}.{} lost labels, which cannot be removed in place",
+                    schema, name
+                );
+            }
+            for (i, label) in new
+                .labels
+                .iter()
+                .enumerate()
+                .filter(|(_, l)| !old.labels.contains(l))
+            {
+                let position = match i {
+                    0 => format!("BEFORE {}", quote_literal(&new.labels[1])),
+                    _ => format!("AFTER {}", quote_literal(&new.labels[i - 1])),
+                };
+                let sql = format!(
+                    "ALTER TYPE {} ADD VALUE {} {}",
+                    qualified(schema, name),
+                    quote_literal(label),
+                    position
+                );
+                changes.push(change(schema, sql));
+            }
+        }
+    }
+    changes
+}
+
+fn drop_enums(from: &SchemaModel, to: &SchemaModel) -> Vec<SchemaChange> {
+    let mut changes = Vec::new();
+    for (schema, definition) in &from.schemas {
+        for (name, _) in definition.types.iter().filter(|(_, t)| t.kind == "enum") {
+            if to
+                .schemas
+                .get(schema)
+                .is_none_or(|s| !s.types.contains_key(name))
+            {
+                changes.push(change(
+                    schema,
+                    format!("DROP TYPE {}", qualified(schema, name)),
+                ));
+            }
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(statements: &[&str]) -> SchemaModel {
+        let mut model = SchemaModel::default();
+        for statement in SchemaChangeParser::parse_script(&statements.join(";\n"), "public") {
+            model.apply(statement);
+        }
+        model
+    }
+
+    fn statements(changes: &[SchemaChange]) -> Vec<&str> {
+        changes
+            .iter()
+            .map(|change| change.details.sql.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_orders_changes() {
+        let from = model(&[
+            "CREATE TABLE users (id int, name text, CONSTRAINT users_pkey PRIMARY KEY (id))",
+            "CREATE TABLE old (id int)",
+            "CREATE INDEX users_name_idx ON users (name)",
+        ]);
+        let to = model(&[
+            "CREATE TABLE orders (id int, user_id int, CONSTRAINT orders_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id))",
+            "CREATE TABLE users (id bigint NOT NULL, email text, CONSTRAINT users_pkey PRIMARY KEY (id))",
+            "CREATE INDEX users_email_idx ON users (email)",
+        ]);
+
+        let changes = diff(&from, &to);
+        assert_eq!(
+            statements(&changes),
+            vec![
+                "DROP INDEX users_name_idx",
+                "DROP TABLE old",
+                "CREATE TABLE orders (id int, user_id int, CONSTRAINT orders_user_id_fkey FOREIGN KEY (user_id) REFERENCES users(id))",
+                "ALTER TABLE users DROP COLUMN name",
+                "ALTER TABLE users ADD COLUMN email text",
+                "ALTER TABLE users ALTER COLUMN id TYPE bigint, ALTER COLUMN id SET NOT NULL",
+                "CREATE INDEX users_email_idx ON users (email)",
+            ]
+        );
+        assert_eq!(changes[3].change_type, ChangeType::DropColumn);
+        // The dropped column comes with its definition, for the rollback
+        assert_eq!(
+            changes[3].details.columns.as_ref().unwrap()[0].data_type,
+            "text"
+        );
+        assert!(diff(&to, &to).is_empty());
+    }
+
+    #[test]
+    fn test_creation_order_breaks_cycles() {
+        let to = model(&[
+            "CREATE TABLE b (id int, a_id int, CONSTRAINT b_a_fkey FOREIGN KEY (a_id) REFERENCES a(id))",
+            "CREATE TABLE a (id int, b_id int, CONSTRAINT a_b_fkey FOREIGN KEY (b_id) REFERENCES b(id))",
+            "CREATE TABLE c (id int, CONSTRAINT c_b_fkey FOREIGN KEY (id) REFERENCES b(id))",
+        ]);
+
+        let changes = diff(&SchemaModel::default(), &to);
+        assert_eq!(
+            statements(&changes),
+            vec![
+                "CREATE TABLE a (id int, b_id int)",
+                "CREATE TABLE b (id int, a_id int, CONSTRAINT b_a_fkey FOREIGN KEY (a_id) REFERENCES a(id))",
+                "CREATE TABLE c (id int, CONSTRAINT c_b_fkey FOREIGN KEY (id) REFERENCES b(id))",
+                "ALTER TABLE a ADD CONSTRAINT a_b_fkey FOREIGN KEY (b_id) REFERENCES b(id)",
+            ]
+        );
+    }
+}
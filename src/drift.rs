@@ -0,0 +1,315 @@
+use crate::ddl;
+use crate::model::{SchemaModel, TableDefinition};
+use crate::schema::ColumnInfo;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// A difference between the schema the migrations build and the database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Drift {
+    /// Created by the migrations, absent from the database
+    Missing(String),
+    /// In the database, never created by the migrations
+    Unexpected(String),
+    /// In both, defined differently
+    Changed {
+        object: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Drift::Missing(object) => write!(f, "- {} is missing from the database", object),
+            Drift::Unexpected(object) => write!(f, "+ {} is not in the migrations", object),
+            Drift::Changed {
+                object,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "~ {} is {} in the database, {} in the migrations",
+                object, actual, expected
+            ),
+        }
+    }
+}
+
+/// How the tables of `actual` depart from `expected`
+///
+/// Tables are matched by name, columns by name, type and nullability, and
+/// indexes by name. Constraints are matched by kind and columns, since
+/// constraints written inline in a statement have no name until PostgreSQL
+/// gives them one. Default expressions are only compared for presence, as
+/// PostgreSQL rewrites them.
+pub fn compare(expected: &SchemaModel, actual: &SchemaModel) -> Vec<Drift> {
+    let mut drifts = Vec::new();
+    let names = |model: &SchemaModel| -> BTreeSet<(String, String)> {
+        model
+            .schemas
+            .iter()
+            .flat_map(|(schema, definition)| {
+                definition
+                    .tables
+                    .keys()
+                    .map(move |table| (schema.clone(), table.clone()))
+            })
+            .collect()
+    };
+
+    for (schema, table) in names(expected).union(&names(actual)) {
+        let object = format!("table {}.{}", schema, table);
+        match (expected.table(schema, table), actual.table(schema, table)) {
+            (Some(expected), Some(actual)) => compare_tables(
+                &format!("{}.{}", schema, table),
+                expected,
+                actual,
+                &mut drifts,
+            ),
+            (Some(_), None) => drifts.push(Drift::Missing(object)),
+            (None, Some(_)) => drifts.push(Drift::Unexpected(object)),
+            (None, None) => {}
+        }
+    }
+    drifts
+}
+
+fn compare_tables(
+    table: &str,
+    expected: &TableDefinition,
+    actual: &TableDefinition,
+    drifts: &mut Vec<Drift>,
+) {
+    let find = |columns: &[ColumnInfo], name: &str| -> Option<ColumnInfo> {
+        columns
+            .iter()
+            .find(|column| same_name(&column.name, name))
+            .cloned()
+    };
+    for column in &expected.columns {
+        let object = format!("column {}.{}", table, column.name);
+        let Some(found) = find(&actual.columns, &column.name) else {
+            drifts.push(Drift::Missing(object));
+            continue;
+        };
+        let changed = |expected: String, actual: String| Drift::Changed {
+            object: object.clone(),
+            expected,
+            actual,
+        };
+        let (expected_type, actual_type) = (
+            ddl::canonical_type(&column.data_type),
+            ddl::canonical_type(&found.data_type),
+        );
+        if expected_type != actual_type {
+            drifts.push(changed(expected_type, actual_type));
+        }
+        let (expected_null, actual_null) = (nullable(expected, column), nullable(actual, &found));
+        if expected_null != actual_null {
+            drifts.push(changed(null_label(expected_null), null_label(actual_null)));
+        }
+        if column.default.is_some() != found.default.is_some()
+            && !is_serial(column)
+            && !is_serial(&found)
+        {
+            drifts.push(changed(
+                default_label(&column.default),
+                default_label(&found.default),
+            ));
+        }
+    }
+    for column in &actual.columns {
+        if find(&expected.columns, &column.name).is_none() {
+            drifts.push(Drift::Unexpected(format!(
+                "column {}.{}",
+                table, column.name
+            )));
+        }
+    }
+
+    let mut unmatched = constraint_keys(actual);
+    for (key, name) in constraint_keys(expected) {
+        match unmatched.iter().position(|(other, _)| *other == key) {
+            Some(position) => {
+                unmatched.remove(position);
+            }
+            None => drifts.push(Drift::Missing(constraint_label(table, &key, &name))),
+        }
+    }
+    for (key, name) in unmatched {
+        drifts.push(Drift::Unexpected(constraint_label(table, &key, &name)));
+    }
+
+    // Indexes of primary key and unique constraints come with the constraint
+    let indexes = |definition: &TableDefinition| -> Vec<(String, bool)> {
+        definition
+            .indexes
+            .iter()
+            .filter(|index| !definition.constraints.iter().any(|c| c.name == index.name))
+            .map(|index| (index.name.to_lowercase(), index.unique))
+            .collect()
+    };
+    let (expected_indexes, actual_indexes) = (indexes(expected), indexes(actual));
+    for (name, unique) in &expected_indexes {
+        let object = format!("index {} on {}", name, table);
+        match actual_indexes.iter().find(|(other, _)| other == name) {
+            None => drifts.push(Drift::Missing(object)),
+            Some((_, other)) if other != unique => drifts.push(Drift::Changed {
+                object,
+                expected: unique_label(*unique),
+                actual: unique_label(*other),
+            }),
+            Some(_) => {}
+        }
+    }
+    for (name, _) in &actual_indexes {
+        if !expected_indexes.iter().any(|(other, _)| other == name) {
+            drifts.push(Drift::Unexpected(format!("index {} on {}", name, table)));
+        }
+    }
+}
+
+/// Kind and columns of every constraint, with its name when it has one;
+/// CHECK constraints are only counted, their columns being unknown when parsed
+fn constraint_keys(table: &TableDefinition) -> Vec<((String, Vec<String>), String)> {
+    let key = |kind: &str, columns: &[String]| {
+        let columns = if kind == "check" {
+            Vec::new()
+        } else {
+            columns.iter().map(|column| column.to_lowercase()).collect()
+        };
+        (kind.to_string(), columns)
+    };
+    let mut keys: Vec<_> = table
+        .constraints
+        .iter()
+        .map(|constraint| {
+            let columns = if constraint.columns.is_empty() {
+                ddl::constraint_columns(&constraint.definition)
+            } else {
+                constraint.columns.clone()
+            };
+            (
+                key(&constraint.constraint_type, &columns),
+                constraint.name.clone(),
+            )
+        })
+        .collect();
+    for column in &table.columns {
+        for constraint in &column.constraints {
+            let upper = constraint.to_uppercase();
+            let kind = if upper == "PRIMARY KEY" {
+                "primary_key"
+            } else if upper == "UNIQUE" {
+                "unique"
+            } else if upper.starts_with("CHECK") {
+                "check"
+            } else if upper.starts_with("REFERENCES") {
+                "foreign_key"
+            } else {
+                continue;
+            };
+            keys.push((key(kind, std::slice::from_ref(&column.name)), String::new()));
+        }
+    }
+    keys
+}
+
+fn constraint_label(table: &str, (kind, columns): &(String, Vec<String>), name: &str) -> String {
+    let kind = kind.replace('_', " ");
+    match (name.is_empty(), columns.is_empty()) {
+        (false, _) => format!("{} constraint {} on {}", kind, name, table),
+        (true, false) => format!("{} constraint on {} ({})", kind, table, columns.join(", ")),
+        (true, true) => format!("{} constraint on {}", kind, table),
+    }
+}
+
+/// Whether `column` accepts NULL, columns of a table-level primary key not included
+fn nullable(table: &TableDefinition, column: &ColumnInfo) -> bool {
+    column.nullable
+        && !column
+            .constraints
+            .iter()
+            .any(|c| c.eq_ignore_ascii_case("PRIMARY KEY"))
+        && !table.constraints.iter().any(|constraint| {
+            constraint.constraint_type == "primary_key"
+                && ddl::constraint_columns(&constraint.definition)
+                    .iter()
+                    .chain(&constraint.columns)
+                    .any(|name| same_name(&column.name, name))
+        })
+}
+
+fn is_serial(column: &ColumnInfo) -> bool {
+    column.data_type.to_lowercase().ends_with("serial")
+}
+
+fn same_name(name: &str, other: &str) -> bool {
+    name == other || name.to_lowercase() == other.to_lowercase()
+}
+
+fn null_label(nullable: bool) -> String {
+    if nullable { "nullable" } else { "NOT NULL" }.to_string()
+}
+
+fn default_label(default: &Option<String>) -> String {
+    match default {
+        Some(default) => format!("DEFAULT {}", default),
+        None => "without default".to_string(),
+    }
+}
+
+fn unique_label(unique: bool) -> String {
+    if unique { "unique" } else { "not unique" }.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{ChangeType, SchemaChange, SchemaChangeParser};
+
+    fn model(statements: &[&str]) -> SchemaModel {
+        let mut model = SchemaModel::default();
+        for change in SchemaChangeParser::parse_script(&statements.join(";\n"), "public") {
+            model.apply(change);
+        }
+        model
+    }
+
+    #[test]
+    fn test_compare_reports_each_kind_of_drift() {
+        let expected = model(&[
+            "CREATE TABLE users (id serial PRIMARY KEY, email varchar(255) UNIQUE, age int)",
+            "CREATE INDEX idx_age ON users (age)",
+            "CREATE TABLE audit (id int)",
+        ]);
+        let mut actual = model(&[
+            "CREATE TABLE users (id integer NOT NULL, email character varying(255), age int NOT NULL, \
+             note text, CONSTRAINT users_pkey PRIMARY KEY (id), CONSTRAINT users_email_key UNIQUE (email))",
+            "CREATE INDEX idx_age ON users (age)",
+        ]);
+        assert_eq!(compare(&expected, &expected), vec![]);
+
+        actual.apply(SchemaChange::new(
+            ChangeType::CreateIndex,
+            "public".to_string(),
+            "idx_note".to_string(),
+            "CREATE INDEX idx_note ON users (note)".to_string(),
+        ));
+        let drifts: Vec<String> = compare(&expected, &actual)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            drifts,
+            [
+                "- table public.audit is missing from the database",
+                "~ column public.users.age is NOT NULL in the database, nullable in the migrations",
+                "+ column public.users.note is not in the migrations",
+                "+ index idx_note on public.users is not in the migrations",
+            ]
+        );
+    }
+}
@@ -0,0 +1,359 @@
+use crate::config::{PgrollFilterConfig, SchemaFilterConfig};
+use crate::ddl;
+use crate::schema::{ChangeType, SchemaChange};
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref IDENTIFIER_RE: Regex = Regex::new(r#"[A-Za-z_][A-Za-z0-9_$]*|"(?:[^"]|"")+""#).unwrap();
+}
+
+/// Recognises DDL that pgroll runs itself while applying a migration
+///
+/// A change is pgroll's own when it was issued from a pgroll session, touches
+/// pgroll's state schema or one of the versioned schemas created for a known
+/// migration (`<schema>_<migration>`), or names one of its internal objects
+/// such as `_pgroll_new_*` shadow columns and `_pgroll_trigger_*` triggers.
+pub struct PgrollFilter {
+    enabled: bool,
+    state_schema: String,
+    object_patterns: Vec<Regex>,
+    application_names: Vec<String>,
+    migration_names: Vec<String>,
+}
+
+impl PgrollFilter {
+    pub fn new(config: &PgrollFilterConfig) -> Result<Self> {
+        let object_patterns = config
+            .object_patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern)
+                    .with_context(|| format!("Invalid pgroll object pattern: {}", pattern))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            enabled: config.enabled,
+            state_schema: config.state_schema.clone(),
+            object_patterns,
+            application_names: config.application_names.clone(),
+            migration_names: Vec::new(),
+        })
+    }
+
+    /// Schema holding pgroll's migration history
+    pub fn state_schema(&self) -> &str {
+        &self.state_schema
+    }
+
+    /// Replace the migration names versioned schemas are recognised by
+    pub fn set_migration_names(&mut self, names: Vec<String>) {
+        self.migration_names = names;
+    }
+
+    /// Why `change` belongs to pgroll, or `None` for user DDL
+    pub fn matches(&self, change: &SchemaChange) -> Option<String> {
+        if !self.enabled {
+            return None;
+        }
+
+        let metadata = &change.details.metadata;
+        if let Some(application_name) = metadata.get("application_name") {
+            if self.application_names.contains(application_name) {
+                return Some(format!("application_name {}", application_name));
+            }
+        }
+
+        for schema in Self::schemas(change) {
+            if schema == self.state_schema {
+                return Some(format!("pgroll state schema {}", schema));
+            }
+            if let Some(migration) = self
+                .migration_names
+                .iter()
+                .find(|migration| schema.ends_with(&format!("_{}", migration)))
+            {
+                return Some(format!("versioned schema {} of migration {}", schema, migration));
+            }
+        }
+
+        IDENTIFIER_RE
+            .find_iter(&change.details.sql)
+            .map(|identifier| ddl::unquote_identifier(identifier.as_str()))
+            .find(|identifier| {
+                self.object_patterns
+                    .iter()
+                    .any(|pattern| pattern.is_match(identifier))
+            })
+            .map(|identifier| format!("internal object {}", identifier))
+    }
+
+    /// Schemas a change touches: its own, and the one in the object identity
+    /// reported by the event trigger (the schema itself for CREATE/DROP SCHEMA)
+    fn schemas(change: &SchemaChange) -> Vec<String> {
+        let mut schemas = vec![change.schema_name.clone()];
+        if let Some(identity) = change.details.metadata.get("object_identity") {
+            let is_schema = change
+                .details
+                .metadata
+                .get("command_tag")
+                .is_some_and(|tag| tag.ends_with("SCHEMA"));
+            match ddl::split_qualified_name(identity) {
+                (Some(schema), _) => schemas.push(schema),
+                (None, name) if is_schema => schemas.push(name),
+                _ => {}
+            }
+        }
+        schemas
+    }
+}
+
+/// Name pattern from the schema filter: a glob such as `audit_*` or a
+/// regex between slashes such as `/^tmp_\d+$/`
+struct Pattern {
+    source: String,
+    regex: Regex,
+    is_regex: bool,
+}
+
+impl Pattern {
+    fn parse(source: &str) -> Result<Self> {
+        let is_regex = source.len() >= 2 && source.starts_with('/') && source.ends_with('/');
+        let expression = if is_regex {
+            source[1..source.len() - 1].to_string()
+        } else {
+            let mut expression = String::from("^");
+            for c in source.chars() {
+                match c {
+                    '*' => expression.push_str(".*"),
+                    '?' => expression.push('.'),
+                    c => expression.push_str(&regex::escape(&c.to_string())),
+                }
+            }
+            expression.push('$');
+            expression
+        };
+
+        Ok(Self {
+            source: source.to_string(),
+            regex: Regex::new(&expression)
+                .with_context(|| format!("Invalid schema filter pattern: {}", source))?,
+            is_regex,
+        })
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.regex.is_match(name)
+    }
+
+    /// Table patterns match `schema.table` when they are regexes or contain
+    /// a dot, and the bare table name otherwise
+    fn matches_table(&self, schema: &str, table: &str) -> bool {
+        if self.is_regex || self.source.contains('.') {
+            self.matches(&format!("{}.{}", schema, table))
+        } else {
+            self.matches(table)
+        }
+    }
+}
+
+/// Include and exclude rules for the schemas and tables repligrate follows
+///
+/// Empty include lists allow everything; exclude rules win over include rules.
+pub struct SchemaFilter {
+    include_schemas: Vec<Pattern>,
+    exclude_schemas: Vec<Pattern>,
+    include_tables: Vec<Pattern>,
+    exclude_tables: Vec<Pattern>,
+}
+
+impl SchemaFilter {
+    pub fn new(config: &SchemaFilterConfig) -> Result<Self> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| Pattern::parse(pattern))
+                .collect::<Result<Vec<_>>>()
+        };
+
+        Ok(Self {
+            include_schemas: compile(&config.include_schemas)?,
+            exclude_schemas: compile(&config.exclude_schemas)?,
+            include_tables: compile(&config.include_tables)?,
+            exclude_tables: compile(&config.exclude_tables)?,
+        })
+    }
+
+    /// Whether any table rules are configured
+    pub fn has_table_rules(&self) -> bool {
+        !self.include_tables.is_empty() || !self.exclude_tables.is_empty()
+    }
+
+    /// The rule excluding a schema, if any
+    pub fn schema_rule(&self, schema: &str) -> Option<String> {
+        if let Some(pattern) = self.exclude_schemas.iter().find(|p| p.matches(schema)) {
+            return Some(format!("exclude_schemas `{}`", pattern.source));
+        }
+        if !self.include_schemas.is_empty() && !self.include_schemas.iter().any(|p| p.matches(schema)) {
+            return Some("no include_schemas rule matches".to_string());
+        }
+        None
+    }
+
+    /// The rule excluding a table, if any
+    pub fn table_rule(&self, schema: &str, table: &str) -> Option<String> {
+        if let Some(rule) = self.schema_rule(schema) {
+            return Some(rule);
+        }
+        if let Some(pattern) = self
+            .exclude_tables
+            .iter()
+            .find(|p| p.matches_table(schema, table))
+        {
+            return Some(format!("exclude_tables `{}`", pattern.source));
+        }
+        if !self.include_tables.is_empty()
+            && !self.include_tables.iter().any(|p| p.matches_table(schema, table))
+        {
+            return Some("no include_tables rule matches".to_string());
+        }
+        None
+    }
+
+    /// The rule excluding a change, if any
+    pub fn excluded(&self, change: &SchemaChange) -> Option<String> {
+        match Self::table_of(change) {
+            Some(table) => self.table_rule(&change.schema_name, &table),
+            None => self.schema_rule(&change.schema_name),
+        }
+    }
+
+    /// Unqualified name of the table a change applies to, when known
+    fn table_of(change: &SchemaChange) -> Option<String> {
+        match change.change_type {
+            ChangeType::CreateIndex => {
+                ddl::parse_create_index(&change.details.sql).map(|index| {
+                    let (_, table) = ddl::split_qualified_name(&index.table);
+                    table
+                })
+            }
+            ChangeType::DropIndex | ChangeType::Other(_) => {
+                let metadata = &change.details.metadata;
+                if metadata.get("object_type").map(String::as_str) != Some("table") {
+                    return None;
+                }
+                let (_, table) = ddl::split_qualified_name(metadata.get("object_identity")?);
+                Some(table)
+            }
+            _ => Some(ddl::split_qualified_name(&change.object_name).1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::SchemaChangeParser;
+
+    fn change(schema: &str, sql: &str) -> SchemaChange {
+        let (change_type, object_name) = SchemaChangeParser::parse(sql).unwrap();
+        SchemaChange::new(change_type, schema.to_string(), object_name, sql.to_string())
+            .with_metadata("application_name".to_string(), "psql".to_string())
+    }
+
+    fn filter() -> PgrollFilter {
+        let mut filter = PgrollFilter::new(&PgrollFilterConfig::default()).unwrap();
+        filter.set_migration_names(vec!["0003_add_email".to_string()]);
+        filter
+    }
+
+    #[test]
+    fn test_keeps_user_changes() {
+        let user = change("public", "ALTER TABLE users ADD COLUMN pgroll_notes text");
+        assert_eq!(filter().matches(&user), None);
+    }
+
+    #[test]
+    fn test_matches_internal_objects() {
+        let shadow = change("public", "ALTER TABLE users ADD COLUMN _pgroll_new_email text");
+        let trigger = change(
+            "public",
+            "CREATE TRIGGER \"_pgroll_trigger_users_email\" BEFORE INSERT ON users FOR EACH ROW EXECUTE FUNCTION f()",
+        );
+        assert!(filter().matches(&shadow).is_some());
+        assert!(filter().matches(&trigger).is_some());
+    }
+
+    #[test]
+    fn test_matches_schemas_and_sessions() {
+        let state = change("pgroll", "CREATE TABLE pgroll.migrations (name text)");
+        let versioned = change("public", "CREATE SCHEMA public_0003_add_email")
+            .with_metadata("command_tag".to_string(), "CREATE SCHEMA".to_string())
+            .with_metadata("object_identity".to_string(), "public_0003_add_email".to_string());
+        let view = change("public_0003_add_email", "CREATE VIEW users AS SELECT * FROM public.users");
+        let session = change("public", "CREATE INDEX idx ON users (email)")
+            .with_metadata("application_name".to_string(), "pgroll".to_string());
+
+        let filter = filter();
+        assert!(filter.matches(&state).is_some());
+        assert!(filter.matches(&versioned).is_some());
+        assert!(filter.matches(&view).is_some());
+        assert!(filter.matches(&session).is_some());
+    }
+
+    #[test]
+    fn test_disabled() {
+        let config = PgrollFilterConfig {
+            enabled: false,
+            ..Default::default()
+        };
+        let shadow = change("pgroll", "ALTER TABLE users ADD COLUMN _pgroll_new_email text");
+        assert_eq!(PgrollFilter::new(&config).unwrap().matches(&shadow), None);
+    }
+
+    fn schema_filter(include_schemas: &[&str], exclude_tables: &[&str]) -> SchemaFilter {
+        let to_vec = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect();
+        SchemaFilter::new(&SchemaFilterConfig {
+            include_schemas: to_vec(include_schemas),
+            exclude_tables: to_vec(exclude_tables),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_schema_filter_globs() {
+        let filter = schema_filter(&["app*"], &["tmp_*", "audit.log"]);
+        assert_eq!(filter.table_rule("app", "users"), None);
+        assert_eq!(
+            filter.table_rule("app", "tmp_import").as_deref(),
+            Some("exclude_tables `tmp_*`")
+        );
+        assert!(filter.table_rule("app", "log").is_none());
+        assert!(filter.table_rule("apple", "users").is_none());
+        assert_eq!(
+            filter.schema_rule("pg_catalog").as_deref(),
+            Some("exclude_schemas `pg_catalog`")
+        );
+        assert_eq!(
+            filter.schema_rule("public").as_deref(),
+            Some("no include_schemas rule matches")
+        );
+    }
+
+    #[test]
+    fn test_schema_filter_regex_and_changes() {
+        let filter = schema_filter(&["public", "sales"], &[r"/^sales\.archive_\d+$/"]);
+        assert!(filter.table_rule("sales", "archive_2024").is_some());
+        assert!(filter.table_rule("public", "archive_2024").is_none());
+
+        let index = change("sales", "CREATE INDEX idx_archive ON archive_2023 (id)");
+        let elsewhere = change("staging", "ALTER TABLE orders ADD COLUMN note text");
+        let kept = change("public", "ALTER TABLE orders ADD COLUMN note text");
+        assert!(filter.excluded(&index).is_some());
+        assert!(filter.excluded(&elsewhere).is_some());
+        assert_eq!(filter.excluded(&kept), None);
+    }
+}
@@ -0,0 +1,174 @@
+use anyhow::{anyhow, bail, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// Quote an identifier for use in SQL, doubling embedded quotes
+pub fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Reserved words that cannot be used as bare identifiers
+const RESERVED_WORDS: &[&str] = &[
+    "all", "analyse", "analyze", "and", "any", "array", "as", "asc", "asymmetric", "both", "case",
+    "cast", "check", "collate", "column", "constraint", "create", "current_catalog",
+    "current_date", "current_role", "current_time", "current_timestamp", "current_user",
+    "default", "deferrable", "desc", "distinct", "do", "else", "end", "except", "false", "fetch",
+    "for", "foreign", "from", "grant", "group", "having", "in", "initially", "intersect", "into",
+    "lateral", "leading", "limit", "localtime", "localtimestamp", "not", "null", "offset", "on",
+    "only", "or", "order", "placing", "primary", "references", "returning", "select",
+    "session_user", "some", "symmetric", "table", "then", "to", "trailing", "true", "union",
+    "unique", "user", "using", "variadic", "when", "where", "window", "with",
+];
+
+/// An identifier as PostgreSQL prints it: bare when that reads back the
+/// same, quoted otherwise
+pub fn sql_ident(name: &str) -> String {
+    let mut chars = name.chars();
+    let bare = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '$')
+        && !RESERVED_WORDS.contains(&name);
+    if bare {
+        name.to_string()
+    } else {
+        quote_ident(name)
+    }
+}
+
+/// Quote a string literal for use in SQL, as PostgreSQL's `quote_literal` does
+pub fn quote_literal(value: &str) -> String {
+    let quoted = value.replace('\'', "''");
+    if quoted.contains('\\') {
+        format!("E'{}'", quoted.replace('\\', "\\\\"))
+    } else {
+        format!("'{}'", quoted)
+    }
+}
+
+/// Table name with an optional schema, as PostgreSQL resolves it: unquoted
+/// parts fold to lower case, quoted parts are kept as written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QualifiedName {
+    pub schema: Option<String>,
+    pub name: String,
+}
+
+impl QualifiedName {
+    pub fn new(schema: Option<String>, name: String) -> Self {
+        Self { schema, name }
+    }
+}
+
+impl FromStr for QualifiedName {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut chars = s.trim().chars().peekable();
+
+        loop {
+            let mut part = String::new();
+            if chars.peek() == Some(&'"') {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            part.push('"');
+                        }
+                        Some('"') => break,
+                        Some(c) => part.push(c),
+                        None => bail!("Unterminated quoted identifier in {}", s),
+                    }
+                }
+                if part.is_empty() {
+                    bail!("Empty quoted identifier in {}", s);
+                }
+            } else {
+                while let Some(&c) = chars.peek() {
+                    let valid = if part.is_empty() {
+                        c.is_alphabetic() || c == '_'
+                    } else {
+                        c.is_alphanumeric() || c == '_' || c == '$'
+                    };
+                    if !valid {
+                        break;
+                    }
+                    part.push(c.to_ascii_lowercase());
+                    chars.next();
+                }
+                if part.is_empty() {
+                    bail!("Invalid identifier: {}", s);
+                }
+            }
+            parts.push(part);
+
+            match chars.next() {
+                None => break,
+                Some('.') => continue,
+                Some(_) => bail!("Invalid identifier: {}", s),
+            }
+        }
+
+        let name = parts.pop().ok_or_else(|| anyhow!("Invalid identifier: {}", s))?;
+        match parts.len() {
+            0 => Ok(Self::new(None, name)),
+            1 => Ok(Self::new(parts.pop(), name)),
+            _ => bail!("Too many name parts in {}", s),
+        }
+    }
+}
+
+impl fmt::Display for QualifiedName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(schema) = &self.schema {
+            write!(f, "{}.", quote_ident(schema))?;
+        }
+        write!(f, "{}", quote_ident(&self.name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_ident() {
+        assert_eq!(quote_ident("users"), "\"users\"");
+        assert_eq!(quote_ident("we\"ird"), "\"we\"\"ird\"");
+    }
+
+    #[test]
+    fn test_sql_ident() {
+        assert_eq!(sql_ident("users"), "users");
+        assert_eq!(sql_ident("Users"), "\"Users\"");
+        assert_eq!(sql_ident("order"), "\"order\"");
+        assert_eq!(sql_ident("1st"), "\"1st\"");
+    }
+
+    #[test]
+    fn test_quote_literal() {
+        assert_eq!(quote_literal("slot"), "'slot'");
+        assert_eq!(quote_literal("x'); DROP"), "'x''); DROP'");
+        assert_eq!(quote_literal("a\\b"), "E'a\\\\b'");
+    }
+
+    #[test]
+    fn test_parse_qualified_name() {
+        let name: QualifiedName = "Public.Users".parse().unwrap();
+        assert_eq!(name, QualifiedName::new(Some("public".to_string()), "users".to_string()));
+
+        let name: QualifiedName = "\"Sales\".\"Order \"\"Items\"\"\"".parse().unwrap();
+        assert_eq!(name.schema.as_deref(), Some("Sales"));
+        assert_eq!(name.name, "Order \"Items\"");
+        assert_eq!(name.to_string(), "\"Sales\".\"Order \"\"Items\"\"\"");
+    }
+
+    #[test]
+    fn test_rejects_injection() {
+        for invalid in ["users; DROP TABLE x", "x')", "a.b.c", "", "\"open", "1abc", "a..b"] {
+            assert!(invalid.parse::<QualifiedName>().is_err(), "{}", invalid);
+        }
+    }
+}
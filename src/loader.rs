@@ -0,0 +1,572 @@
+use crate::ddl;
+use crate::ident::sql_ident;
+use crate::migration::{MigrationGenerator, MigrationWriter, PgrollMigration};
+use crate::model::SchemaModel;
+use crate::schema::{ChangeType, ColumnInfo, ConstraintInfo, SchemaChange, SchemaChangeParser};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::PathBuf;
+use tracing::warn;
+
+/// A pgroll operation, as found in migration files
+///
+/// Only the fields that shape the schema are read; `up` and `down`
+/// expressions used while pgroll backfills are ignored.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    CreateTable {
+        name: String,
+        columns: Vec<Column>,
+    },
+    DropTable {
+        name: String,
+    },
+    AddColumn {
+        table: String,
+        column: Column,
+    },
+    DropColumn {
+        table: String,
+        column: String,
+    },
+    CreateConstraint {
+        table: String,
+        name: String,
+        #[serde(rename = "type")]
+        constraint_type: String,
+        #[serde(default)]
+        columns: Vec<String>,
+        check: Option<String>,
+        references: Option<ConstraintReferences>,
+    },
+    DropMulticolumnConstraint {
+        table: String,
+        name: String,
+    },
+    DropConstraint {
+        table: String,
+        name: String,
+    },
+    AlterColumn {
+        table: String,
+        column: String,
+        /// New name of the column
+        name: Option<String>,
+        #[serde(rename = "type")]
+        data_type: Option<String>,
+        nullable: Option<bool>,
+        default: Option<String>,
+        unique: Option<UniqueConstraint>,
+        check: Option<CheckConstraint>,
+        references: Option<ColumnReferences>,
+    },
+    RenameTable {
+        from: String,
+        to: String,
+    },
+    RenameColumn {
+        table: String,
+        from: String,
+        to: String,
+    },
+    RenameConstraint {
+        table: String,
+        from: String,
+        to: String,
+    },
+    CreateIndex {
+        name: String,
+        table: String,
+        columns: Vec<String>,
+        #[serde(default)]
+        unique: bool,
+        method: Option<String>,
+        predicate: Option<String>,
+    },
+    DropIndex {
+        name: String,
+    },
+    Sql {
+        up: String,
+    },
+}
+
+/// Column of `create_table` and `add_column`; pgroll columns are NOT NULL
+/// unless `nullable` is set
+#[derive(Debug, Clone, Deserialize)]
+pub struct Column {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub data_type: String,
+    #[serde(default)]
+    pub nullable: bool,
+    #[serde(default)]
+    pub pk: bool,
+    #[serde(default)]
+    pub unique: bool,
+    pub default: Option<String>,
+    pub check: Option<CheckConstraint>,
+    pub references: Option<ColumnReferences>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CheckConstraint {
+    pub name: String,
+    pub constraint: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UniqueConstraint {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ColumnReferences {
+    pub name: String,
+    pub table: String,
+    pub column: String,
+    pub on_delete: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConstraintReferences {
+    pub table: String,
+    pub columns: Vec<String>,
+    pub on_delete: Option<String>,
+}
+
+#[allow(dead_code)]
+impl Operation {
+    /// The schema changes the operation makes, with their columns and
+    /// constraints attached
+    pub fn changes(&self) -> Vec<SchemaChange> {
+        match self {
+            Operation::CreateTable { name, columns } => {
+                let table = ddl::split_qualified_name(name).1;
+                let constraints = table_constraints(&table, columns);
+                let columns: Vec<ColumnInfo> = columns.iter().map(column_info).collect();
+                let elements: Vec<String> = columns
+                    .iter()
+                    .map(MigrationGenerator::column_sql)
+                    .chain(constraints.iter().map(MigrationGenerator::constraint_sql))
+                    .collect();
+                let sql = format!("CREATE TABLE {} ({})", name, elements.join(", "));
+                vec![change(ChangeType::CreateTable, name, sql)
+                    .with_columns(columns)
+                    .with_constraints(constraints)]
+            }
+            Operation::DropTable { name } => {
+                vec![change(
+                    ChangeType::DropTable,
+                    name,
+                    format!("DROP TABLE {}", name),
+                )]
+            }
+            Operation::AddColumn { table, column } => {
+                let constraints = table_constraints(
+                    &ddl::split_qualified_name(table).1,
+                    std::slice::from_ref(column),
+                );
+                let column = column_info(column);
+                let sql = format!(
+                    "ALTER TABLE {} ADD COLUMN {}",
+                    table,
+                    MigrationGenerator::column_sql(&column)
+                );
+                vec![change(ChangeType::AddColumn, table, sql)
+                    .with_columns(vec![column])
+                    .with_constraints(constraints)]
+            }
+            Operation::DropColumn { table, column } => {
+                let sql = format!("ALTER TABLE {} DROP COLUMN {}", table, sql_ident(column));
+                vec![change(ChangeType::DropColumn, table, sql)]
+            }
+            Operation::CreateConstraint {
+                table,
+                name,
+                constraint_type,
+                columns,
+                check,
+                references,
+            } => {
+                let columns_sql = identifier_list(columns);
+                let definition = match (constraint_type.as_str(), check, references) {
+                    ("check", Some(check), _) => format!("CHECK ({})", check),
+                    ("foreign_key", _, Some(references)) => format!(
+                        "FOREIGN KEY ({}) {}",
+                        columns_sql,
+                        references_sql(
+                            &references.table,
+                            &references.columns,
+                            references.on_delete.as_deref()
+                        )
+                    ),
+                    ("primary_key", _, _) => format!("PRIMARY KEY ({})", columns_sql),
+                    _ => format!("UNIQUE ({})", columns_sql),
+                };
+                let constraint = ConstraintInfo {
+                    name: name.clone(),
+                    constraint_type: constraint_type.clone(),
+                    definition,
+                    columns: columns.clone(),
+                };
+                let sql = format!(
+                    "ALTER TABLE {} ADD {}",
+                    table,
+                    MigrationGenerator::constraint_sql(&constraint)
+                );
+                vec![change(ChangeType::AddConstraint, table, sql)
+                    .with_constraints(vec![constraint])]
+            }
+            Operation::DropMulticolumnConstraint { table, name }
+            | Operation::DropConstraint { table, name } => {
+                let sql = format!("ALTER TABLE {} DROP CONSTRAINT {}", table, sql_ident(name));
+                vec![change(ChangeType::DropConstraint, table, sql)]
+            }
+            Operation::AlterColumn {
+                table,
+                column,
+                name,
+                data_type,
+                nullable,
+                default,
+                unique,
+                check,
+                references,
+            } => {
+                let target = sql_ident(column);
+                let mut clauses = Vec::new();
+                if let Some(data_type) = data_type {
+                    clauses.push(format!("ALTER COLUMN {} TYPE {}", target, data_type));
+                }
+                match nullable {
+                    Some(true) => clauses.push(format!("ALTER COLUMN {} DROP NOT NULL", target)),
+                    Some(false) => clauses.push(format!("ALTER COLUMN {} SET NOT NULL", target)),
+                    None => {}
+                }
+                if let Some(default) = default {
+                    clauses.push(format!("ALTER COLUMN {} SET DEFAULT {}", target, default));
+                }
+
+                let mut changes = Vec::new();
+                if !clauses.is_empty() {
+                    let sql = format!("ALTER TABLE {} {}", table, clauses.join(", "));
+                    changes.push(change(ChangeType::ModifyColumn, table, sql));
+                }
+                // Constraints are added like those of a new column
+                let added = Column {
+                    name: column.clone(),
+                    data_type: String::new(),
+                    nullable: true,
+                    pk: false,
+                    unique: false,
+                    default: None,
+                    check: check.clone(),
+                    references: references.clone(),
+                };
+                let mut constraints =
+                    table_constraints(&ddl::split_qualified_name(table).1, &[added]);
+                if let Some(unique) = unique {
+                    constraints.push(ConstraintInfo {
+                        name: unique.name.clone(),
+                        constraint_type: "unique".to_string(),
+                        definition: format!("UNIQUE ({})", target),
+                        columns: vec![column.clone()],
+                    });
+                }
+                for constraint in constraints {
+                    let sql = format!(
+                        "ALTER TABLE {} ADD {}",
+                        table,
+                        MigrationGenerator::constraint_sql(&constraint)
+                    );
+                    changes.push(
+                        change(ChangeType::AddConstraint, table, sql)
+                            .with_constraints(vec![constraint]),
+                    );
+                }
+                if let Some(name) = name.as_ref().filter(|name| *name != column) {
+                    let sql = format!(
+                        "ALTER TABLE {} RENAME COLUMN {} TO {}",
+                        table,
+                        target,
+                        sql_ident(name)
+                    );
+                    changes.push(change(ChangeType::AlterTable, table, sql));
+                }
+                changes
+            }
+            Operation::RenameTable { from, to } => {
+                let to = ddl::split_qualified_name(to).1;
+                let sql = format!("ALTER TABLE {} RENAME TO {}", from, sql_ident(&to));
+                vec![change(ChangeType::AlterTable, from, sql)]
+            }
+            Operation::RenameColumn { table, from, to } => {
+                let sql = format!(
+                    "ALTER TABLE {} RENAME COLUMN {} TO {}",
+                    table,
+                    sql_ident(from),
+                    sql_ident(to)
+                );
+                vec![change(ChangeType::AlterTable, table, sql)]
+            }
+            Operation::RenameConstraint { table, from, to } => {
+                let sql = format!(
+                    "ALTER TABLE {} RENAME CONSTRAINT {} TO {}",
+                    table,
+                    sql_ident(from),
+                    sql_ident(to)
+                );
+                vec![change(ChangeType::AlterTable, table, sql)]
+            }
+            Operation::CreateIndex {
+                name,
+                table,
+                columns,
+                unique,
+                method,
+                predicate,
+            } => {
+                let mut sql = format!(
+                    "CREATE {}INDEX {} ON {}",
+                    if *unique { "UNIQUE " } else { "" },
+                    sql_ident(name),
+                    table
+                );
+                if let Some(method) = method {
+                    sql.push_str(&format!(" USING {}", method));
+                }
+                sql.push_str(&format!(" ({})", identifier_list(columns)));
+                if let Some(predicate) = predicate {
+                    sql.push_str(&format!(" WHERE {}", predicate));
+                }
+                // An index lives in the schema of its table
+                let (schema, _) = ddl::split_qualified_name(table);
+                vec![SchemaChange::new(
+                    ChangeType::CreateIndex,
+                    schema.unwrap_or_else(|| "public".to_string()),
+                    name.clone(),
+                    sql,
+                )]
+            }
+            Operation::DropIndex { name } => {
+                vec![change(
+                    ChangeType::DropIndex,
+                    name,
+                    format!("DROP INDEX {}", name),
+                )]
+            }
+            Operation::Sql { up } => SchemaChangeParser::parse_script(up, "public"),
+        }
+    }
+}
+
+/// Schema built by replaying every migration of `dir` in order
+pub fn load(dir: &PathBuf) -> Result<SchemaModel> {
+    let mut model = SchemaModel::default();
+    for path in MigrationWriter::list_migrations(dir)? {
+        let migration = PgrollMigration::read(&path)
+            .with_context(|| format!("Failed to read migration {}", path.display()))?;
+        replay(&mut model, &migration);
+    }
+    model.normalize();
+    Ok(model)
+}
+
+/// Apply the operations of `migration` to `model`, skipping the ones it
+/// cannot replay
+pub fn replay(model: &mut SchemaModel, migration: &PgrollMigration) {
+    for operation in &migration.operations {
+        match serde_json::from_value::<Operation>(operation.clone()) {
+            Ok(operation) => {
+                for change in operation.changes() {
+                    // Raw SQL is only understood as far as the DDL parser goes
+                    if !SchemaModel::tracks(&change) {
+                        warn!(
+                            "Statement of {} left out of the schema model: {}",
+                            migration.name, change.details.sql
+                        );
+                    }
+                    model.apply(change);
+                }
+            }
+            Err(err) => {
+                let kind = operation
+                    .as_object()
+                    .and_then(|operation| operation.keys().next().cloned())
+                    .unwrap_or_default();
+                warn!("Skipping {} operation of {}: {}", kind, migration.name, err);
+            }
+        }
+    }
+}
+
+fn change(change_type: ChangeType, object_name: &str, sql: String) -> SchemaChange {
+    let (schema, _) = ddl::split_qualified_name(object_name);
+    SchemaChange::new(
+        change_type,
+        schema.unwrap_or_else(|| "public".to_string()),
+        object_name.to_string(),
+        sql,
+    )
+}
+
+fn column_info(column: &Column) -> ColumnInfo {
+    ColumnInfo {
+        name: column.name.clone(),
+        data_type: column.data_type.clone(),
+        nullable: column.nullable && !column.pk,
+        default: column.default.clone(),
+        constraints: Vec::new(),
+    }
+}
+
+/// Constraints of pgroll columns, named as PostgreSQL names them by default
+/// when pgroll gives no name
+fn table_constraints(table: &str, columns: &[Column]) -> Vec<ConstraintInfo> {
+    let mut constraints = Vec::new();
+    let primary_key: Vec<String> = columns
+        .iter()
+        .filter(|column| column.pk)
+        .map(|column| column.name.clone())
+        .collect();
+    if !primary_key.is_empty() {
+        constraints.push(ConstraintInfo {
+            name: format!("{}_pkey", table),
+            constraint_type: "primary_key".to_string(),
+            definition: format!("PRIMARY KEY ({})", identifier_list(&primary_key)),
+            columns: primary_key,
+        });
+    }
+
+    for column in columns {
+        let name = sql_ident(&column.name);
+        if column.unique {
+            constraints.push(ConstraintInfo {
+                name: format!("{}_{}_key", table, column.name),
+                constraint_type: "unique".to_string(),
+                definition: format!("UNIQUE ({})", name),
+                columns: vec![column.name.clone()],
+            });
+        }
+        if let Some(check) = &column.check {
+            constraints.push(ConstraintInfo {
+                name: check.name.clone(),
+                constraint_type: "check".to_string(),
+                definition: format!("CHECK ({})", check.constraint),
+                columns: vec![column.name.clone()],
+            });
+        }
+        if let Some(references) = &column.references {
+            constraints.push(ConstraintInfo {
+                name: references.name.clone(),
+                constraint_type: "foreign_key".to_string(),
+                definition: format!(
+                    "FOREIGN KEY ({}) {}",
+                    name,
+                    references_sql(
+                        &references.table,
+                        std::slice::from_ref(&references.column),
+                        references.on_delete.as_deref()
+                    )
+                ),
+                columns: vec![column.name.clone()],
+            });
+        }
+    }
+    constraints
+}
+
+fn references_sql(table: &str, columns: &[String], on_delete: Option<&str>) -> String {
+    let mut sql = format!("REFERENCES {}({})", table, identifier_list(columns));
+    if let Some(on_delete) = on_delete {
+        sql.push_str(&format!(" ON DELETE {}", on_delete));
+    }
+    sql
+}
+
+fn identifier_list(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| sql_ident(name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_replays_operations_into_the_model() {
+        let mut migration = PgrollMigration::new("0001_users".to_string());
+        migration.add_operation(json!({
+            "create_table": {
+                "name": "users",
+                "columns": [
+                    {"name": "id", "type": "integer", "pk": true},
+                    {"name": "email", "type": "text", "nullable": true, "unique": true},
+                    {"name": "team_id", "type": "integer", "nullable": true,
+                     "references": {"name": "users_team_fk", "table": "teams", "column": "id"}}
+                ]
+            }
+        }));
+        migration.add_operation(json!({"drop_column": {"table": "users", "column": "team_id"}}));
+        migration.add_operation(json!({"sql": {"up": "CREATE INDEX idx_email ON users (email)"}}));
+        migration.add_operation(json!({"set_replica_identity": {"table": "users"}}));
+
+        let mut model = SchemaModel::default();
+        replay(&mut model, &migration);
+        let users = model.table("public", "users").unwrap();
+        assert_eq!(users.columns.len(), 2);
+        assert!(!users.columns[0].nullable);
+        let names: Vec<&str> = users.constraints.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["users_pkey", "users_email_key"]);
+        assert_eq!(users.indexes[0].name, "idx_email");
+    }
+    #[test]
+    fn test_loads_hand_written_yaml_migrations() {
+        let dir = std::env::temp_dir().join(format!("repligrate-loader-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("01_accounts.yaml"),
+            "operations:
+  - create_table:
+      name: accounts
+      columns:
+        - {name: id, type: bigint, pk: true}
+        - {name: login, type: text}
+  - create_index: {name: idx_login, table: accounts, columns: [login]}
+  - alter_column: {table: accounts, column: login, type: varchar(64), nullable: true, name: username}
+",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("02_rename.yaml"),
+            "operations:
+  - rename_table: {from: accounts, to: users}
+  - rename_constraint: {table: users, from: accounts_pkey, to: users_pkey}
+  - drop_index: {name: idx_login}
+  - sql: {up: CREATE VIEW active AS SELECT 1}
+  - sql: {up: \"CREATE TYPE mood AS ENUM ('sad', 'ok'); ALTER TYPE mood ADD VALUE 'meh' AFTER 'sad'\"}
+",
+        )
+        .unwrap();
+
+        let model = load(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(model.table("public", "accounts").is_none());
+        let users = model.table("public", "users").unwrap();
+        assert_eq!(users.columns[1].name, "username");
+        assert_eq!(users.columns[1].data_type, "varchar(64)");
+        assert!(users.columns[1].nullable);
+        assert_eq!(users.constraints[0].name, "users_pkey");
+        assert!(users.indexes.is_empty());
+        assert_eq!(
+            model.schemas["public"].types["mood"].labels,
+            ["sad", "meh", "ok"]
+        );
+    }
+}
@@ -1,20 +1,43 @@
+mod admin;
+mod baseline;
+mod capture;
+mod catalog;
+mod cleanup;
 mod cli;
 mod config;
+mod control;
 mod db;
+mod ddl;
+mod diff;
+mod drift;
+mod filter;
+mod ident;
+mod loader;
+mod metrics;
 mod migration;
+mod model;
+mod naming;
 mod optimizer;
+mod pgroll;
 mod replication;
 mod schema;
+mod signals;
+mod sink;
+mod slot;
+mod snapshot;
 mod state;
+mod status;
 mod wal;
 
 use anyhow::Result;
 use clap::Parser;
+use std::process::ExitCode;
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    // Initialize tracing
+async fn main() -> Result<ExitCode> {
+    // Initialize tracing; logs go to stderr so command output can be piped
     tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
         .with_env_filter(
             tracing_subscriber::EnvFilter::from_default_env()
                 .add_directive(tracing::Level::INFO.into()),
@@ -0,0 +1,180 @@
+use crate::state::ListenerState;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Content type of the Prometheus text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Counters and gauges of the replication pipeline, shared by every clone
+#[derive(Clone, Default)]
+pub struct ListenerMetrics {
+    messages_received: Arc<AtomicU64>,
+    ddl_captured: Arc<AtomicU64>,
+    migrations_written: Arc<AtomicU64>,
+    received_lsn: Arc<AtomicU64>,
+    flushed_lsn: Arc<AtomicU64>,
+    lag_bytes: Arc<AtomicU64>,
+    /// Milliseconds since the Unix epoch, 0 until a message arrives
+    last_message_at: Arc<AtomicU64>,
+}
+
+#[allow(dead_code)]
+impl ListenerMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a message read from the slot
+    pub fn record_message(&self) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.last_message_at.store(now_millis(), Ordering::Relaxed);
+    }
+
+    /// Count a DDL statement decoded from a capture message
+    pub fn record_ddl(&self) {
+        self.ddl_captured.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a migration written by the output backend
+    pub fn record_migration(&self) {
+        self.migrations_written.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_received_lsn(&self, lsn: u64) {
+        self.received_lsn.store(lsn, Ordering::Relaxed);
+    }
+
+    pub fn set_flushed_lsn(&self, lsn: u64) {
+        self.flushed_lsn.store(lsn, Ordering::Relaxed);
+    }
+
+    /// WAL the server has written past the flushed position
+    pub fn set_lag_bytes(&self, bytes: u64) {
+        self.lag_bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    pub fn messages_received(&self) -> u64 {
+        self.messages_received.load(Ordering::Relaxed)
+    }
+
+    pub fn ddl_captured(&self) -> u64 {
+        self.ddl_captured.load(Ordering::Relaxed)
+    }
+
+    pub fn migrations_written(&self) -> u64 {
+        self.migrations_written.load(Ordering::Relaxed)
+    }
+
+    /// Seconds since the last message, if one was received
+    pub fn seconds_since_last_message(&self) -> Option<f64> {
+        match self.last_message_at.load(Ordering::Relaxed) {
+            0 => None,
+            at => Some(now_millis().saturating_sub(at) as f64 / 1000.0),
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Render the listener's metrics in the Prometheus text format
+pub fn render(state: &ListenerState) -> String {
+    let metrics = state.metrics();
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        let _ = writeln!(out, "{} {}", name, value);
+    };
+
+    metric(
+        "repligrate_messages_received_total",
+        "counter",
+        "Logical messages read from the replication slot.",
+        metrics.messages_received().to_string(),
+    );
+    metric(
+        "repligrate_ddl_captured_total",
+        "counter",
+        "DDL statements decoded from captured messages.",
+        metrics.ddl_captured().to_string(),
+    );
+    metric(
+        "repligrate_migrations_written_total",
+        "counter",
+        "Migrations written by the output backend.",
+        metrics.migrations_written().to_string(),
+    );
+    metric(
+        "repligrate_received_lsn",
+        "gauge",
+        "Commit LSN of the last transaction read from the slot.",
+        metrics.received_lsn.load(Ordering::Relaxed).to_string(),
+    );
+    metric(
+        "repligrate_flushed_lsn",
+        "gauge",
+        "LSN the replication slot has been advanced to.",
+        metrics.flushed_lsn.load(Ordering::Relaxed).to_string(),
+    );
+    metric(
+        "repligrate_replication_lag_bytes",
+        "gauge",
+        "WAL written by the server past the flushed LSN.",
+        metrics.lag_bytes.load(Ordering::Relaxed).to_string(),
+    );
+    if let Some(seconds) = metrics.seconds_since_last_message() {
+        metric(
+            "repligrate_seconds_since_last_message",
+            "gauge",
+            "Seconds since the last message was read from the slot.",
+            format!("{:.3}", seconds),
+        );
+    }
+    metric(
+        "repligrate_pending_changes",
+        "gauge",
+        "Captured changes waiting to be written.",
+        state.pending().to_string(),
+    );
+    metric(
+        "repligrate_paused",
+        "gauge",
+        "Whether the listener is paused.",
+        (state.is_paused() as u8).to_string(),
+    );
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let state = ListenerState::new();
+        let text = render(&state);
+        assert!(text.contains("# TYPE repligrate_messages_received_total counter\nrepligrate_messages_received_total 0\n"));
+        assert!(!text.contains("repligrate_seconds_since_last_message"));
+
+        let metrics = state.metrics();
+        metrics.record_message();
+        metrics.record_ddl();
+        metrics.set_flushed_lsn(0x19D1D70);
+        state.pause();
+
+        let text = render(&state);
+        assert!(text.contains("repligrate_messages_received_total 1\n"));
+        assert!(text.contains("repligrate_ddl_captured_total 1\n"));
+        assert!(text.contains("repligrate_flushed_lsn 27073904\n"));
+        assert!(text.contains("repligrate_seconds_since_last_message "));
+        assert!(text.contains("repligrate_paused 1\n"));
+    }
+}
@@ -1,14 +1,65 @@
-use crate::schema::{ ChangeType, SchemaChange };
+use crate::baseline;
+use crate::config::Config;
+use crate::ddl;
+use crate::ident;
+use crate::model;
+use crate::naming;
+use crate::pgroll::{ PgrollOperation, PgrollVersion };
+use crate::schema::{ ChangeType, ColumnInfo, ConstraintInfo, SchemaChange };
+use crate::sink::{ self, MigrationSink };
 use anyhow::Result;
 use serde::{ Deserialize, Serialize };
 use serde_json::{ json, Value };
-use std::path::PathBuf;
+use std::path::{ Path, PathBuf };
 use tracing::{ debug, info };
 
+/// File format generated migrations are written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Json,
+    Yaml,
+}
+
+impl OutputFormat {
+    /// File extension used for migrations in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Json => "json",
+            OutputFormat::Yaml => "yaml",
+        }
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(OutputFormat::Json),
+            "yaml" | "yml" => Ok(OutputFormat::Yaml),
+            _ => Err(anyhow::anyhow!("Unknown output format: {}", s)),
+        }
+    }
+}
+
+/// Extension of the file recording the pgroll version of a JSON migration
+pub const VERSION_EXTENSION: &str = "pgroll_version";
+
+/// Comment opening YAML migrations with the pgroll version they target
+const VERSION_COMMENT: &str = "# pgroll_version: ";
+
 /// Represents a pgroll migration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PgrollMigration {
+    /// Taken from the file name when a hand-written migration has none
+    #[serde(default)]
     pub name: String,
+    /// pgroll release the operations were generated for; pgroll refuses
+    /// unknown fields, so it is kept out of the document
+    #[serde(skip)]
+    pub pgroll_version: Option<PgrollVersion>,
     pub operations: Vec<Value>,
 }
 
@@ -16,6 +67,7 @@ impl PgrollMigration {
     pub fn new(name: String) -> Self {
         Self {
             name,
+            pgroll_version: None,
             operations: Vec::new(),
         }
     }
@@ -27,141 +79,535 @@ impl PgrollMigration {
     pub fn to_json(&self) -> Result<String> {
         Ok(serde_json::to_string_pretty(&self)?)
     }
+
+    pub fn to_yaml(&self) -> Result<String> {
+        Ok(serde_yaml::to_string(&self)?)
+    }
+
+    /// Serialize the migration in the given output format, YAML starting
+    /// with a comment naming the pgroll version
+    pub fn render(&self, format: OutputFormat) -> Result<String> {
+        match (format, self.pgroll_version) {
+            (OutputFormat::Json, _) => self.to_json(),
+            (OutputFormat::Yaml, Some(version)) => {
+                Ok(format!("{}{}\n{}", VERSION_COMMENT, version, self.to_yaml()?))
+            }
+            (OutputFormat::Yaml, None) => self.to_yaml(),
+        }
+    }
+
+    /// Read a migration file, as JSON or YAML depending on its extension
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let mut migration: Self = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => serde_yaml::from_str(&content)?,
+            _ => serde_json::from_str(&content)?,
+        };
+        if migration.name.is_empty() {
+            if let Some(stem) = path.file_stem() {
+                migration.name = stem.to_string_lossy().into_owned();
+            }
+        }
+        // JSON has no comments, so its version sits in a file next to it
+        let version = match content.lines().next().and_then(|line| line.strip_prefix(VERSION_COMMENT)) {
+            Some(version) => Some(version.to_string()),
+            None => std::fs::read_to_string(path.with_extension(VERSION_EXTENSION)).ok(),
+        };
+        migration.pgroll_version = version.and_then(|version| version.trim().parse().ok());
+        Ok(migration)
+    }
 }
 
 /// Converter from schema changes to pgroll migrations
 pub struct MigrationGenerator;
 
 impl MigrationGenerator {
-    /// Generate a pgroll migration from schema changes
-    pub fn generate(migration_name: String, changes: Vec<SchemaChange>) -> Result<PgrollMigration> {
+    /// Generate a pgroll migration from schema changes, using only the
+    /// operations available in the targeted pgroll release
+    pub fn generate(
+        migration_name: String,
+        changes: Vec<SchemaChange>,
+        version: PgrollVersion
+    ) -> Result<PgrollMigration> {
         let mut migration = PgrollMigration::new(migration_name);
+        migration.pgroll_version = Some(version);
 
         for change in changes {
-            let operation = Self::schema_change_to_operation(&change)?;
-            migration.add_operation(operation);
+            for operation in Self::schema_change_to_operations(&change, version)? {
+                migration.add_operation(operation);
+            }
         }
 
         Ok(migration)
     }
 
-    /// Convert a single schema change to a pgroll operation
-    fn schema_change_to_operation(change: &SchemaChange) -> Result<Value> {
-        match change.change_type {
-            ChangeType::CreateTable => Self::create_table_operation(change),
-            ChangeType::DropTable => Self::drop_table_operation(change),
-            ChangeType::AddColumn => Self::add_column_operation(change),
-            ChangeType::DropColumn => Self::drop_column_operation(change),
-            ChangeType::AlterTable => Self::alter_table_operation(change),
-            ChangeType::AddConstraint => Self::add_constraint_operation(change),
-            ChangeType::DropConstraint => Self::drop_constraint_operation(change),
-            ChangeType::CreateIndex => Self::create_index_operation(change),
-            ChangeType::DropIndex => Self::drop_index_operation(change),
+    /// Convert a single schema change to pgroll operations
+    fn schema_change_to_operations(
+        change: &SchemaChange,
+        version: PgrollVersion
+    ) -> Result<Vec<Value>> {
+        let operations = match change.change_type {
+            ChangeType::CreateTable =>
+                Self::native_or_sql(version, PgrollOperation::CreateTable, change, || {
+                    Self::create_table_operation(change)
+                }),
+            ChangeType::DropTable =>
+                Self::native_or_sql(version, PgrollOperation::DropTable, change, || {
+                    Some(vec![Self::drop_table_operation(change)])
+                }),
+            ChangeType::AddColumn =>
+                Self::native_or_sql(version, PgrollOperation::AddColumn, change, || {
+                    Self::add_column_operations(change)
+                }),
+            ChangeType::DropColumn =>
+                Self::native_or_sql(version, PgrollOperation::DropColumn, change, || {
+                    Self::drop_column_operations(change)
+                }),
+            ChangeType::AlterTable => vec![Self::sql_operation(change)],
+            ChangeType::AddConstraint => vec![Self::add_constraint_operation(change, version)],
+            ChangeType::DropConstraint => vec![Self::drop_constraint_operation(change, version)],
+            ChangeType::CreateIndex =>
+                Self::native_or_sql(version, PgrollOperation::CreateIndex, change, || {
+                    Self::create_index_operation(change)
+                }),
+            ChangeType::DropIndex => vec![Self::drop_index_operation(change, version)],
             _ => {
-                debug!("Unsupported change type: {:?}", change.change_type);
-                Ok(json!({}))
+                debug!("No native operation for {:?}, using sql", change.change_type);
+                vec![Self::sql_operation(change)]
             }
+        };
+
+        Ok(operations)
+    }
+
+    /// Build native operations when the targeted release supports them and
+    /// the statement is understood, otherwise fall back to a `sql` operation
+    /// with both up and down
+    fn native_or_sql(
+        version: PgrollVersion,
+        operation: PgrollOperation,
+        change: &SchemaChange,
+        native: impl FnOnce() -> Option<Vec<Value>>
+    ) -> Vec<Value> {
+        if !version.supports(operation) {
+            debug!("pgroll {} lacks {:?}, falling back to sql", version, operation);
+            return vec![Self::sql_operation(change)];
         }
+        native().unwrap_or_else(|| {
+            debug!("Cannot express as {:?}, using sql: {}", operation, change.details.sql);
+            vec![Self::sql_operation(change)]
+        })
     }
 
-    fn create_table_operation(change: &SchemaChange) -> Result<Value> {
-        Ok(
-            json!({
+    /// A `sql` operation running the captured statement, with its rollback as `down`
+    fn sql_operation(change: &SchemaChange) -> Value {
+        json!({
+            "sql": {
+                "up": change.details.sql.clone(),
+                "down": Self::rollback_sql(change)
+            }
+        })
+    }
+
+    /// SQL undoing a schema change, or a comment when it cannot be derived
+    pub fn rollback_sql(change: &SchemaChange) -> String {
+        match change.change_type {
+            ChangeType::CreateTable => format!("DROP TABLE IF EXISTS {}", change.object_name),
+            ChangeType::CreateIndex => format!("DROP INDEX IF EXISTS {}", Self::index_name(change)),
+            ChangeType::AddColumn => Self::generate_drop_columns_sql(change),
+            ChangeType::AddConstraint => Self::generate_drop_constraint_sql(change),
+            ChangeType::DropTable => Self::generate_recreate_table_sql(change),
+            ChangeType::DropColumn => Self::generate_recreate_columns_sql(change),
+            ChangeType::ModifyColumn => Self::generate_restore_columns_sql(change),
+            ChangeType::DropIndex =>
+                match change.details.metadata.get(model::INDEX_DEFINITION) {
+                    Some(definition) => definition.clone(),
+                    None => format!("-- Recreate index: {}", change.object_name),
+                }
+            ChangeType::DropConstraint => Self::generate_recreate_constraint_sql(change),
+            _ => Self::rollback_comment("Rollback for", &change.details.sql),
+        }
+    }
+
+    /// A rollback that cannot be derived, as a comment quoting the statement;
+    /// every line is commented so a multi-line statement never runs on rollback
+    fn rollback_comment(label: &str, sql: &str) -> String {
+        format!("{}: {}", label, sql.trim())
+            .lines()
+            .map(|line| format!("-- {}", line).trim_end().to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// `create_table` with the table's columns, from the catalog when the
+    /// listener attached them and parsed from the statement otherwise
+    fn create_table_operation(change: &SchemaChange) -> Option<Vec<Value>> {
+        let upper = change.details.sql.to_uppercase();
+        // Inheritance and partitioning have no create_table equivalent
+        if upper.contains(" INHERITS") || upper.contains(" PARTITION ") {
+            return None;
+        }
+
+        let (columns, constraints) = match &change.details.columns {
+            Some(columns) => (columns.clone(), change.details.constraints.clone().unwrap_or_default()),
+            None => ddl::parse_create_table(&change.details.sql)?,
+        };
+        let columns = Self::column_definitions(change, &columns, &constraints)?;
+        if columns.is_empty() {
+            return None;
+        }
+
+        Some(vec![json!({
             "create_table": {
                 "name": change.object_name,
-                "columns": []
+                "columns": columns
             }
-        })
-        )
+        })])
     }
 
-    fn drop_table_operation(change: &SchemaChange) -> Result<Value> {
-        Ok(
-            json!({
+    fn drop_table_operation(change: &SchemaChange) -> Value {
+        json!({
             "drop_table": {
                 "name": change.object_name
             }
         })
-        )
     }
 
-    fn add_column_operation(change: &SchemaChange) -> Result<Value> {
-        Ok(
-            json!({
-            "add_column": {
-                "table": change.object_name,
-                "column": {
-                    "name": "new_column",
-                    "type": "text"
+    /// One `add_column` per added column; a NOT NULL column without a default
+    /// needs an `up` expression, so it is left to the sql operation
+    fn add_column_operations(change: &SchemaChange) -> Option<Vec<Value>> {
+        let sql = &change.details.sql;
+        let parsed = ddl::parse_add_columns(sql);
+        // Other clauses in the statement would be lost
+        if parsed.is_empty() || parsed.len() != ddl::alter_table_clauses(sql).len() {
+            return None;
+        }
+
+        let (columns, constraints) = match &change.details.columns {
+            Some(columns) => (columns.clone(), change.details.constraints.clone().unwrap_or_default()),
+            None => (parsed, Vec::new()),
+        };
+        let definitions = Self::column_definitions(change, &columns, &constraints)?;
+
+        definitions
+            .into_iter()
+            .map(|column| {
+                let has_default = column.get("default").is_some()
+                    || column["type"].as_str().is_some_and(|data_type| data_type.ends_with("serial"));
+                if column["nullable"] == false && !has_default {
+                    return None;
                 }
-            }
-        })
-        )
+                Some(
+                    json!({
+                    "add_column": {
+                        "table": change.object_name,
+                        "column": column
+                    }
+                })
+                )
+            })
+            .collect()
     }
 
-    fn drop_column_operation(change: &SchemaChange) -> Result<Value> {
-        Ok(
-            json!({
-            "drop_column": {
-                "table": change.object_name,
-                "column": "column_name"
-            }
-        })
+    /// One `drop_column` per dropped column
+    fn drop_column_operations(change: &SchemaChange) -> Option<Vec<Value>> {
+        let sql = &change.details.sql;
+        let columns = ddl::dropped_column_names(sql);
+        if columns.is_empty() || columns.len() != ddl::alter_table_clauses(sql).len() {
+            return None;
+        }
+
+        Some(
+            columns
+                .iter()
+                .map(|column| {
+                    json!({
+                    "drop_column": {
+                        "table": change.object_name,
+                        "column": column
+                    }
+                })
+                })
+                .collect()
         )
     }
 
-    fn alter_table_operation(change: &SchemaChange) -> Result<Value> {
-        Ok(
-            json!({
-            "raw_sql": {
-                "up": change.details.sql.clone(),
-                "down": format!("-- Rollback for: {}", change.details.sql)
+    /// pgroll column definitions with the table constraints folded in, or
+    /// `None` when a constraint has no column-level equivalent
+    fn column_definitions(
+        change: &SchemaChange,
+        columns: &[ColumnInfo],
+        constraints: &[ConstraintInfo]
+    ) -> Option<Vec<Value>> {
+        let table = Self::bare_table_name(change);
+        let mut definitions = columns
+            .iter()
+            .map(|column| Self::column_definition(&table, column))
+            .collect::<Option<Vec<_>>>()?;
+
+        for constraint in constraints {
+            let constrained = if constraint.columns.is_empty() {
+                ddl::constraint_columns(&constraint.definition)
+            } else {
+                constraint.columns.clone()
+            };
+
+            if constraint.constraint_type == "primary_key" {
+                for name in &constrained {
+                    let column = definitions.iter_mut().find(|column| column["name"] == name.as_str())?;
+                    column["pk"] = json!(true);
+                    column["nullable"] = json!(false);
+                }
+                continue;
             }
-        })
-        )
+
+            let [name] = constrained.as_slice() else {
+                return None;
+            };
+            let column = definitions.iter_mut().find(|column| column["name"] == name.as_str())?;
+            let constraint_name = |suffix: &str| {
+                if constraint.name.is_empty() {
+                    format!("{}_{}_{}", table, name, suffix)
+                } else {
+                    constraint.name.clone()
+                }
+            };
+            match constraint.constraint_type.as_str() {
+                "unique" => {
+                    column["unique"] = json!(true);
+                }
+                "check" => {
+                    column["check"] = Self::check_definition(
+                        constraint_name("check"),
+                        &constraint.definition
+                    )?;
+                }
+                "foreign_key" => {
+                    column["references"] = Self::references_definition(
+                        constraint_name("fkey"),
+                        &constraint.definition
+                    )?;
+                }
+                _ => {
+                    return None;
+                }
+            }
+        }
+
+        Some(definitions)
     }
 
-    fn create_index_operation(change: &SchemaChange) -> Result<Value> {
-        Ok(
+    /// pgroll column definition, or `None` for a column constraint pgroll
+    /// cannot express; constraint names follow PostgreSQL's defaults
+    fn column_definition(table: &str, column: &ColumnInfo) -> Option<Value> {
+        let mut definition =
             json!({
-            "raw_sql": {
-                "up": change.details.sql.clone(),
-                "down": format!("DROP INDEX IF EXISTS {}", change.object_name)
+            "name": column.name,
+            "type": column.data_type,
+            "nullable": column.nullable
+        });
+        if let Some(default) = &column.default {
+            definition["default"] = json!(default);
+        }
+
+        for constraint in &column.constraints {
+            let upper = constraint.to_uppercase();
+            if upper == "PRIMARY KEY" {
+                definition["pk"] = json!(true);
+            } else if upper == "UNIQUE" {
+                definition["unique"] = json!(true);
+            } else if upper.starts_with("CHECK") {
+                definition["check"] = Self::check_definition(
+                    format!("{}_{}_check", table, column.name),
+                    constraint
+                )?;
+            } else if upper.starts_with("REFERENCES") {
+                definition["references"] = Self::references_definition(
+                    format!("{}_{}_fkey", table, column.name),
+                    constraint
+                )?;
+            } else {
+                return None;
             }
-        })
-        )
+        }
+
+        Some(definition)
+    }
+
+    fn check_definition(name: String, definition: &str) -> Option<Value> {
+        Some(json!({
+            "name": name,
+            "constraint": ddl::check_expression(definition)?
+        }))
     }
 
-    fn drop_index_operation(change: &SchemaChange) -> Result<Value> {
-        Ok(
+    /// Column `references` for a single-column foreign key
+    fn references_definition(name: String, definition: &str) -> Option<Value> {
+        let reference = ddl::foreign_key_reference(definition)?;
+        let [referenced] = reference.columns.as_slice() else {
+            return None;
+        };
+        let mut references =
             json!({
-            "raw_sql": {
-                "up": change.details.sql.clone(),
-                "down": format!("-- Recreate index: {}", change.object_name)
+            "name": name,
+            "table": reference.table,
+            "column": referenced
+        });
+        if let Some(on_delete) = reference.on_delete {
+            references["on_delete"] = json!(on_delete);
+        }
+        Some(references)
+    }
+
+    /// Index name qualified with its schema, which is always the table's
+    fn index_name(change: &SchemaChange) -> String {
+        match ddl::split_qualified_name(&change.object_name) {
+            (None, name) if change.schema_name != "public" && !change.schema_name.is_empty() =>
+                format!("{}.{}", ident::sql_ident(&change.schema_name), ident::sql_ident(&name)),
+            _ => change.object_name.clone(),
+        }
+    }
+
+    /// Table name without its schema, as used in default constraint names
+    fn bare_table_name(change: &SchemaChange) -> String {
+        ddl::split_qualified_name(&change.object_name).1
+    }
+
+    /// Dropping an index is deferred to completion when supported, so the old
+    /// schema version keeps it until the migration is completed
+    /// `create_index` for a plain index on columns; unique, partial and
+    /// expression indexes, and those with options, stay `sql`
+    fn create_index_operation(change: &SchemaChange) -> Option<Vec<Value>> {
+        let upper = change.details.sql.to_uppercase();
+        if [" USING ", " WHERE ", " INCLUDE", " WITH ", " TABLESPACE ", " NULLS "]
+            .iter()
+            .any(|clause| upper.contains(clause))
+        {
+            return None;
+        }
+        let index = ddl::parse_create_index(&change.details.sql)?;
+        if index.unique {
+            return None;
+        }
+
+        Some(vec![json!({
+            "create_index": {
+                "name": index.name,
+                "table": index.table,
+                "columns": index.columns
             }
-        })
-        )
+        })])
     }
 
-    fn add_constraint_operation(change: &SchemaChange) -> Result<Value> {
-        Ok(
+    fn drop_index_operation(change: &SchemaChange, version: PgrollVersion) -> Value {
+        if version.supports(PgrollOperation::SqlOnComplete) {
             json!({
-            "raw_sql": {
-                "up": change.details.sql.clone(),
-                "down": Self::generate_drop_constraint_sql(change)
+                "sql": {
+                    "up": change.details.sql.clone(),
+                    "onComplete": true
+                }
+            })
+        } else {
+            Self::sql_operation(change)
+        }
+    }
+
+    /// Use `create_constraint` for a single UNIQUE, CHECK or FOREIGN KEY constraint
+    fn add_constraint_operation(change: &SchemaChange, version: PgrollVersion) -> Value {
+        let constraints = match &change.details.constraints {
+            Some(constraints) => constraints.clone(),
+            None => ddl::parse_add_constraints(&change.details.sql),
+        };
+        match constraints.as_slice() {
+            [constraint] if version.supports(PgrollOperation::CreateConstraint) => {
+                let columns = if constraint.columns.is_empty() {
+                    ddl::constraint_columns(&constraint.definition)
+                } else {
+                    constraint.columns.clone()
+                };
+                let mut operation =
+                    json!({
+                    "table": change.object_name,
+                    "name": constraint.name,
+                    "type": constraint.constraint_type,
+                    "columns": columns,
+                    "up": Self::identity_column_map(&columns),
+                    "down": Self::identity_column_map(&columns)
+                });
+
+                match constraint.constraint_type.as_str() {
+                    "unique" => {}
+                    "check" => {
+                        operation["check"] = json!(ddl::check_expression(&constraint.definition));
+                    }
+                    "foreign_key" => {
+                        let Some(reference) = ddl::foreign_key_reference(&constraint.definition) else {
+                            return Self::sql_operation(change);
+                        };
+                        let mut references =
+                            json!({
+                            "table": reference.table,
+                            "columns": reference.columns
+                        });
+                        if let Some(on_delete) = reference.on_delete {
+                            references["on_delete"] = json!(on_delete);
+                        }
+                        operation["references"] = references;
+                    }
+                    _ => {
+                        return Self::sql_operation(change);
+                    }
+                }
+
+                json!({ "create_constraint": operation })
             }
-        })
-        )
+            _ => Self::sql_operation(change),
+        }
     }
 
-    fn drop_constraint_operation(change: &SchemaChange) -> Result<Value> {
-        Ok(
-            json!({
-            "raw_sql": {
-                "up": change.details.sql.clone(),
-                "down": format!("-- Recreate constraint: {}", change.object_name)
+    /// Use `drop_multicolumn_constraint` when the dropped constraint's columns are known
+    fn drop_constraint_operation(change: &SchemaChange, version: PgrollVersion) -> Value {
+        // Several dropped constraints are left to a single sql operation
+        let dropped = match change.details.constraints.as_deref() {
+            Some([constraint]) if ddl::alter_table_clauses(&change.details.sql).len() == 1 =>
+                Some(constraint).filter(|constraint| !ddl::constraint_columns(&constraint.definition).is_empty()),
+            Some([]) | None => None,
+            Some(_) => {
+                return Self::sql_operation(change);
             }
-        })
+        };
+
+        match dropped {
+            Some(constraint) if version.supports(PgrollOperation::DropMulticolumnConstraint) => {
+                let columns = ddl::constraint_columns(&constraint.definition);
+                json!({
+                    "drop_multicolumn_constraint": {
+                        "table": change.object_name,
+                        "name": constraint.name,
+                        "up": Self::identity_column_map(&columns),
+                        "down": Self::identity_column_map(&columns)
+                    }
+                })
+            }
+            _ if version.supports(PgrollOperation::SqlOnComplete) && dropped.is_none() => {
+                json!({
+                    "sql": {
+                        "up": change.details.sql.clone(),
+                        "onComplete": true
+                    }
+                })
+            }
+            _ => Self::sql_operation(change),
+        }
+    }
+
+    /// Column map leaving values untouched while pgroll backfills
+    fn identity_column_map(columns: &[String]) -> Value {
+        Value::Object(
+            columns
+                .iter()
+                .map(|column| (column.clone(), json!(column)))
+                .collect()
         )
     }
 
@@ -175,7 +621,116 @@ impl MigrationGenerator {
                 constraint_name
             )
         } else {
-            format!("-- Unable to generate rollback for: {}", sql)
+            Self::rollback_comment("Unable to generate rollback for", sql)
+        }
+    }
+
+    fn generate_drop_columns_sql(change: &SchemaChange) -> String {
+        let columns = ddl::added_column_names(&change.details.sql);
+        if columns.is_empty() {
+            return Self::rollback_comment("Unable to generate rollback for", &change.details.sql);
+        }
+
+        let clauses: Vec<String> = columns
+            .iter()
+            .map(|column| format!("DROP COLUMN IF EXISTS {}", column))
+            .collect();
+        format!("ALTER TABLE {} {}", change.object_name, clauses.join(", "))
+    }
+
+    /// CREATE TABLE for a dropped table, followed by its other indexes
+    fn generate_recreate_table_sql(change: &SchemaChange) -> String {
+        let Some(columns) = change.details.columns.as_ref().filter(|columns| !columns.is_empty()) else {
+            return Self::rollback_comment("Rollback for", &change.details.sql);
+        };
+        let mut elements: Vec<String> = columns.iter().map(Self::column_sql).collect();
+        for constraint in change.details.constraints.iter().flatten() {
+            elements.push(Self::constraint_sql(constraint));
+        }
+
+        let mut sql = format!("CREATE TABLE {} ({})", change.object_name, elements.join(", "));
+        if let Some(indexes) = change.details.metadata.get(model::INDEX_DEFINITIONS) {
+            sql.push_str(";\n");
+            sql.push_str(indexes);
+        }
+        sql
+    }
+
+    /// Add dropped columns back, with the constraints that went with them
+    fn generate_recreate_columns_sql(change: &SchemaChange) -> String {
+        let Some(columns) = change.details.columns.as_ref().filter(|columns| !columns.is_empty()) else {
+            return Self::rollback_comment("Rollback for", &change.details.sql);
+        };
+        let mut clauses: Vec<String> = columns
+            .iter()
+            .map(|column| format!("ADD COLUMN {}", Self::column_sql(column)))
+            .collect();
+        for constraint in change.details.constraints.iter().flatten() {
+            clauses.push(format!("ADD {}", Self::constraint_sql(constraint)));
+        }
+        format!("ALTER TABLE {} {}", change.object_name, clauses.join(", "))
+    }
+
+    /// Put modified columns back to their previous type, nullability and default
+    fn generate_restore_columns_sql(change: &SchemaChange) -> String {
+        let Some(columns) = change.details.columns.as_ref().filter(|columns| !columns.is_empty()) else {
+            return Self::rollback_comment("Rollback for", &change.details.sql);
+        };
+        let clauses: Vec<String> = columns
+            .iter()
+            .flat_map(|column| {
+                let name = &column.name;
+                [
+                    format!("ALTER COLUMN {} TYPE {}", name, column.data_type),
+                    match column.nullable {
+                        true => format!("ALTER COLUMN {} DROP NOT NULL", name),
+                        false => format!("ALTER COLUMN {} SET NOT NULL", name),
+                    },
+                    match &column.default {
+                        Some(default) => format!("ALTER COLUMN {} SET DEFAULT {}", name, default),
+                        None => format!("ALTER COLUMN {} DROP DEFAULT", name),
+                    },
+                ]
+            })
+            .collect();
+        format!("ALTER TABLE {} {}", change.object_name, clauses.join(", "))
+    }
+
+    /// Column definition as written in CREATE TABLE or ADD COLUMN
+    pub fn column_sql(column: &ColumnInfo) -> String {
+        let mut sql = format!("{} {}", ident::sql_ident(&column.name), column.data_type);
+        if !column.nullable {
+            sql.push_str(" NOT NULL");
+        }
+        if let Some(default) = &column.default {
+            sql.push_str(&format!(" DEFAULT {}", default));
+        }
+        for constraint in &column.constraints {
+            sql.push(' ');
+            sql.push_str(constraint);
+        }
+        sql
+    }
+
+    fn generate_recreate_constraint_sql(change: &SchemaChange) -> String {
+        match change.details.constraints.as_ref().filter(|constraints| !constraints.is_empty()) {
+            Some(constraints) => {
+                let clauses: Vec<String> = constraints
+                    .iter()
+                    .map(|constraint| format!("ADD {}", Self::constraint_sql(constraint)))
+                    .collect();
+                format!("ALTER TABLE {} {}", change.object_name, clauses.join(", "))
+            }
+            None => format!("-- Recreate constraint: {}", change.object_name),
+        }
+    }
+
+    /// Table constraint as written in CREATE TABLE or ADD
+    pub fn constraint_sql(constraint: &ConstraintInfo) -> String {
+        if constraint.name.is_empty() {
+            constraint.definition.clone()
+        } else {
+            format!("CONSTRAINT {} {}", ident::sql_ident(&constraint.name), constraint.definition)
         }
     }
 
@@ -206,19 +761,34 @@ pub struct MigrationWriter;
 
 #[allow(dead_code)]
 impl MigrationWriter {
-    /// Write migration to file
-    pub fn write(migration: &PgrollMigration, output_dir: &PathBuf) -> Result<PathBuf> {
+    /// Output backend selected by the configuration
+    pub fn sink(config: &Config) -> Box<dyn MigrationSink> {
+        sink::from_config(config)
+    }
+
+    /// Write migration to `<name>.<extension>`, refusing to overwrite an existing file
+    ///
+    /// The pgroll version of a JSON migration goes to `<name>.pgroll_version`.
+    pub fn write(
+        migration: &PgrollMigration,
+        output_dir: &PathBuf,
+        format: OutputFormat
+    ) -> Result<PathBuf> {
         // Create output directory if it doesn't exist
         std::fs::create_dir_all(output_dir)?;
 
-        // Generate filename with timestamp
-        let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S");
-        let filename = format!("{}_{}.json", timestamp, migration.name);
+        let filename = format!("{}.{}", migration.name, format.extension());
         let filepath = output_dir.join(&filename);
 
         // Write migration to file
-        let json_content = migration.to_json()?;
-        std::fs::write(&filepath, json_content)?;
+        let content = migration.render(format)?;
+        match (format, migration.pgroll_version) {
+            (OutputFormat::Json, Some(version)) => naming::write_all_atomic(&[
+                (filepath.clone(), content),
+                (filepath.with_extension(VERSION_EXTENSION), format!("{}\n", version)),
+            ])?,
+            _ => naming::write_atomic(&filepath, &content)?,
+        }
 
         info!("Migration written to: {}", filepath.display());
         Ok(filepath)
@@ -235,7 +805,11 @@ impl MigrationWriter {
         for entry in std::fs::read_dir(output_dir)? {
             let entry = entry?;
             let path = entry.path();
-            if path.extension().is_some_and(|ext| ext == "json") {
+            // The baseline marker sits next to the migrations
+            if path.file_name().is_some_and(|name| name == baseline::MARKER_FILE) {
+                continue;
+            }
+            if path.extension().is_some_and(|ext| ext == "json" || ext == "yaml" || ext == "yml") {
                 migrations.push(path);
             }
         }
@@ -244,3 +818,299 @@ impl MigrationWriter {
         Ok(migrations)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_unique_constraint() -> SchemaChange {
+        SchemaChange::new(
+            ChangeType::AddConstraint,
+            "public".to_string(),
+            "users".to_string(),
+            "ALTER TABLE users ADD CONSTRAINT uk_email UNIQUE (email)".to_string()
+        )
+    }
+
+    #[test]
+    fn test_create_constraint_when_supported() {
+        let migration = MigrationGenerator::generate(
+            "m".to_string(),
+            vec![add_unique_constraint()],
+            PgrollVersion::new(0, 7, 0)
+        ).unwrap();
+
+        let operation = &migration.operations[0]["create_constraint"];
+        assert_eq!(operation["name"], "uk_email");
+        assert_eq!(operation["type"], "unique");
+        assert_eq!(operation["columns"], json!(["email"]));
+        assert_eq!(operation["up"]["email"], "email");
+    }
+
+    #[test]
+    fn test_sql_fallback_for_older_versions() {
+        let migration = MigrationGenerator::generate(
+            "m".to_string(),
+            vec![add_unique_constraint()],
+            PgrollVersion::new(0, 6, 0)
+        ).unwrap();
+
+        let operation = &migration.operations[0]["sql"];
+        assert!(operation["up"].as_str().unwrap().contains("ADD CONSTRAINT uk_email"));
+        assert_eq!(operation["down"], "ALTER TABLE users DROP CONSTRAINT IF EXISTS uk_email");
+    }
+
+    #[test]
+    fn test_drop_index_on_complete() {
+        let change = SchemaChange::new(
+            ChangeType::DropIndex,
+            "public".to_string(),
+            "idx_users_email".to_string(),
+            "DROP INDEX idx_users_email".to_string()
+        );
+
+        let recent = MigrationGenerator::generate(
+            "m".to_string(),
+            vec![change.clone()],
+            PgrollVersion::new(0, 5, 0)
+        ).unwrap();
+        assert_eq!(recent.operations[0]["sql"]["onComplete"], true);
+
+        let old = MigrationGenerator::generate(
+            "m".to_string(),
+            vec![change],
+            PgrollVersion::new(0, 4, 0)
+        ).unwrap();
+        assert!(old.operations[0]["sql"].get("onComplete").is_none());
+        assert!(old.operations[0]["sql"]["down"].is_string());
+    }
+
+    #[test]
+    fn test_yaml_output() {
+        let migration = MigrationGenerator::generate(
+            "m".to_string(),
+            vec![add_unique_constraint()],
+            PgrollVersion::new(0, 7, 0)
+        ).unwrap();
+
+        let yaml = migration.render(OutputFormat::Yaml).unwrap();
+        assert!(yaml.contains("name: m"));
+        assert!(yaml.contains("create_constraint:"));
+
+        let parsed: PgrollMigration = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(parsed.operations, migration.operations);
+    }
+
+    #[test]
+    fn test_list_migrations_recognises_both_formats() {
+        let dir = std::env::temp_dir().join(format!("repligrate-list-{}", std::process::id()));
+        let migration = PgrollMigration::new("0001_m".to_string());
+        MigrationWriter::write(&migration, &dir, OutputFormat::Json).unwrap();
+        MigrationWriter::write(&migration, &dir, OutputFormat::Yaml).unwrap();
+        assert!(MigrationWriter::write(&migration, &dir, OutputFormat::Json).is_err());
+        std::fs::write(dir.join("notes.txt"), "ignored").unwrap();
+        std::fs::write(dir.join(baseline::MARKER_FILE), "{}").unwrap();
+
+        let migrations = MigrationWriter::list_migrations(&dir).unwrap();
+        let names: Vec<String> = migrations
+            .iter()
+            .map(|path| PgrollMigration::read(path).unwrap().name)
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(names, ["0001_m", "0001_m"]);
+    }
+
+    #[test]
+    fn test_migration_records_version() {
+        let dir = std::env::temp_dir().join(format!("repligrate-version-{}", std::process::id()));
+        let mut migration = MigrationGenerator::generate(
+            "0001_m".to_string(),
+            vec![],
+            PgrollVersion::new(0, 6, 1)
+        ).unwrap();
+        let json = MigrationWriter::write(&migration, &dir, OutputFormat::Json).unwrap();
+        migration.name = "0002_m".to_string();
+        let yaml = MigrationWriter::write(&migration, &dir, OutputFormat::Yaml).unwrap();
+
+        let yaml_content = std::fs::read_to_string(&yaml).unwrap();
+        let document: Value = serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+        let versions: Vec<Option<PgrollVersion>> = [&json, &yaml]
+            .iter()
+            .map(|path| PgrollMigration::read(path).unwrap().pgroll_version)
+            .collect();
+        let migrations = MigrationWriter::list_migrations(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(yaml_content.starts_with("# pgroll_version: 0.6.1\n"));
+        // The documents only hold what pgroll reads
+        let mut keys: Vec<&String> = document.as_object().unwrap().keys().collect();
+        keys.sort();
+        assert_eq!(keys, ["name", "operations"]);
+        assert_eq!(versions, [Some(PgrollVersion::new(0, 6, 1)); 2]);
+        assert_eq!(migrations, [json, yaml]);
+    }
+
+    #[test]
+    fn test_create_index_operation() {
+        let index = |sql: &str| {
+            let change = SchemaChange::new(
+                ChangeType::CreateIndex,
+                "public".to_string(),
+                "idx_users_email".to_string(),
+                sql.to_string()
+            );
+            MigrationGenerator::generate("m".to_string(), vec![change], PgrollVersion::LATEST)
+                .unwrap()
+                .operations.remove(0)
+        };
+
+        assert_eq!(
+            index("CREATE INDEX idx_users_email ON users (email, \"Name\")"),
+            json!({
+                "create_index": {"name": "idx_users_email", "table": "users", "columns": ["email", "Name"]}
+            })
+        );
+        for sql in [
+            "CREATE UNIQUE INDEX idx_users_email ON users (email)",
+            "CREATE INDEX idx_users_email ON users (lower(email))",
+            "CREATE INDEX idx_users_email ON users (email) WHERE active",
+            "CREATE INDEX idx_users_email ON users USING gin (email)",
+            "CREATE INDEX idx_users_email ON users (email DESC)",
+        ] {
+            assert!(index(sql).get("sql").is_some(), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_native_table_and_column_operations() {
+        let changes = vec![
+            SchemaChange::new(
+                ChangeType::CreateTable,
+                "public".to_string(),
+                "orders".to_string(),
+                "CREATE TABLE orders (id serial, user_id int REFERENCES users(id), total numeric CHECK (total > 0), PRIMARY KEY (id))".to_string()
+            ),
+            SchemaChange::new(
+                ChangeType::AddColumn,
+                "public".to_string(),
+                "orders".to_string(),
+                "ALTER TABLE orders ADD COLUMN note text, ADD COLUMN status text NOT NULL DEFAULT 'new'".to_string()
+            )
+        ];
+        let migration = MigrationGenerator::generate(
+            "m".to_string(),
+            changes,
+            PgrollVersion::new(0, 8, 0)
+        ).unwrap();
+
+        let columns = &migration.operations[0]["create_table"]["columns"];
+        assert_eq!(columns[0]["pk"], true);
+        assert_eq!(columns[0]["nullable"], false);
+        assert_eq!(columns[1]["references"]["name"], "orders_user_id_fkey");
+        assert_eq!(columns[1]["references"]["column"], "id");
+        assert_eq!(columns[2]["check"]["constraint"], "total > 0");
+
+        assert_eq!(migration.operations.len(), 3);
+        assert_eq!(migration.operations[1]["add_column"]["column"]["name"], "note");
+        assert_eq!(migration.operations[2]["add_column"]["column"]["default"], "'new'");
+    }
+
+    #[test]
+    fn test_prefers_catalog_columns_and_constraints() {
+        let column = |name: &str, data_type: &str, nullable: bool| ColumnInfo {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            nullable,
+            default: None,
+            constraints: vec![],
+        };
+        let constraint = |name: &str, constraint_type: &str, definition: &str, column: &str| ConstraintInfo {
+            name: name.to_string(),
+            constraint_type: constraint_type.to_string(),
+            definition: definition.to_string(),
+            columns: vec![column.to_string()],
+        };
+        // The statement's `int` and inline check as the catalog reports them
+        let change = SchemaChange::new(
+            ChangeType::CreateTable,
+            "public".to_string(),
+            "orders".to_string(),
+            "CREATE TABLE orders (id serial PRIMARY KEY, total int CHECK (total>0))".to_string()
+        )
+            .with_columns(vec![column("id", "serial", false), column("total", "integer", true)])
+            .with_constraints(
+                vec![
+                    constraint("orders_pkey", "primary_key", "PRIMARY KEY (id)", "id"),
+                    constraint("positive_total", "check", "CHECK ((total > 0))", "total")
+                ]
+            );
+
+        let migration = MigrationGenerator::generate(
+            "m".to_string(),
+            vec![change],
+            PgrollVersion::new(0, 8, 0)
+        ).unwrap();
+
+        let columns = &migration.operations[0]["create_table"]["columns"];
+        assert_eq!(columns[0]["type"], "serial");
+        assert_eq!(columns[0]["pk"], true);
+        assert_eq!(columns[1]["type"], "integer");
+        assert_eq!(columns[1]["check"]["name"], "positive_total");
+        assert_eq!(columns[1]["check"]["constraint"], "(total > 0)");
+    }
+
+    #[test]
+    fn test_rollbacks_from_schema_model() {
+        let mut model = model::SchemaModel::default();
+        let mut apply = |change_type: ChangeType, object_name: &str, sql: &str| {
+            model.apply(
+                SchemaChange::new(change_type, "public".to_string(), object_name.to_string(), sql.to_string())
+            )
+        };
+        apply(
+            ChangeType::CreateTable,
+            "orders",
+            "CREATE TABLE orders (id int, note text DEFAULT '', PRIMARY KEY (id))"
+        );
+        apply(ChangeType::CreateIndex, "idx_note", "CREATE INDEX idx_note ON orders (note)");
+
+        let drop_index = apply(ChangeType::DropIndex, "idx_note", "DROP INDEX idx_note");
+        assert_eq!(
+            MigrationGenerator::rollback_sql(&drop_index),
+            "CREATE INDEX idx_note ON orders (note)"
+        );
+
+        let drop_column = apply(ChangeType::DropColumn, "orders", "ALTER TABLE orders DROP COLUMN note");
+        assert_eq!(
+            MigrationGenerator::rollback_sql(&drop_column),
+            "ALTER TABLE orders ADD COLUMN note text DEFAULT ''"
+        );
+
+        let drop_table = apply(ChangeType::DropTable, "orders", "DROP TABLE orders");
+        assert_eq!(
+            MigrationGenerator::rollback_sql(&drop_table),
+            "CREATE TABLE orders (id int, PRIMARY KEY (id))"
+        );
+    }
+
+    #[test]
+    fn test_sql_fallback_for_unsupported_definitions() {
+        for sql in [
+            "ALTER TABLE orders ADD COLUMN code int NOT NULL",
+            "ALTER TABLE orders ADD COLUMN code int, ALTER COLUMN note SET NOT NULL",
+        ] {
+            let change = SchemaChange::new(
+                ChangeType::AddColumn,
+                "public".to_string(),
+                "orders".to_string(),
+                sql.to_string()
+            );
+            let migration = MigrationGenerator::generate(
+                "m".to_string(),
+                vec![change],
+                PgrollVersion::new(0, 8, 0)
+            ).unwrap();
+            assert_eq!(migration.operations[0]["sql"]["up"], sql);
+        }
+    }
+}
@@ -0,0 +1,598 @@
+use crate::catalog::{self, IndexDefinition};
+use crate::ddl;
+use crate::filter::SchemaFilter;
+use crate::ident::QualifiedName;
+use crate::schema::{ChangeType, ColumnInfo, ConstraintInfo, SchemaChange};
+use anyhow::Result;
+use postgres::GenericClient;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::debug;
+
+/// Filter on `pg_namespace n` leaving out system schemas and repligrate's own
+const USER_SCHEMAS: &str = "n.nspname NOT IN ('pg_catalog', 'information_schema', 'repligrate')
+    AND n.nspname NOT LIKE 'pg\\_toast%' AND n.nspname NOT LIKE 'pg\\_temp%'";
+
+/// Metadata key holding the definitions of the indexes a dropped table had
+pub const INDEX_DEFINITIONS: &str = "index_definitions";
+
+/// Metadata key holding an index definition, as `pg_get_indexdef` prints it
+pub const INDEX_DEFINITION: &str = "index_definition";
+
+/// The database schema as repligrate last saw it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SchemaModel {
+    pub schemas: BTreeMap<String, SchemaDefinition>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SchemaDefinition {
+    #[serde(default)]
+    pub tables: BTreeMap<String, TableDefinition>,
+    #[serde(default)]
+    pub sequences: BTreeMap<String, SequenceDefinition>,
+    #[serde(default)]
+    pub types: BTreeMap<String, TypeDefinition>,
+    #[serde(default)]
+    pub views: BTreeMap<String, ViewDefinition>,
+    /// Functions and procedures by signature, as `name(argument types)`
+    #[serde(default)]
+    pub functions: BTreeMap<String, FunctionDefinition>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TableDefinition {
+    pub columns: Vec<ColumnInfo>,
+    #[serde(default)]
+    pub constraints: Vec<ConstraintInfo>,
+    #[serde(default)]
+    pub indexes: Vec<IndexDefinition>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SequenceDefinition {
+    pub data_type: String,
+    /// `table.column` owning the sequence, for serial and identity columns
+    pub owned_by: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TypeDefinition {
+    /// `enum`, `domain`, `composite` or `range`
+    pub kind: String,
+    /// Labels of an enum, in sort order
+    #[serde(default)]
+    pub labels: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ViewDefinition {
+    #[serde(default)]
+    pub materialized: bool,
+    /// Query from `pg_get_viewdef`
+    pub definition: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionDefinition {
+    /// `function` or `procedure`
+    pub kind: String,
+    /// Result type, `None` for procedures
+    pub returns: Option<String>,
+    pub language: String,
+    /// CREATE statement from `pg_get_functiondef`
+    pub definition: String,
+}
+
+#[allow(dead_code)]
+impl SchemaModel {
+    /// Read every user schema from the catalog
+    pub fn load(client: &mut impl GenericClient) -> Result<Self> {
+        let mut model = Self::default();
+
+        let query = format!("SELECT n.nspname::text FROM pg_namespace n WHERE {}", USER_SCHEMAS);
+        for row in client.query(&query, &[])? {
+            model.schemas.insert(row.get(0), SchemaDefinition::default());
+        }
+
+        let query = format!(
+            "SELECT n.nspname::text, c.relname::text
+             FROM pg_class c
+             JOIN pg_namespace n ON n.oid = c.relnamespace
+             WHERE c.relkind IN ('r', 'p') AND {}
+             ORDER BY 1, 2",
+            USER_SCHEMAS
+        );
+        for row in client.query(&query, &[])? {
+            let (schema, name): (String, String) = (row.get(0), row.get(1));
+            let relation = QualifiedName::new(Some(schema.clone()), name.clone()).to_string();
+            let table = TableDefinition {
+                columns: catalog::columns(client, &relation)?,
+                constraints: catalog::constraints(client, &relation)?,
+                indexes: catalog::indexes(client, &relation)?,
+            };
+            model.schema_mut(&schema).tables.insert(name, table);
+        }
+
+        let query = format!(
+            "SELECT n.nspname::text, c.relname::text, format_type(s.seqtypid, NULL),
+                    (SELECT t.relname || '.' || a.attname
+                     FROM pg_depend d
+                     JOIN pg_class t ON t.oid = d.refobjid
+                     JOIN pg_attribute a ON a.attrelid = d.refobjid AND a.attnum = d.refobjsubid
+                     WHERE d.classid = 'pg_class'::regclass AND d.objid = c.oid
+                       AND d.deptype IN ('a', 'i'))
+             FROM pg_sequence s
+             JOIN pg_class c ON c.oid = s.seqrelid
+             JOIN pg_namespace n ON n.oid = c.relnamespace
+             WHERE {}",
+            USER_SCHEMAS
+        );
+        for row in client.query(&query, &[])? {
+            let schema: String = row.get(0);
+            let sequence = SequenceDefinition {
+                data_type: row.get(2),
+                owned_by: row.get(3),
+            };
+            model.schema_mut(&schema).sequences.insert(row.get(1), sequence);
+        }
+
+        // Composite types of tables and views come with the relation
+        let query = format!(
+            "SELECT n.nspname::text, t.typname::text,
+                    CASE t.typtype WHEN 'e' THEN 'enum' WHEN 'd' THEN 'domain'
+                                   WHEN 'c' THEN 'composite' ELSE 'range' END,
+                    array(SELECT e.enumlabel::text FROM pg_enum e
+                          WHERE e.enumtypid = t.oid ORDER BY e.enumsortorder)
+             FROM pg_type t
+             JOIN pg_namespace n ON n.oid = t.typnamespace
+             LEFT JOIN pg_class c ON c.oid = t.typrelid
+             WHERE t.typtype IN ('e', 'd', 'c', 'r') AND (c.oid IS NULL OR c.relkind = 'c')
+               AND {}",
+            USER_SCHEMAS
+        );
+        for row in client.query(&query, &[])? {
+            let schema: String = row.get(0);
+            let definition = TypeDefinition {
+                kind: row.get(2),
+                labels: row.get(3),
+            };
+            model.schema_mut(&schema).types.insert(row.get(1), definition);
+        }
+
+        let query = format!(
+            "SELECT n.nspname::text, c.relname::text, c.relkind = 'm', pg_get_viewdef(c.oid)
+             FROM pg_class c
+             JOIN pg_namespace n ON n.oid = c.relnamespace
+             WHERE c.relkind IN ('v', 'm') AND {}",
+            USER_SCHEMAS
+        );
+        for row in client.query(&query, &[])? {
+            let schema: String = row.get(0);
+            let view = ViewDefinition {
+                materialized: row.get(2),
+                definition: row.get(3),
+            };
+            model.schema_mut(&schema).views.insert(row.get(1), view);
+        }
+
+        // Functions installed by an extension are left to the extension
+        let query = format!(
+            "SELECT n.nspname::text,
+                    p.proname || '(' || pg_get_function_identity_arguments(p.oid) || ')',
+                    CASE p.prokind WHEN 'p' THEN 'procedure' ELSE 'function' END,
+                    pg_get_function_result(p.oid),
+                    l.lanname::text,
+                    pg_get_functiondef(p.oid)
+             FROM pg_proc p
+             JOIN pg_namespace n ON n.oid = p.pronamespace
+             JOIN pg_language l ON l.oid = p.prolang
+             WHERE p.prokind IN ('f', 'p') AND {}
+               AND NOT EXISTS (SELECT 1 FROM pg_depend d
+                               WHERE d.classid = 'pg_proc'::regclass AND d.objid = p.oid
+                                 AND d.deptype = 'e')",
+            USER_SCHEMAS
+        );
+        for row in client.query(&query, &[])? {
+            let schema: String = row.get(0);
+            let function = FunctionDefinition {
+                kind: row.get(2),
+                returns: row.get(3),
+                language: row.get(4),
+                definition: row.get(5),
+            };
+            model.schema_mut(&schema).functions.insert(row.get(1), function);
+        }
+
+        debug!(
+            "Schema model loaded: {} schemas, {} tables",
+            model.schemas.len(),
+            model.schemas.values().map(|schema| schema.tables.len()).sum::<usize>()
+        );
+        Ok(model)
+    }
+
+    /// Drop the schemas and tables `filter` excludes
+    pub fn retain(&mut self, filter: &SchemaFilter) {
+        self.schemas.retain(|schema, _| filter.schema_rule(schema).is_none());
+        for (schema, definition) in &mut self.schemas {
+            definition.tables.retain(|table, _| filter.table_rule(schema, table).is_none());
+            definition.views.retain(|view, _| filter.table_rule(schema, view).is_none());
+        }
+    }
+
+    /// Sort what the catalog returns in creation order, so that equal schemas
+    /// give equal documents
+    pub fn normalize(&mut self) {
+        for schema in self.schemas.values_mut() {
+            for table in schema.tables.values_mut() {
+                table.constraints.sort_by(|a, b| a.name.cmp(&b.name));
+                table.indexes.sort_by(|a, b| a.name.cmp(&b.name));
+            }
+        }
+    }
+
+    fn schema_mut(&mut self, schema: &str) -> &mut SchemaDefinition {
+        self.schemas.entry(schema.to_string()).or_default()
+    }
+
+    /// Table `name` of `schema`; unquoted names in statements may differ in case
+    pub fn table(&self, schema: &str, name: &str) -> Option<&TableDefinition> {
+        let tables = &self.schemas.get(schema)?.tables;
+        tables.get(name).or_else(|| tables.get(&name.to_lowercase()))
+    }
+
+    fn table_mut(&mut self, schema: &str, name: &str) -> Option<&mut TableDefinition> {
+        let tables = &mut self.schemas.get_mut(schema)?.tables;
+        let key = if tables.contains_key(name) { name.to_string() } else { name.to_lowercase() };
+        tables.get_mut(&key)
+    }
+
+    fn remove_table(&mut self, schema: &str, name: &str) -> Option<TableDefinition> {
+        let tables = &mut self.schemas.get_mut(schema)?.tables;
+        tables.remove(name).or_else(|| tables.remove(&name.to_lowercase()))
+    }
+
+    /// Apply `change` to the model
+    ///
+    /// The change is returned with the definitions it removes or replaces
+    /// attached, which is what its rollback needs: the columns and constraints
+    /// of a dropped table or column, the dropped constraints, the definition of
+    /// a dropped index, and a modified column as it was before.
+    pub fn apply(&mut self, change: SchemaChange) -> SchemaChange {
+        let schema = change.schema_name.clone();
+        let (_, table) = ddl::split_qualified_name(&change.object_name);
+        let sql = change.details.sql.clone();
+
+        match change.change_type {
+            ChangeType::CreateTable => {
+                let (columns, constraints) = match &change.details.columns {
+                    Some(columns) => {
+                        (columns.clone(), change.details.constraints.clone().unwrap_or_default())
+                    }
+                    None => ddl::parse_create_table(&sql).unwrap_or_default(),
+                };
+                let definition = TableDefinition {
+                    columns,
+                    constraints,
+                    indexes: Vec::new(),
+                };
+                self.schema_mut(&schema).tables.insert(table, definition);
+                change
+            }
+            ChangeType::DropTable => match self.remove_table(&schema, &table) {
+                Some(dropped) => {
+                    let indexes: Vec<String> = dropped
+                        .indexes
+                        .iter()
+                        .filter(|index| !dropped.constraints.iter().any(|c| c.name == index.name))
+                        .map(|index| index.definition.clone())
+                        .collect();
+                    let change = change.with_columns(dropped.columns).with_constraints(dropped.constraints);
+                    if indexes.is_empty() {
+                        change
+                    } else {
+                        change.with_metadata(INDEX_DEFINITIONS.to_string(), indexes.join(";\n"))
+                    }
+                }
+                None => Self::unknown(change, &table),
+            },
+            ChangeType::AddColumn => {
+                let Some(definition) = self.table_mut(&schema, &table) else {
+                    return Self::unknown(change, &table);
+                };
+                match &change.details.columns {
+                    Some(columns) => {
+                        definition.columns.extend(columns.iter().cloned());
+                        definition.constraints.extend(change.details.constraints.iter().flatten().cloned());
+                    }
+                    None => definition.columns.extend(ddl::parse_add_columns(&sql)),
+                }
+                change
+            }
+            ChangeType::DropColumn => {
+                let Some(definition) = self.table_mut(&schema, &table) else {
+                    return Self::unknown(change, &table);
+                };
+                let names = ddl::dropped_column_names(&sql);
+                let dropped = |column: &String| names.iter().any(|name| same_name(column, name));
+
+                let (columns, kept) = definition.columns.drain(..).partition(|c| dropped(&c.name));
+                definition.columns = kept;
+                // PostgreSQL drops every constraint and index using a dropped column
+                let (constraints, kept) = definition
+                    .constraints
+                    .drain(..)
+                    .partition(|constraint| constraint.columns.iter().any(dropped));
+                definition.constraints = kept;
+                definition.indexes.retain(|index| {
+                    let columns = ddl::parse_create_index(&index.definition)
+                        .map(|index| index.columns)
+                        .unwrap_or_default();
+                    !columns.iter().any(dropped)
+                });
+
+                change.with_columns(columns).with_constraints(constraints)
+            }
+            ChangeType::ModifyColumn | ChangeType::AlterTable => {
+                if let Some(new) = ddl::parse_rename_table(&sql) {
+                    let Some(definition) = self.remove_table(&schema, &table) else {
+                        return Self::unknown(change, &table);
+                    };
+                    self.schema_mut(&schema).tables.insert(new, definition);
+                    return change;
+                }
+                let Some(definition) = self.table_mut(&schema, &table) else {
+                    return Self::unknown(change, &table);
+                };
+                let mut before = Vec::new();
+                for clause in ddl::alter_table_clauses(&sql) {
+                    if let Some(column) = alter_column(definition, &clause) {
+                        if !before.iter().any(|c: &ColumnInfo| c.name == column.name) {
+                            before.push(column);
+                        }
+                    }
+                }
+                if let Some((old, new)) = ddl::parse_rename_column(&sql) {
+                    rename_column(definition, &old, &new);
+                }
+                if let Some((old, new)) = ddl::parse_rename_constraint(&sql) {
+                    rename_constraint(definition, &old, &new);
+                }
+                if before.is_empty() {
+                    change
+                } else {
+                    change.with_columns(before)
+                }
+            }
+            ChangeType::AddConstraint => {
+                let Some(definition) = self.table_mut(&schema, &table) else {
+                    return Self::unknown(change, &table);
+                };
+                let added = change
+                    .details
+                    .constraints
+                    .clone()
+                    .unwrap_or_else(|| ddl::parse_add_constraints(&sql));
+                definition.constraints.extend(added);
+                change
+            }
+            ChangeType::DropConstraint => {
+                let Some(definition) = self.table_mut(&schema, &table) else {
+                    return Self::unknown(change, &table);
+                };
+                let names = ddl::dropped_constraint_names(&sql);
+                let dropped = |name: &String| names.iter().any(|n| same_name(name, n));
+                let (constraints, kept): (Vec<ConstraintInfo>, _) =
+                    definition.constraints.drain(..).partition(|c| dropped(&c.name));
+                definition.constraints = kept;
+                // Primary key and unique constraints own an index of the same name
+                definition.indexes.retain(|index| !dropped(&index.name));
+
+                if constraints.is_empty() {
+                    change
+                } else {
+                    change.with_constraints(constraints)
+                }
+            }
+            ChangeType::CreateIndex => {
+                let Some(on) = ddl::index_table(&sql) else {
+                    return change;
+                };
+                let (_, on) = ddl::split_qualified_name(&on);
+                let Some(definition) = self.table_mut(&schema, &on) else {
+                    return Self::unknown(change, &on);
+                };
+                let index_definition =
+                    change.details.metadata.get(INDEX_DEFINITION).cloned().unwrap_or_else(|| sql.clone());
+                definition.indexes.push(IndexDefinition {
+                    name: table,
+                    unique: sql.to_uppercase().contains("UNIQUE"),
+                    primary: false,
+                    definition: index_definition,
+                });
+                change
+            }
+            ChangeType::DropIndex => {
+                let dropped = self.schemas.get_mut(&schema).and_then(|definition| {
+                    definition.tables.values_mut().find_map(|definition| {
+                        let position = definition.indexes.iter().position(|index| same_name(&index.name, &table))?;
+                        Some(definition.indexes.remove(position))
+                    })
+                });
+                match dropped {
+                    Some(index) => change.with_metadata(INDEX_DEFINITION.to_string(), index.definition),
+                    None => Self::unknown(change, &table),
+                }
+            }
+            ChangeType::Other(_) => {
+                self.apply_type(&schema, &sql);
+                change
+            }
+        }
+    }
+
+    /// Whether `apply` records anything for `change`
+    pub fn tracks(change: &SchemaChange) -> bool {
+        let sql = &change.details.sql;
+        match change.change_type {
+            ChangeType::Other(_) => {
+                ddl::parse_create_enum(sql).is_some()
+                    || ddl::parse_add_enum_value(sql).is_some()
+                    || !ddl::dropped_type_names(sql).is_empty()
+            }
+            _ => true,
+        }
+    }
+
+    /// Apply an enum type statement, whose names default to `schema`
+    fn apply_type(&mut self, schema: &str, sql: &str) {
+        let split = |name: &str| {
+            let (type_schema, name) = ddl::split_qualified_name(name);
+            (type_schema.unwrap_or_else(|| schema.to_string()), name)
+        };
+
+        if let Some((name, labels)) = ddl::parse_create_enum(sql) {
+            let (schema, name) = split(&name);
+            let definition = TypeDefinition {
+                kind: "enum".to_string(),
+                labels,
+            };
+            self.schema_mut(&schema).types.insert(name, definition);
+        } else if let Some(value) = ddl::parse_add_enum_value(sql) {
+            let (schema, name) = split(&value.type_name);
+            let Some(definition) = self.schemas.get_mut(&schema).and_then(|s| s.types.get_mut(&name)) else {
+                return;
+            };
+            let labels = &mut definition.labels;
+            if labels.contains(&value.label) {
+                return;
+            }
+            let position = match (&value.before, &value.after) {
+                (Some(before), _) => labels.iter().position(|label| label == before),
+                (_, Some(after)) => labels.iter().position(|label| label == after).map(|i| i + 1),
+                _ => None,
+            };
+            labels.insert(position.unwrap_or(labels.len()), value.label);
+        } else {
+            for name in ddl::dropped_type_names(sql) {
+                let (schema, name) = split(&name);
+                if let Some(definition) = self.schemas.get_mut(&schema) {
+                    definition.types.remove(&name);
+                }
+            }
+        }
+    }
+
+    fn unknown(change: SchemaChange, name: &str) -> SchemaChange {
+        debug!("{} is not in the schema model, nothing to record for rollback", name);
+        change
+    }
+}
+
+/// `name` as stored matches `written`, which PostgreSQL folds when unquoted
+fn same_name(name: &str, written: &str) -> bool {
+    name == written || name == written.to_lowercase()
+}
+
+/// Apply an `ALTER [COLUMN] name ...` clause, returning the column as it was
+fn alter_column(table: &mut TableDefinition, clause: &str) -> Option<ColumnInfo> {
+    let mut words = clause.split_whitespace();
+    if !words.next()?.eq_ignore_ascii_case("ALTER") {
+        return None;
+    }
+    let mut name = words.next()?;
+    if name.eq_ignore_ascii_case("COLUMN") {
+        name = words.next()?;
+    }
+    let name = ddl::unquote_identifier(name);
+    let action: Vec<&str> = words.collect();
+    let upper: Vec<String> = action.iter().map(|word| word.to_uppercase()).collect();
+    let upper: Vec<&str> = upper.iter().map(String::as_str).collect();
+
+    let column = table.columns.iter_mut().find(|column| same_name(&column.name, &name))?;
+    let before = column.clone();
+    match upper.as_slice() {
+        ["SET", "NOT", "NULL"] => column.nullable = false,
+        ["DROP", "NOT", "NULL"] => column.nullable = true,
+        ["DROP", "DEFAULT"] => column.default = None,
+        ["SET", "DEFAULT", ..] => column.default = Some(action[2..].join(" ")),
+        ["TYPE", ..] | ["SET", "DATA", "TYPE", ..] => {
+            let start = if upper[0] == "TYPE" { 1 } else { 3 };
+            let end = upper.iter().position(|word| *word == "USING" || *word == "COLLATE");
+            column.data_type = action[start..end.unwrap_or(action.len())].join(" ");
+        }
+        _ => return None,
+    }
+    Some(before)
+}
+
+fn rename_column(table: &mut TableDefinition, old: &str, new: &str) {
+    let new = ddl::unquote_identifier(new);
+    let old = ddl::unquote_identifier(old);
+    for column in table.columns.iter_mut().filter(|column| same_name(&column.name, &old)) {
+        column.name = new.clone();
+    }
+    for constraint in &mut table.constraints {
+        for column in constraint.columns.iter_mut().filter(|column| same_name(column, &old)) {
+            *column = new.clone();
+        }
+    }
+}
+
+/// Rename a constraint, and the index backing a primary key or unique constraint
+fn rename_constraint(table: &mut TableDefinition, old: &str, new: &str) {
+    for constraint in table.constraints.iter_mut().filter(|constraint| same_name(&constraint.name, old)) {
+        constraint.name = new.to_string();
+    }
+    for index in table.indexes.iter_mut().filter(|index| same_name(&index.name, old)) {
+        index.name = new.to_string();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(change_type: ChangeType, object_name: &str, sql: &str) -> SchemaChange {
+        SchemaChange::new(change_type, "public".to_string(), object_name.to_string(), sql.to_string())
+    }
+
+    #[test]
+    fn test_apply_records_what_changes_remove() {
+        let mut model = SchemaModel::default();
+        model.apply(change(
+            ChangeType::CreateTable,
+            "users",
+            "CREATE TABLE users (id int PRIMARY KEY, email text NOT NULL, age int, CONSTRAINT uk_email UNIQUE (email))",
+        ));
+        model.apply(change(ChangeType::CreateIndex, "idx_age", "CREATE INDEX idx_age ON users (age)"));
+
+        let modified = model.apply(change(
+            ChangeType::ModifyColumn,
+            "users",
+            "ALTER TABLE users ALTER COLUMN age TYPE bigint USING age::bigint, ALTER COLUMN age SET DEFAULT 0",
+        ));
+        let before = &modified.details.columns.as_ref().unwrap()[0];
+        assert_eq!(before.data_type, "int");
+        assert_eq!(before.default, None);
+        let age = &model.table("public", "users").unwrap().columns[2];
+        assert_eq!(age.data_type, "bigint");
+        assert_eq!(age.default.as_deref(), Some("0"));
+
+        let dropped = model.apply(change(ChangeType::DropIndex, "idx_age", "DROP INDEX idx_age"));
+        assert_eq!(dropped.details.metadata[INDEX_DEFINITION], "CREATE INDEX idx_age ON users (age)");
+
+        let dropped = model.apply(change(ChangeType::DropColumn, "users", "ALTER TABLE users DROP COLUMN email"));
+        assert_eq!(dropped.details.columns.as_ref().unwrap()[0].data_type, "text");
+        assert_eq!(dropped.details.constraints.as_ref().unwrap()[0].name, "uk_email");
+
+        let users = model.table("public", "USERS").unwrap();
+        assert_eq!(users.columns.len(), 2);
+        assert!(users.constraints.iter().all(|constraint| constraint.name != "uk_email"));
+
+        let dropped = model.apply(change(ChangeType::DropTable, "users", "DROP TABLE users"));
+        assert_eq!(dropped.details.columns.as_ref().unwrap().len(), 2);
+        assert!(model.table("public", "users").is_none());
+    }
+}
@@ -0,0 +1,428 @@
+use crate::ddl;
+use crate::schema::{ChangeType, SchemaChange};
+use anyhow::{bail, Result};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::warn;
+
+/// Longest slug kept in file names, cut at a word boundary
+const MAX_SLUG_LEN: usize = 60;
+
+/// Width sequence numbers are zero-padded to
+const SEQUENCE_WIDTH: usize = 4;
+
+/// Names rendered for one migration before a collision is reported
+const MAX_NAME_ATTEMPTS: usize = 5;
+
+/// A migration file that another writer published first
+#[derive(Debug)]
+pub struct NameTaken(PathBuf);
+
+impl std::fmt::Display for NameTaken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Migration file {} already exists; refusing to overwrite it",
+            self.0.display()
+        )
+    }
+}
+
+impl std::error::Error for NameTaken {}
+
+/// Renders migration file names from a template
+///
+/// Supported placeholders:
+/// - `{seq}`: next sequence number in the output directory, zero-padded
+/// - `{timestamp}`: UTC time as `YYYYMMDDHHMMSS`
+/// - `{slug}`: summary of the changes, such as `create_users_add_email`
+/// - `{name}`: name requested by the caller
+pub struct MigrationNamer {
+    template: String,
+}
+
+impl MigrationNamer {
+    pub fn new(template: String) -> Self {
+        Self { template }
+    }
+
+    /// Render the base file name (without extension) for a new migration
+    pub fn render(&self, name: &str, changes: &[SchemaChange], existing: &[PathBuf]) -> Result<String> {
+        let mut rendered = String::new();
+        let mut rest = self.template.as_str();
+
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            let Some(end) = rest[start..].find('}') else {
+                bail!("Unterminated placeholder in naming template: {}", self.template);
+            };
+            let value = match &rest[start + 1..start + end] {
+                "seq" => format!(
+                    "{:0width$}",
+                    next_sequence(existing),
+                    width = SEQUENCE_WIDTH
+                ),
+                "timestamp" => chrono::Utc::now().format("%Y%m%d%H%M%S").to_string(),
+                "slug" => slug(changes),
+                "name" => name.to_string(),
+                other => bail!("Unknown placeholder {{{}}} in naming template", other),
+            };
+            rendered.push_str(&value);
+            rest = &rest[start + end + 1..];
+        }
+        rendered.push_str(rest);
+
+        let rendered = rendered.replace(['/', '\\'], "_");
+        if rendered.trim().is_empty() {
+            bail!("Naming template {} rendered an empty name", self.template);
+        }
+        Ok(rendered)
+    }
+}
+
+/// Sequence number following the highest one among existing migration files
+///
+/// Only short numeric prefixes count, so timestamp-named files are ignored.
+pub fn next_sequence(existing: &[PathBuf]) -> u64 {
+    existing
+        .iter()
+        .filter_map(|path| {
+            let filename = path.file_name()?.to_str()?;
+            let digits: String = filename.chars().take_while(char::is_ascii_digit).collect();
+            let terminator = filename[digits.len()..].chars().next();
+            if (1..=6).contains(&digits.len()) && matches!(terminator, Some('_' | '.' | '-')) {
+                digits.parse::<u64>().ok()
+            } else {
+                None
+            }
+        })
+        .max()
+        .unwrap_or(0)
+        + 1
+}
+
+/// Summarise changes as a file-name friendly slug, e.g. `create_users_add_email`
+pub fn slug(changes: &[SchemaChange]) -> String {
+    let mut words: Vec<String> = Vec::new();
+
+    for change in changes {
+        let (_, object) = ddl::split_qualified_name(&change.object_name);
+        let sql = &change.details.sql;
+        let phrase = match &change.change_type {
+            ChangeType::CreateTable => vec![format!("create_{}", object)],
+            ChangeType::DropTable => vec![format!("drop_{}", object)],
+            ChangeType::AddColumn => ddl::added_column_names(sql)
+                .into_iter()
+                .map(|column| format!("add_{}", column))
+                .collect(),
+            ChangeType::DropColumn => ddl::dropped_column_names(sql)
+                .into_iter()
+                .map(|column| format!("drop_{}", column))
+                .collect(),
+            ChangeType::ModifyColumn | ChangeType::AlterTable => {
+                match ddl::parse_rename_column(sql) {
+                    Some((_, new)) => vec![format!("rename_{}", new)],
+                    None => vec![format!("alter_{}", object)],
+                }
+            }
+            ChangeType::AddConstraint => ddl::parse_add_constraints(sql)
+                .into_iter()
+                .map(|constraint| format!("add_{}", constraint.name))
+                .collect(),
+            ChangeType::DropConstraint => vec![format!("drop_constraint_{}", object)],
+            ChangeType::CreateIndex => vec![format!("create_{}", object)],
+            ChangeType::DropIndex => vec![format!("drop_{}", object)],
+            ChangeType::Other(tag) => vec![tag.clone()],
+        };
+
+        for word in phrase {
+            let word = sanitize(&word);
+            if !word.is_empty() && words.last() != Some(&word) {
+                words.push(word);
+            }
+        }
+    }
+
+    let mut slug = String::new();
+    for word in words {
+        let candidate_len = slug.len() + word.len() + usize::from(!slug.is_empty());
+        if candidate_len > MAX_SLUG_LEN {
+            break;
+        }
+        if !slug.is_empty() {
+            slug.push('_');
+        }
+        slug.push_str(&word);
+    }
+
+    if slug.is_empty() {
+        "migration".to_string()
+    } else {
+        slug
+    }
+}
+
+/// Lowercase ASCII alphanumerics separated by single underscores
+fn sanitize(s: &str) -> String {
+    let mut sanitized = String::new();
+    for c in s.chars() {
+        if c.is_ascii_alphanumeric() {
+            sanitized.push(c.to_ascii_lowercase());
+        } else if !sanitized.ends_with('_') {
+            sanitized.push('_');
+        }
+    }
+    sanitized.trim_matches('_').to_string()
+}
+
+/// Write `content` to `path` through a temporary file, refusing to replace an
+/// existing file
+///
+/// The temporary file is published with a hard link, which fails when the
+/// target exists, so two writers racing for the same name never overwrite
+/// each other.
+pub fn write_atomic(path: &Path, content: &str) -> Result<()> {
+    let temp_path = write_temp(path, content)?;
+    let published = std::fs::hard_link(&temp_path, path);
+    let _ = std::fs::remove_file(&temp_path);
+    match published {
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+            Err(NameTaken(path.to_path_buf()).into())
+        }
+        Err(err) => {
+            Err(anyhow::Error::new(err).context(format!("Failed to write {}", path.display())))
+        }
+        Ok(()) => Ok(()),
+    }
+}
+
+/// Write several new files with [`write_atomic`], all or none of them
+pub fn write_all_atomic(files: &[(PathBuf, String)]) -> Result<()> {
+    if let Some((path, _)) = files.iter().find(|(path, _)| path.exists()) {
+        return Err(NameTaken(path.clone()).into());
+    }
+    for (i, (path, content)) in files.iter().enumerate() {
+        if let Err(err) = write_atomic(path, content) {
+            for (written, _) in &files[..i] {
+                let _ = std::fs::remove_file(written);
+            }
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// Run `write` again while another writer takes the name it rendered first
+///
+/// `write` renders the name from a fresh listing on every attempt, so a
+/// `{seq}` placeholder moves past the file that took the name. Gives up after
+/// a bounded number of attempts, for templates that render the same name again.
+pub fn with_fresh_name<T>(mut write: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut attempt = 1;
+    loop {
+        match write() {
+            Err(err) if attempt < MAX_NAME_ATTEMPTS && err.is::<NameTaken>() => {
+                warn!("{}; rendering a new name", err);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Replace the content of `path` through a temporary file and a rename, so
+/// readers and crashes see either the old or the new content
+pub fn replace_atomic(path: &Path, content: &str) -> Result<()> {
+    let temp_path = write_temp(path, content)?;
+    if let Err(err) = std::fs::rename(&temp_path, path) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(anyhow::Error::new(err).context(format!("Failed to write {}", path.display())));
+    }
+    Ok(())
+}
+
+/// Write `content` to a temporary file next to `path`, unique to this writer
+fn write_temp(path: &Path, content: &str) -> Result<PathBuf> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let filename = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow::anyhow!("Invalid migration path: {}", path.display()))?;
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        filename,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let written = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(content.as_bytes())?;
+            file.sync_all()
+        });
+    if let Err(err) = written {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(err.into());
+    }
+    Ok(temp_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes() -> Vec<SchemaChange> {
+        vec![
+            SchemaChange::new(
+                ChangeType::CreateTable,
+                "public".to_string(),
+                "users".to_string(),
+                "CREATE TABLE users (id SERIAL PRIMARY KEY)".to_string(),
+            ),
+            SchemaChange::new(
+                ChangeType::AddColumn,
+                "public".to_string(),
+                "users".to_string(),
+                "ALTER TABLE users ADD COLUMN email VARCHAR(255)".to_string(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_slug() {
+        assert_eq!(slug(&changes()), "create_users_add_email");
+        assert_eq!(slug(&[]), "migration");
+    }
+
+    #[test]
+    fn test_next_sequence_ignores_timestamps() {
+        let existing = vec![
+            PathBuf::from("20240101_120000_old.json"),
+            PathBuf::from("0002_create_users.json"),
+            PathBuf::from("0007_add_email.up.sql"),
+        ];
+        assert_eq!(next_sequence(&existing), 8);
+        assert_eq!(next_sequence(&[]), 1);
+    }
+
+    #[test]
+    fn test_render_template() {
+        let namer = MigrationNamer::new("{seq}_{slug}".to_string());
+        let existing = vec![PathBuf::from("0041_x.json")];
+        assert_eq!(
+            namer.render("ignored", &changes(), &existing).unwrap(),
+            "0042_create_users_add_email"
+        );
+
+        let namer = MigrationNamer::new("{timestamp}-{name}".to_string());
+        let rendered = namer.render("nightly", &[], &[]).unwrap();
+        assert_eq!(rendered.len(), "YYYYMMDDHHMMSS-nightly".len());
+        assert!(rendered.ends_with("-nightly"));
+
+        assert!(MigrationNamer::new("{bogus}".to_string())
+            .render("x", &[], &[])
+            .is_err());
+    }
+
+    #[test]
+    fn test_write_atomic_detects_collisions() {
+        let dir = std::env::temp_dir().join(format!("repligrate-naming-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("0001_m.json");
+
+        write_atomic(&path, "first").unwrap();
+        let second = write_atomic(&path, "second");
+        let content = std::fs::read_to_string(&path).unwrap();
+        let leftovers = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(second.is_err());
+        assert_eq!(content, "first");
+        assert_eq!(leftovers, 1);
+    }
+
+    #[test]
+    fn test_write_all_atomic_writes_all_or_nothing() {
+        let dir = std::env::temp_dir().join(format!("repligrate-naming-all-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let up = dir.join("0001_m.up.sql");
+        let down = dir.join("0001_m.down.sql");
+        std::fs::write(&down, "taken").unwrap();
+
+        let files = [(up.clone(), "up".to_string()), (down.clone(), "down".to_string())];
+
+        let collision = write_all_atomic(&files);
+        let up_written = up.exists();
+        let down_content = std::fs::read_to_string(&down).unwrap();
+        std::fs::remove_file(&down).unwrap();
+        write_all_atomic(&files).unwrap();
+        let written = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(collision.is_err());
+        assert!(!up_written);
+        assert_eq!(down_content, "taken");
+        assert_eq!(written, 2);
+    }
+
+    #[test]
+    fn test_collisions_render_a_new_name() {
+        let dir = std::env::temp_dir().join(format!("repligrate-naming-fresh-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let namer = MigrationNamer::new("{seq}_{name}".to_string());
+        let list = || -> Vec<PathBuf> {
+            std::fs::read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .collect()
+        };
+
+        let mut raced = false;
+        let written = with_fresh_name(|| {
+            let path = dir.join(format!("{}.sql", namer.render("m", &[], &list())?));
+            if !raced {
+                // Another writer publishes the same name between listing and writing
+                std::fs::write(&path, "theirs").unwrap();
+                raced = true;
+            }
+            write_atomic(&path, "ours")?;
+            Ok(path)
+        })
+        .unwrap();
+
+        let namer = MigrationNamer::new("{name}".to_string());
+        let mut attempts = 0;
+        let stuck = with_fresh_name(|| {
+            attempts += 1;
+            write_atomic(&dir.join(namer.render("0001_m.sql", &[], &[])?), "ours")
+        });
+        let content = std::fs::read_to_string(&written).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(written.ends_with("0002_m.sql"));
+        assert_eq!(content, "ours");
+        assert!(stuck.unwrap_err().is::<NameTaken>());
+        assert_eq!(attempts, MAX_NAME_ATTEMPTS);
+    }
+
+    #[test]
+    fn test_replace_atomic() {
+        let dir = std::env::temp_dir().join(format!("repligrate-naming-replace-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("changelog.xml");
+
+        replace_atomic(&path, "first").unwrap();
+        replace_atomic(&path, "second").unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        let files = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(content, "second");
+        assert_eq!(files, 1);
+    }
+}
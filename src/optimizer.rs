@@ -1,14 +1,209 @@
+use crate::ddl;
 use crate::schema::{ChangeType, SchemaChange};
-use std::collections::HashMap;
+use std::collections::BTreeSet;
 use tracing::{debug, info};
 
 /// Optimizes migrations by merging related schema changes
 pub struct MigrationOptimizer;
 
+/// Changes of a batch each change has to stay after
+///
+/// A change depends on an earlier one when both touch the same table, index
+/// or view, when it references a table the other touches through a foreign
+/// key or a view query, or when either is a statement whose objects are
+/// unknown.
+struct DependencyGraph {
+    dependencies: Vec<BTreeSet<usize>>,
+}
+
+/// Objects a change touches, as lowercase `schema.name`
+struct Footprint {
+    objects: BTreeSet<String>,
+    /// Table the change alters, or an index is created on
+    table: Option<String>,
+    /// Words of a view query, any of which may be a table it reads
+    words: BTreeSet<String>,
+    /// Objects unknown, so ordered against every other change
+    barrier: bool,
+}
+
+impl DependencyGraph {
+    fn build(changes: &[SchemaChange]) -> Self {
+        let footprints: Vec<Footprint> = changes.iter().map(Footprint::of).collect();
+        let dependencies = footprints
+            .iter()
+            .enumerate()
+            .map(|(index, footprint)| {
+                (0..index)
+                    .filter(|&other| footprint.related(&footprints[other]))
+                    .collect()
+            })
+            .collect();
+        Self { dependencies }
+    }
+
+    /// Whether change `index` has to stay after change `other`
+    fn depends_on(&self, index: usize, other: usize) -> bool {
+        self.dependencies[index].contains(&other)
+    }
+}
+
+impl Footprint {
+    fn of(change: &SchemaChange) -> Self {
+        let sql = &change.details.sql;
+        let key = |name: &str| object_key(&change.schema_name, name);
+        let mut footprint = Self {
+            objects: BTreeSet::new(),
+            table: None,
+            words: BTreeSet::new(),
+            barrier: false,
+        };
+
+        match &change.change_type {
+            ChangeType::CreateIndex => {
+                footprint.objects.insert(key(&change.object_name));
+                match ddl::index_table(sql) {
+                    Some(table) => {
+                        footprint.objects.insert(key(&table));
+                        footprint.table = Some(key(&table));
+                    }
+                    None => footprint.barrier = true,
+                }
+            }
+            // The dropped index's table is unknown
+            ChangeType::DropIndex => footprint.barrier = true,
+            ChangeType::Other(_) => match ddl::view_name(sql) {
+                Some(view) => {
+                    footprint.objects.insert(key(&view));
+                    footprint.words = words(sql);
+                }
+                None => footprint.barrier = true,
+            },
+            _ if change.object_name.is_empty() => footprint.barrier = true,
+            _ => {
+                footprint.objects.insert(key(&change.object_name));
+                footprint.table = Some(key(&change.object_name));
+                if let Some(name) = ddl::parse_rename_table(sql) {
+                    footprint.objects.insert(key(&name));
+                }
+                for table in ddl::referenced_tables(sql) {
+                    footprint.objects.insert(key(&table));
+                }
+            }
+        }
+        footprint
+    }
+
+    fn related(&self, other: &Footprint) -> bool {
+        self.barrier
+            || other.barrier
+            || !self.objects.is_disjoint(&other.objects)
+            || self.reads_any(&other.objects)
+            || other.reads_any(&self.objects)
+    }
+
+    /// Whether a view query may read one of `objects`
+    fn reads_any(&self, objects: &BTreeSet<String>) -> bool {
+        objects.iter().any(|object| {
+            let name = object.rsplit('.').next().unwrap_or(object);
+            self.words.contains(name)
+        })
+    }
+}
+
+/// Changes of a batch being folded, removed ones left as `None`
+struct Batch {
+    changes: Vec<Option<SchemaChange>>,
+    footprints: Vec<Footprint>,
+}
+
+impl Batch {
+    fn new(changes: Vec<SchemaChange>) -> Self {
+        Self {
+            footprints: changes.iter().map(Footprint::of).collect(),
+            changes: changes.into_iter().map(Some).collect(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    fn get(&self, index: usize) -> Option<&SchemaChange> {
+        self.changes[index].as_ref()
+    }
+
+    /// Changes left before `index` that it depends on, latest first
+    fn dependencies(&self, index: usize) -> Vec<usize> {
+        (0..index)
+            .rev()
+            .filter(|&other| {
+                self.changes[other].is_some()
+                    && self.footprints[index].related(&self.footprints[other])
+            })
+            .collect()
+    }
+
+    /// Whether the change at `index` alters `table` itself, without renaming it
+    fn alters(&self, index: usize, table: &Option<String>) -> bool {
+        let footprint = &self.footprints[index];
+        footprint.table.is_some()
+            && footprint.table == *table
+            && !footprint.barrier
+            && self
+                .get(index)
+                .is_some_and(|change| ddl::parse_rename_table(&change.details.sql).is_none())
+    }
+
+    fn replace(&mut self, index: usize, change: SchemaChange) {
+        self.footprints[index] = Footprint::of(&change);
+        self.changes[index] = Some(change);
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.changes[index] = None;
+    }
+
+    fn into_changes(self) -> Vec<SchemaChange> {
+        self.changes.into_iter().flatten().collect()
+    }
+}
+
+/// Lowercase `schema.name` of a possibly qualified object name
+fn object_key(default_schema: &str, name: &str) -> String {
+    let (schema, name) = ddl::split_qualified_name(name);
+    format!("{}.{}", schema.as_deref().unwrap_or(default_schema), name).to_lowercase()
+}
+
+/// Lowercase words of a statement
+fn words(sql: &str) -> BTreeSet<String> {
+    sql.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// The only clause of an ALTER TABLE statement
+fn single_clause(sql: &str) -> Option<String> {
+    let mut clauses = ddl::alter_table_clauses(sql);
+    (clauses.len() == 1).then(|| clauses.remove(0))
+}
+
+/// Offset of the new name in a `RENAME ... TO new` clause
+fn rename_target(clause: &str) -> Option<usize> {
+    let offset = clause.to_uppercase().rfind(" TO ")? + " TO ".len();
+    Some(offset + clause[offset..].len() - clause[offset..].trim_start().len())
+}
+
 #[allow(dead_code)]
 impl MigrationOptimizer {
     /// Optimize a list of schema changes by merging related operations
     /// while preserving the correct migration semantics
+    ///
+    /// Changes that undo each other are dropped and successive changes of an
+    /// object folded into one first. Changes keep their order, except that
+    /// one may join an earlier change it merges with when it depends on none
+    /// of the changes in between.
     pub fn optimize(changes: Vec<SchemaChange>) -> Vec<SchemaChange> {
         if changes.is_empty() {
             return changes;
@@ -16,109 +211,368 @@ impl MigrationOptimizer {
 
         info!("Optimizing {} schema changes", changes.len());
 
-        let mut optimized = Vec::new();
-        let mut pending_changes: HashMap<String, Vec<SchemaChange>> = HashMap::new();
+        let mut batch = Batch::new(changes);
+        Self::cancel_created_tables(&mut batch);
+        Self::cancel_added_columns(&mut batch);
+        Self::collapse_renames(&mut batch);
+        Self::collapse_column_changes(&mut batch);
+        Self::fold_added_columns(&mut batch);
+        let changes = batch.into_changes();
 
-        // Group changes by table
-        for change in changes {
-            let key = format!("{}.{}", change.schema_name, change.object_name);
-            pending_changes.entry(key).or_default().push(change);
+        let graph = DependencyGraph::build(&changes);
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        for index in 0..changes.len() {
+            let mut target = None;
+            for (position, group) in groups.iter().enumerate().rev() {
+                if Self::can_merge(&changes[group[0]], &changes[index]) {
+                    target = Some(position);
+                    break;
+                }
+                if group.iter().any(|&other| graph.depends_on(index, other)) {
+                    break;
+                }
+            }
+            match target {
+                Some(position) => groups[position].push(index),
+                None => groups.push(vec![index]),
+            }
         }
 
-        // Process each group of changes
-        for (_, group) in pending_changes {
-            let merged = Self::merge_table_changes(group);
-            optimized.extend(merged);
-        }
+        let optimized: Vec<SchemaChange> = groups
+            .into_iter()
+            .map(|group| {
+                let group: Vec<SchemaChange> = group
+                    .into_iter()
+                    .map(|index| changes[index].clone())
+                    .collect();
+                Self::merge_group(group)
+            })
+            .collect();
 
         info!("Optimized to {} operations", optimized.len());
         optimized
     }
 
-    /// Merge related changes for a single table
-    fn merge_table_changes(changes: Vec<SchemaChange>) -> Vec<SchemaChange> {
-        if changes.is_empty() {
-            return changes;
-        }
-
-        let mut result = Vec::new();
-        let mut i = 0;
-
-        while i < changes.len() {
-            let current = &changes[i];
-
-            // Try to merge consecutive ADD COLUMN operations
-            if current.change_type == ChangeType::AddColumn && i + 1 < changes.len() {
-                let mut merged_columns = vec![current.clone()];
-                let mut j = i + 1;
+    /// Drop a table created in the batch along with its creation and the
+    /// changes made to it in between
+    fn cancel_created_tables(batch: &mut Batch) {
+        for index in 0..batch.len() {
+            let Some(change) = batch.get(index) else {
+                continue;
+            };
+            if change.change_type != ChangeType::DropTable || change.details.sql.contains(',') {
+                continue;
+            }
+            let table = batch.footprints[index].table.clone();
 
-                // Collect consecutive ADD COLUMN operations
-                while j < changes.len() && changes[j].change_type == ChangeType::AddColumn {
-                    merged_columns.push(changes[j].clone());
-                    j += 1;
+            let mut cancelled = vec![index];
+            for other in batch.dependencies(index) {
+                if !batch.alters(other, &table) {
+                    break;
                 }
-
-                // If we found multiple consecutive ADD COLUMN operations, merge them
-                if merged_columns.len() > 1 {
-                    let merged = Self::merge_add_columns(merged_columns);
-                    result.push(merged);
-                    i = j;
-                    continue;
+                cancelled.push(other);
+                let Some(created) = batch.get(other) else {
+                    break;
+                };
+                if created.change_type == ChangeType::CreateTable {
+                    // The table may have existed before the batch
+                    if !created.details.sql.to_uppercase().contains("IF NOT EXISTS") {
+                        debug!("Table {} is created and dropped", created.object_name);
+                        for index in cancelled {
+                            batch.remove(index);
+                        }
+                    }
+                    break;
                 }
             }
+        }
+    }
 
-            // Try to merge ALTER TABLE operations
-            if current.change_type == ChangeType::AlterTable && i + 1 < changes.len() {
-                let mut merged_alters = vec![current.clone()];
-                let mut j = i + 1;
+    /// Drop a column added in the batch along with its addition and the
+    /// changes made to its type, default or nullability in between
+    fn cancel_added_columns(batch: &mut Batch) {
+        for index in 0..batch.len() {
+            let Some(change) = batch.get(index) else {
+                continue;
+            };
+            let sql = &change.details.sql;
+            let dropped = ddl::dropped_column_names(sql);
+            let ([column], Some(_)) = (dropped.as_slice(), single_clause(sql)) else {
+                continue;
+            };
+            let column = column.to_lowercase();
+            let table = batch.footprints[index].table.clone();
 
-                // Collect consecutive ALTER TABLE operations on the same table
-                while j < changes.len()
-                    && changes[j].change_type == ChangeType::AlterTable
-                    && changes[j].object_name == current.object_name
+            let mut cancelled = vec![index];
+            for other in batch.dependencies(index) {
+                let Some(change) = batch.get(other).filter(|_| batch.alters(other, &table)) else {
+                    break;
+                };
+                let sql = &change.details.sql;
+                if !words(sql).contains(&column) {
+                    continue;
+                }
+                let added = ddl::added_column_names(sql);
+                if change.change_type == ChangeType::AddColumn
+                    && single_clause(sql).is_some()
+                    && added.len() == 1
+                    && added[0].to_lowercase() == column
+                    && !sql.to_uppercase().contains("IF NOT EXISTS")
                 {
-                    merged_alters.push(changes[j].clone());
-                    j += 1;
+                    debug!(
+                        "Column {}.{} is added and dropped",
+                        change.object_name, column
+                    );
+                    cancelled.push(other);
+                    for index in cancelled {
+                        batch.remove(index);
+                    }
+                    break;
+                }
+                let clauses = ddl::alter_table_clauses(sql);
+                let alters_column = !clauses.is_empty()
+                    && clauses.iter().all(|clause| {
+                        ddl::parse_alter_column(clause)
+                            .is_some_and(|(name, _)| name.to_lowercase() == column)
+                    });
+                if !alters_column {
+                    break;
                 }
+                cancelled.push(other);
+            }
+        }
+    }
+
+    /// Turn renames `a` to `b` then `b` to `c` of a table or column into a
+    /// single rename of `a` to `c`, or none when `c` is `a`
+    fn collapse_renames(batch: &mut Batch) {
+        for index in 0..batch.len() {
+            let Some(change) = batch.get(index) else {
+                continue;
+            };
+            let sql = &change.details.sql;
+            let Some(clause) = single_clause(sql) else {
+                continue;
+            };
+            let table = batch.footprints[index].table.clone();
 
-                // If we found multiple consecutive ALTER TABLE operations, merge them
-                if merged_alters.len() > 1 {
-                    let merged = Self::merge_alter_tables(merged_alters);
-                    result.push(merged);
-                    i = j;
+            if let Some(new) = ddl::parse_rename_table(sql) {
+                let Some(&other) = batch.dependencies(index).first() else {
+                    continue;
+                };
+                let Some(earlier) = batch.get(other) else {
+                    continue;
+                };
+                let renamed = ddl::parse_rename_table(&earlier.details.sql)
+                    .map(|name| object_key(&earlier.schema_name, &name));
+                if renamed.is_none() || renamed != table {
                     continue;
                 }
+                let restored = object_key(&earlier.schema_name, &earlier.object_name)
+                    == object_key(&change.schema_name, &new);
+                Self::collapse_rename(batch, other, index, &clause, restored);
+            } else if let Some((old, new)) = ddl::parse_rename_column(sql) {
+                let (old, new) = (old.to_lowercase(), new.to_lowercase());
+                for other in batch.dependencies(index) {
+                    let Some(earlier) = batch.get(other).filter(|_| batch.alters(other, &table))
+                    else {
+                        break;
+                    };
+                    let renamed = ddl::parse_rename_column(&earlier.details.sql);
+                    if let Some((first, _)) = renamed.filter(|(_, name)| name.to_lowercase() == old)
+                    {
+                        let restored = first.to_lowercase() == new;
+                        Self::collapse_rename(batch, other, index, &clause, restored);
+                        break;
+                    }
+                    let mentioned = words(&earlier.details.sql);
+                    if mentioned.contains(&old) || mentioned.contains(&new) {
+                        break;
+                    }
+                }
             }
+        }
+    }
 
-            // Try to merge ADD CONSTRAINT operations
-            if current.change_type == ChangeType::AddConstraint && i + 1 < changes.len() {
-                let mut merged_constraints = vec![current.clone()];
-                let mut j = i + 1;
+    /// Give the rename at `earlier` the new name of the one at `later`, or
+    /// drop both when they restore the original name
+    fn collapse_rename(
+        batch: &mut Batch,
+        earlier: usize,
+        later: usize,
+        clause: &str,
+        restored: bool,
+    ) {
+        let Some(change) = batch.get(earlier) else {
+            return;
+        };
+        debug!("Collapsing renames of {}", change.object_name);
+        if restored {
+            batch.remove(earlier);
+            batch.remove(later);
+            return;
+        }
 
-                // Collect consecutive ADD CONSTRAINT operations on the same table
-                while j < changes.len()
-                    && changes[j].change_type == ChangeType::AddConstraint
-                    && changes[j].object_name == current.object_name
-                {
-                    merged_constraints.push(changes[j].clone());
-                    j += 1;
-                }
+        let sql = &change.details.sql;
+        let (Some(first), Some(target)) = (single_clause(sql), rename_target(clause)) else {
+            return;
+        };
+        let (Some(start), Some(offset)) = (sql.rfind(&first), rename_target(&first)) else {
+            return;
+        };
+        let mut collapsed = change.clone();
+        collapsed.details.sql = format!(
+            "{}{}{}",
+            &sql[..start + offset],
+            &clause[target..],
+            &sql[start + first.len()..]
+        );
+        batch.replace(earlier, collapsed);
+        batch.remove(later);
+    }
 
-                // If we found multiple consecutive ADD CONSTRAINT operations, merge them
-                if merged_constraints.len() > 1 {
-                    let merged = Self::merge_add_constraints(merged_constraints);
-                    result.push(merged);
-                    i = j;
-                    continue;
+    /// Keep only the last of successive type or default changes of a column,
+    /// with the column as it was before the first for its rollback
+    fn collapse_column_changes(batch: &mut Batch) {
+        for index in 0..batch.len() {
+            let Some(change) = batch.get(index) else {
+                continue;
+            };
+            let Some((column, kind)) = Self::column_change(&change.details.sql) else {
+                continue;
+            };
+            let table = batch.footprints[index].table.clone();
+
+            for other in batch.dependencies(index) {
+                let Some(earlier) = batch.get(other).filter(|_| batch.alters(other, &table)) else {
+                    break;
+                };
+                match Self::column_change(&earlier.details.sql) {
+                    Some((name, earlier_kind)) if name == column && earlier_kind == kind => {
+                        debug!("Collapsing {} changes of {}", kind, column);
+                        let mut last = change.clone();
+                        last.details.columns = earlier.details.columns.clone();
+                        batch.replace(index, last);
+                        batch.remove(other);
+                        break;
+                    }
+                    // Type and default are set independently
+                    Some((name, _)) if name == column => continue,
+                    _ => {}
                 }
+                if words(&earlier.details.sql).contains(&column) {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Column and kind of a statement only changing the type or the default
+    /// of a column; type changes converting values with USING are left alone
+    fn column_change(sql: &str) -> Option<(String, &'static str)> {
+        let (column, action) = ddl::parse_alter_column(&single_clause(sql)?)?;
+        let action = action.to_uppercase();
+        let kind = if action.starts_with("SET DEFAULT") || action == "DROP DEFAULT" {
+            "default"
+        } else if (action.starts_with("TYPE") || action.starts_with("SET DATA TYPE"))
+            && !action.contains(" USING ")
+        {
+            "type"
+        } else {
+            return None;
+        };
+        Some((column.to_lowercase(), kind))
+    }
+
+    /// Move columns added to a table created in the batch into its CREATE
+    /// TABLE statement, when nothing in between needs them to come later
+    fn fold_added_columns(batch: &mut Batch) {
+        for index in 0..batch.len() {
+            let Some(change) = batch.get(index) else {
+                continue;
+            };
+            if change.change_type != ChangeType::AddColumn
+                || change.details.sql.to_uppercase().contains("IF NOT EXISTS")
+            {
+                continue;
+            }
+            let Some(&other) = batch.dependencies(index).first() else {
+                continue;
+            };
+            let Some(created) = batch.get(other) else {
+                continue;
+            };
+            if created.change_type != ChangeType::CreateTable
+                || batch.footprints[other].table != batch.footprints[index].table
+            {
+                continue;
+            }
+            if let Some(folded) = Self::fold_columns(created, change) {
+                debug!(
+                    "Folding columns added to {} into its creation",
+                    created.object_name
+                );
+                batch.replace(other, folded);
+                batch.remove(index);
             }
+        }
+    }
+
+    /// `create` with the columns of `add` appended to its column list
+    fn fold_columns(create: &SchemaChange, add: &SchemaChange) -> Option<SchemaChange> {
+        let definitions = ddl::added_column_definitions(&add.details.sql);
+        if definitions.is_empty()
+            || definitions.len() != ddl::alter_table_clauses(&add.details.sql).len()
+        {
+            return None;
+        }
+
+        let sql = &create.details.sql;
+        let open = sql.find('(')?;
+        let body = ddl::first_parenthesized(sql)?;
+        // AS, OF and PARTITION OF tables have no column list to extend
+        let name = sql[..open].split_whitespace().last()?;
+        if ddl::split_qualified_name(name).1 != ddl::split_qualified_name(&create.object_name).1 {
+            return None;
+        }
+        ddl::parse_create_table(sql)?;
 
-            // No merge possible, add as-is
-            result.push(current.clone());
-            i += 1;
+        let close = open + 1 + body.len();
+        let separator = if body.trim().is_empty() { "" } else { ", " };
+        let mut folded = create.clone();
+        folded.details.sql = format!(
+            "{}{}{}{}",
+            &sql[..close],
+            separator,
+            definitions.join(", "),
+            &sql[close..]
+        );
+        if let Some(columns) = &mut folded.details.columns {
+            columns.extend(
+                add.details
+                    .columns
+                    .clone()
+                    .unwrap_or_else(|| ddl::parse_add_columns(&add.details.sql)),
+            );
+            folded
+                .details
+                .constraints
+                .get_or_insert_with(Vec::new)
+                .extend(add.details.constraints.iter().flatten().cloned());
         }
+        Some(folded)
+    }
 
-        result
+    /// Merge changes of the same kind on a single table into one statement
+    fn merge_group(mut changes: Vec<SchemaChange>) -> SchemaChange {
+        if changes.len() == 1 {
+            return changes.remove(0);
+        }
+        match changes[0].change_type {
+            ChangeType::AddColumn => Self::merge_add_columns(changes),
+            ChangeType::AddConstraint => Self::merge_add_constraints(changes),
+            _ => Self::merge_alter_tables(changes),
+        }
     }
 
     /// Merge multiple ADD COLUMN operations into a single operation
@@ -220,28 +674,30 @@ impl MigrationOptimizer {
     }
 
     /// Check if two changes can be merged
+    ///
+    /// RENAME is left alone, PostgreSQL not accepting it next to other clauses.
     fn can_merge(change1: &SchemaChange, change2: &SchemaChange) -> bool {
-        // Same table and compatible operations
-        change1.object_name == change2.object_name
-            && Self::are_compatible_operations(&change1.change_type, &change2.change_type)
-    }
-
-    /// Check if two operation types are compatible for merging
-    fn are_compatible_operations(type1: &ChangeType, type2: &ChangeType) -> bool {
-        matches!(
-            (type1, type2),
-            (ChangeType::AddColumn, ChangeType::AddColumn)
-                | (ChangeType::AlterTable, ChangeType::AlterTable)
-                | (ChangeType::DropColumn, ChangeType::DropColumn)
-                | (ChangeType::AddConstraint, ChangeType::AddConstraint)
-                | (ChangeType::DropConstraint, ChangeType::DropConstraint)
-        )
+        let renames = |change: &SchemaChange| {
+            ddl::alter_table_clauses(&change.details.sql)
+                .iter()
+                .any(|clause| clause.to_uppercase().starts_with("RENAME"))
+        };
+        change1.schema_name == change2.schema_name
+            && change1.object_name == change2.object_name
+            && change1.change_type == change2.change_type
+            && matches!(
+                change1.change_type,
+                ChangeType::AddColumn | ChangeType::AlterTable | ChangeType::AddConstraint
+            )
+            && !renames(change1)
+            && !renames(change2)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::schema::{ColumnInfo, SchemaChangeParser};
 
     #[test]
     fn test_merge_consecutive_add_columns() {
@@ -310,6 +766,37 @@ mod tests {
 
     #[test]
     fn test_preserve_non_mergeable_operations() {
+        let changes = vec![
+            SchemaChange::new(
+                ChangeType::AddColumn,
+                "public".to_string(),
+                "users".to_string(),
+                "ALTER TABLE users ADD COLUMN email VARCHAR(255)".to_string(),
+            ),
+            SchemaChange::new(
+                ChangeType::CreateIndex,
+                "public".to_string(),
+                "idx_users_email".to_string(),
+                "CREATE INDEX idx_users_email ON users(email)".to_string(),
+            ),
+            SchemaChange::new(
+                ChangeType::DropTable,
+                "public".to_string(),
+                "legacy".to_string(),
+                "DROP TABLE legacy".to_string(),
+            ),
+        ];
+
+        let optimized = MigrationOptimizer::optimize(changes.clone());
+        assert_eq!(optimized.len(), 3);
+        for (optimized, change) in optimized.iter().zip(&changes) {
+            assert_eq!(optimized.change_type, change.change_type);
+            assert_eq!(optimized.details.sql, change.details.sql);
+        }
+    }
+
+    #[test]
+    fn test_fold_added_column_keeps_index_after_table() {
         let changes = vec![
             SchemaChange::new(
                 ChangeType::CreateTable,
@@ -332,7 +819,10 @@ mod tests {
         ];
 
         let optimized = MigrationOptimizer::optimize(changes);
-        assert_eq!(optimized.len(), 3);
+        assert_eq!(optimized.len(), 2);
+        assert_eq!(optimized[0].change_type, ChangeType::CreateTable);
+        assert!(optimized[0].details.sql.contains("email"));
+        assert_eq!(optimized[1].change_type, ChangeType::CreateIndex);
     }
 
     #[test]
@@ -355,4 +845,244 @@ mod tests {
         let optimized = MigrationOptimizer::optimize(changes);
         assert_eq!(optimized.len(), 1);
     }
+
+    fn script(statements: &[&str]) -> Vec<SchemaChange> {
+        SchemaChangeParser::parse_script(&statements.join(";\n"), "public")
+    }
+
+    fn sqls(changes: &[SchemaChange]) -> Vec<&str> {
+        changes
+            .iter()
+            .map(|change| change.details.sql.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_keeps_the_original_order() {
+        let changes = script(&[
+            "CREATE TABLE users (id int)",
+            "CREATE INDEX idx_users_id ON users (id)",
+            "CREATE TABLE orders (id int)",
+            "ALTER TABLE users ADD COLUMN email text",
+            "CREATE INDEX idx_users_email ON users (email)",
+        ]);
+        let expected = sqls(&changes);
+        for _ in 0..10 {
+            assert_eq!(
+                sqls(&MigrationOptimizer::optimize(changes.clone())),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_merges_past_unrelated_changes_only() {
+        let optimized = MigrationOptimizer::optimize(script(&[
+            "ALTER TABLE users ADD COLUMN email text",
+            "ALTER TABLE orders ADD COLUMN status text",
+            "ALTER TABLE users ADD COLUMN phone text",
+        ]));
+        assert_eq!(
+            sqls(&optimized),
+            [
+                "ALTER TABLE users ADD COLUMN email text, ADD COLUMN phone text;",
+                "ALTER TABLE orders ADD COLUMN status text",
+            ]
+        );
+
+        // A foreign key, a view query or an unknown statement in between
+        // holds the later change back
+        for between in [
+            "CREATE TABLE orders (user_id int REFERENCES users (id))",
+            "ALTER TABLE orders ADD CONSTRAINT fk_user FOREIGN KEY (user_id) REFERENCES public.users (id)",
+            "CREATE VIEW emails AS SELECT email FROM users",
+            "CREATE TYPE mood AS ENUM ('happy')",
+            "DROP INDEX idx_users_email",
+        ] {
+            let changes = script(&[
+                "ALTER TABLE users ADD COLUMN email text",
+                between,
+                "ALTER TABLE users ADD COLUMN phone text",
+            ]);
+            let expected = sqls(&changes);
+            assert_eq!(sqls(&MigrationOptimizer::optimize(changes.clone())), expected);
+        }
+    }
+
+    #[test]
+    fn test_dependency_graph() {
+        let changes = script(&[
+            "CREATE TABLE users (id int)",
+            "CREATE TABLE orders (id int, user_id int REFERENCES users)",
+            "CREATE INDEX idx_orders_user ON orders (user_id)",
+            "CREATE VIEW user_orders AS SELECT * FROM users JOIN orders USING (id)",
+            "CREATE TABLE audit (id int)",
+            "ALTER TABLE users RENAME TO customers",
+            "ALTER TABLE customers ADD COLUMN email text",
+        ]);
+        let graph = DependencyGraph::build(&changes);
+        assert!(graph.depends_on(1, 0));
+        assert!(graph.depends_on(2, 1));
+        assert!(!graph.depends_on(2, 0));
+        assert!(graph.depends_on(3, 0) && graph.depends_on(3, 1));
+        assert!((0..4).all(|other| !graph.depends_on(4, other)));
+        assert!(graph.depends_on(6, 5));
+    }
+
+    #[test]
+    fn test_renames_are_not_merged() {
+        let changes = script(&[
+            "ALTER TABLE users RENAME COLUMN name TO full_name",
+            "ALTER TABLE users RENAME COLUMN mail TO email",
+        ]);
+        assert_eq!(MigrationOptimizer::optimize(changes).len(), 2);
+    }
+
+    #[test]
+    fn test_created_and_dropped_table_cancels_out() {
+        let optimized = MigrationOptimizer::optimize(script(&[
+            "CREATE TABLE scratch (id int)",
+            "ALTER TABLE users ADD COLUMN email text",
+            "ALTER TABLE scratch ADD COLUMN note text",
+            "CREATE INDEX idx_scratch_note ON scratch (note)",
+            "DROP TABLE scratch",
+        ]));
+        assert_eq!(
+            sqls(&optimized),
+            ["ALTER TABLE users ADD COLUMN email text"]
+        );
+
+        // A view reading the table, or a table that may predate the batch
+        for changes in [
+            script(&[
+                "CREATE TABLE scratch (id int)",
+                "CREATE VIEW v AS SELECT id FROM scratch",
+                "DROP TABLE scratch CASCADE",
+            ]),
+            script(&[
+                "CREATE TABLE IF NOT EXISTS scratch (id int)",
+                "DROP TABLE scratch",
+            ]),
+        ] {
+            let expected = changes.len();
+            assert_eq!(MigrationOptimizer::optimize(changes).len(), expected);
+        }
+    }
+
+    #[test]
+    fn test_added_and_dropped_column_cancels_out() {
+        let optimized = MigrationOptimizer::optimize(script(&[
+            "ALTER TABLE users ADD COLUMN tmp int",
+            "ALTER TABLE users ADD COLUMN email text",
+            "ALTER TABLE users ALTER COLUMN tmp TYPE bigint",
+            "ALTER TABLE users DROP COLUMN tmp",
+        ]));
+        assert_eq!(
+            sqls(&optimized),
+            ["ALTER TABLE users ADD COLUMN email text"]
+        );
+
+        // An index on the column is left to the DROP COLUMN to remove
+        let changes = script(&[
+            "ALTER TABLE users ADD COLUMN tmp int",
+            "CREATE INDEX idx_tmp ON users (tmp)",
+            "ALTER TABLE users DROP COLUMN tmp",
+        ]);
+        assert_eq!(MigrationOptimizer::optimize(changes).len(), 3);
+    }
+
+    #[test]
+    fn test_added_columns_fold_into_the_created_table() {
+        let optimized = MigrationOptimizer::optimize(script(&[
+            "CREATE TABLE users (id int PRIMARY KEY)",
+            "ALTER TABLE users ADD COLUMN email text NOT NULL",
+            "ALTER TABLE users ADD COLUMN IF NOT EXISTS age int",
+            "CREATE TABLE teams (id int)",
+            "ALTER TABLE teams ADD COLUMN name text, ADD COLUMN size int DEFAULT 0",
+        ]));
+        assert_eq!(
+            sqls(&optimized),
+            [
+                "CREATE TABLE users (id int PRIMARY KEY, email text NOT NULL)",
+                "ALTER TABLE users ADD COLUMN IF NOT EXISTS age int",
+                "CREATE TABLE teams (id int, name text, size int DEFAULT 0)",
+            ]
+        );
+
+        // A column whose type is created after the table stays behind
+        let changes = script(&[
+            "CREATE TABLE pets (id int)",
+            "CREATE TYPE mood AS ENUM ('happy')",
+            "ALTER TABLE pets ADD COLUMN mood mood",
+        ]);
+        assert_eq!(MigrationOptimizer::optimize(changes).len(), 3);
+    }
+
+    #[test]
+    fn test_column_changes_collapse_to_the_final_state() {
+        let mut changes = script(&[
+            "ALTER TABLE users ALTER COLUMN age TYPE bigint",
+            "ALTER TABLE users ALTER COLUMN age SET DEFAULT 1",
+            "ALTER TABLE users ADD COLUMN email text",
+            "ALTER TABLE users ALTER COLUMN age SET DATA TYPE numeric",
+            "ALTER TABLE users ALTER COLUMN age DROP DEFAULT",
+        ]);
+        let before = ColumnInfo {
+            name: "age".to_string(),
+            data_type: "integer".to_string(),
+            nullable: true,
+            default: None,
+            constraints: Vec::new(),
+        };
+        changes[0] = changes[0].clone().with_columns(vec![before]);
+
+        let optimized = MigrationOptimizer::optimize(changes);
+        assert_eq!(
+            sqls(&optimized),
+            [
+                "ALTER TABLE users ADD COLUMN email text",
+                "ALTER TABLE users ALTER COLUMN age SET DATA TYPE numeric",
+                "ALTER TABLE users ALTER COLUMN age DROP DEFAULT",
+            ]
+        );
+        // Rolled back to the type before the first change
+        let columns = optimized[1].details.columns.as_ref().unwrap();
+        assert_eq!(columns[0].data_type, "integer");
+
+        // Values converted with USING depend on the type in between
+        let changes = script(&[
+            "ALTER TABLE users ALTER COLUMN age TYPE text",
+            "ALTER TABLE users ALTER COLUMN age TYPE int USING length(age)",
+        ]);
+        assert_eq!(MigrationOptimizer::optimize(changes).len(), 2);
+    }
+
+    #[test]
+    fn test_rename_chains_collapse() {
+        let optimized = MigrationOptimizer::optimize(script(&[
+            "ALTER TABLE users RENAME TO members",
+            "ALTER TABLE members RENAME TO people",
+            "ALTER TABLE people RENAME COLUMN name TO nickname",
+            "ALTER TABLE people ADD COLUMN age int",
+            "ALTER TABLE people RENAME COLUMN nickname TO \"Display Name\"",
+            "ALTER TABLE people RENAME COLUMN mail TO email",
+            "ALTER TABLE people RENAME COLUMN email TO mail",
+        ]));
+        assert_eq!(
+            sqls(&optimized),
+            [
+                "ALTER TABLE users RENAME TO people",
+                "ALTER TABLE people RENAME COLUMN name TO \"Display Name\"",
+                "ALTER TABLE people ADD COLUMN age int",
+            ]
+        );
+
+        // The intermediate name is used in between
+        let changes = script(&[
+            "ALTER TABLE people RENAME COLUMN name TO nickname",
+            "CREATE INDEX idx_nickname ON people (nickname)",
+            "ALTER TABLE people RENAME COLUMN nickname TO handle",
+        ]);
+        assert_eq!(MigrationOptimizer::optimize(changes).len(), 3);
+    }
 }
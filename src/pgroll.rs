@@ -0,0 +1,169 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// A pgroll release that generated migrations are targeted at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PgrollVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+/// pgroll operations (and operation options) whose availability depends on the release
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PgrollOperation {
+    CreateTable,
+    DropTable,
+    RenameTable,
+    AddColumn,
+    DropColumn,
+    AlterColumn,
+    CreateIndex,
+    DropIndex,
+    DropConstraint,
+    Sql,
+    /// The `onComplete` flag of the `sql` operation
+    SqlOnComplete,
+    RenameConstraint,
+    CreateConstraint,
+    DropMulticolumnConstraint,
+}
+
+impl PgrollVersion {
+    /// Newest release whose operation set is known to repligrate
+    pub const LATEST: PgrollVersion = PgrollVersion::new(0, 8, 0);
+
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Check whether this release understands the given operation
+    pub fn supports(&self, operation: PgrollOperation) -> bool {
+        *self >= operation.since()
+    }
+}
+
+impl PgrollOperation {
+    /// First pgroll release that shipped the operation
+    pub fn since(&self) -> PgrollVersion {
+        match self {
+            PgrollOperation::CreateTable
+            | PgrollOperation::DropTable
+            | PgrollOperation::RenameTable
+            | PgrollOperation::AddColumn
+            | PgrollOperation::DropColumn
+            | PgrollOperation::AlterColumn
+            | PgrollOperation::CreateIndex
+            | PgrollOperation::DropIndex
+            | PgrollOperation::DropConstraint
+            | PgrollOperation::Sql => PgrollVersion::new(0, 1, 0),
+            PgrollOperation::SqlOnComplete => PgrollVersion::new(0, 5, 0),
+            PgrollOperation::RenameConstraint => PgrollVersion::new(0, 6, 0),
+            PgrollOperation::CreateConstraint | PgrollOperation::DropMulticolumnConstraint => {
+                PgrollVersion::new(0, 7, 0)
+            }
+        }
+    }
+}
+
+impl Default for PgrollVersion {
+    fn default() -> Self {
+        Self::LATEST
+    }
+}
+
+impl fmt::Display for PgrollVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for PgrollVersion {
+    type Err = anyhow::Error;
+
+    /// Parse `0.7.0`, `v0.7.0` or `0.7`
+    fn from_str(s: &str) -> Result<Self> {
+        let trimmed = s.trim();
+        let version = trimmed.strip_prefix('v').unwrap_or(trimmed);
+
+        let parts = version
+            .split('.')
+            .map(|part| part.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| anyhow!("Invalid pgroll version: {}", s))?;
+
+        match parts.as_slice() {
+            [major, minor] => Ok(Self::new(*major, *minor, 0)),
+            [major, minor, patch] => Ok(Self::new(*major, *minor, *patch)),
+            _ => Err(anyhow!("Invalid pgroll version: {}", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for PgrollVersion {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<PgrollVersion> for String {
+    fn from(version: PgrollVersion) -> Self {
+        version.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            "0.7.1".parse::<PgrollVersion>().unwrap(),
+            PgrollVersion::new(0, 7, 1)
+        );
+        assert_eq!(
+            "v0.6".parse::<PgrollVersion>().unwrap(),
+            PgrollVersion::new(0, 6, 0)
+        );
+        assert!("latest".parse::<PgrollVersion>().is_err());
+        assert!("0.7.0.1".parse::<PgrollVersion>().is_err());
+    }
+
+    #[test]
+    fn test_version_ordering() {
+        assert!(PgrollVersion::new(0, 10, 0) > PgrollVersion::new(0, 9, 3));
+        assert!(PgrollVersion::new(1, 0, 0) > PgrollVersion::LATEST);
+    }
+
+    #[test]
+    fn test_supports() {
+        let old = PgrollVersion::new(0, 4, 0);
+        assert!(old.supports(PgrollOperation::Sql));
+        assert!(!old.supports(PgrollOperation::SqlOnComplete));
+        assert!(!old.supports(PgrollOperation::CreateConstraint));
+
+        let recent = PgrollVersion::new(0, 7, 0);
+        assert!(recent.supports(PgrollOperation::SqlOnComplete));
+        assert!(recent.supports(PgrollOperation::CreateConstraint));
+        assert!(recent.supports(PgrollOperation::DropMulticolumnConstraint));
+    }
+
+    #[test]
+    fn test_serde_roundtrip() {
+        let version = PgrollVersion::new(0, 6, 1);
+        let json = serde_json::to_string(&version).unwrap();
+        assert_eq!(json, "\"0.6.1\"");
+        let parsed: PgrollVersion = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, version);
+    }
+}
@@ -1,28 +1,64 @@
-use crate::config::Config;
-use crate::db::DbConnection;
-use crate::migration::{MigrationGenerator, MigrationWriter};
+use crate::baseline::BaselineMarker;
+use crate::capture::{CaptureDecoder, CapturedDdl, MESSAGE_PREFIX};
+use crate::catalog;
+use crate::cleanup::CleanupPlan;
+use crate::config::{Config, ConfigSource};
+use crate::db::{DbConnection, DecodedRow};
+use crate::ddl;
+use crate::filter::{PgrollFilter, SchemaFilter};
+use crate::ident::QualifiedName;
+use crate::migration::MigrationWriter;
+use crate::model::SchemaModel;
 use crate::optimizer::MigrationOptimizer;
-use crate::schema::{SchemaChange, SchemaChangeParser};
+use crate::schema::SchemaChange;
 use crate::state::ListenerState;
-use anyhow::Result;
+use crate::wal::{self, WalMessageParser};
+use anyhow::{Context, Result};
+use std::time::{Duration, Instant};
 use tracing::{debug, info, warn};
 
+/// How often the slot is polled for new changes
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Rows decoded per poll; what lies beyond is read once the slot is advanced
+const PEEK_LIMIT: i32 = 10_000;
+
 /// Listens to PostgreSQL logical replication changes
+///
+/// DDL reaches the slot as logical messages emitted by the capture event
+/// trigger. Changes are buffered until `receive_timeout` seconds pass without
+/// new DDL or `batch_size` changes are pending, then written as one migration.
+/// The slot is only advanced past changes once their migration is written, so
+/// nothing is lost if the listener stops before a flush.
 pub struct ReplicationListener {
-    #[allow(dead_code)]
     config: Config,
     slot_name: String,
     publication_name: String,
     db: DbConnection,
     state: ListenerState,
+    decoder: CaptureDecoder,
+    pgroll_filter: PgrollFilter,
+    schema_filter: SchemaFilter,
+    pending: Vec<SchemaChange>,
+    last_change_at: Option<Instant>,
+    /// Commit LSN of the last transaction read from the slot
+    received_lsn: u64,
+    /// Position the slot has been advanced to
+    confirmed_lsn: u64,
+    /// Where the configuration is reloaded from on SIGHUP
+    config_source: Option<ConfigSource>,
+    /// Schema before the pending changes, for their rollbacks
+    model: SchemaModel,
 }
 
 #[allow(dead_code)]
 impl ReplicationListener {
     /// Create a new replication listener
-    pub async fn new(config: Config, slot_name: String, publication_name: String) -> Result<Self> {
+    pub fn new(config: Config, slot_name: String, publication_name: String) -> Result<Self> {
         config.validate()?;
         let db = DbConnection::new(&config.database_url)?;
+        let pgroll_filter = PgrollFilter::new(&config.pgroll_filter)?;
+        let schema_filter = SchemaFilter::new(&config.schema_filter)?;
 
         Ok(Self {
             config,
@@ -30,70 +66,353 @@ impl ReplicationListener {
             publication_name,
             db,
             state: ListenerState::new(),
+            decoder: CaptureDecoder::new(),
+            pgroll_filter,
+            schema_filter,
+            pending: Vec::new(),
+            last_change_at: None,
+            received_lsn: 0,
+            confirmed_lsn: 0,
+            config_source: None,
+            model: SchemaModel::default(),
         })
     }
 
+    /// Allow the configuration to be reloaded from `source`
+    pub fn set_config_source(&mut self, source: ConfigSource) {
+        self.config_source = Some(source);
+    }
+
     /// Start listening for replication changes
-    pub async fn listen(&mut self, tables: Option<String>) -> Result<()> {
+    ///
+    /// The PostgreSQL client is blocking, so this must run outside the async runtime.
+    pub fn listen(&mut self, tables: Option<String>) -> Result<()> {
         info!("Setting up replication...");
 
         // Parse table list if provided
-        let table_list = tables.map(|t| {
-            t.split(',')
-                .map(|s| s.trim().to_string())
-                .collect::<Vec<_>>()
-        });
-
-        // Create replication slot and publication
-        self.db.create_replication_slot(&self.slot_name)?;
+        let table_list = tables
+            .map(|t| {
+                ddl::split_top_level(&t, ',')
+                    .iter()
+                    .filter(|s| !s.is_empty())
+                    .map(|s| {
+                        s.parse::<QualifiedName>()
+                            .with_context(|| format!("Invalid table in --tables: {}", s))
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .transpose()?;
+
+        // Install the DDL capture before the slot exists, so that the slot
+        // holds all DDL committed after the snapshot it exports
+        self.db.install_capture()?;
+        let slot = self.db.create_exported_slot(&self.slot_name)?;
+        let created = slot.is_some();
+        if let Some(slot) = slot {
+            self.model = self.load_model(Some(&slot.snapshot_name));
+        }
         self.db
-            .create_publication(&self.publication_name, table_list)?;
+            .create_publication(&self.publication_name, table_list, &self.schema_filter)?;
+
+        if let Some(slot) = self.db.get_slot_info(&self.slot_name)? {
+            self.confirmed_lsn = wal::parse_lsn(&slot.confirmed_flush_lsn).unwrap_or(0);
+            self.received_lsn = self.confirmed_lsn;
+        }
+        self.skip_to_baseline()?;
+
+        // Changes still in an existing slot from an earlier run are already
+        // in the catalog, so their rollbacks may be incomplete
+        if !created {
+            self.model = self.load_model(None);
+        }
+
+        self.state.metrics().set_received_lsn(self.received_lsn);
+        self.state.metrics().set_flushed_lsn(self.confirmed_lsn);
+        self.state.set_ready();
 
         info!("Replication setup complete. Listening for changes...");
         info!("Press Ctrl+C to stop, or use pause/continue commands");
 
-        // Collect schema changes
-        let _schema_changes: Vec<SchemaChange> = Vec::new();
-        let _change_count = 0;
+        self.run()
+    }
+
+    /// Schema the pending changes start from, read in `snapshot` if given
+    fn load_model(&mut self, snapshot: Option<&str>) -> SchemaModel {
+        let model = self
+            .db
+            .snapshot_transaction(snapshot)
+            .and_then(|mut transaction| {
+                let model = SchemaModel::load(&mut transaction)?;
+                transaction.commit()?;
+                Ok(model)
+            });
+        model.unwrap_or_else(|err| {
+            warn!("Failed to load the schema model, rollbacks will be incomplete: {}", err);
+            SchemaModel::default()
+        })
+    }
 
-        // In a real implementation, we would connect to the replication slot
-        // and process messages. For now, we'll demonstrate the structure.
-        info!("Waiting for schema changes (press Ctrl+C to stop)...");
+    /// Advance a slot lagging behind the baseline migration, whose schema
+    /// already includes the DDL it holds up to there
+    fn skip_to_baseline(&mut self) -> Result<()> {
+        let Some(marker) = BaselineMarker::read(&self.config.output_dir)? else {
+            return Ok(());
+        };
+        let Some(lsn) = wal::parse_lsn(&marker.lsn) else {
+            warn!("Ignoring baseline marker with invalid LSN {}", marker.lsn);
+            return Ok(());
+        };
+        if marker.slot_name != self.slot_name || lsn <= self.confirmed_lsn {
+            return Ok(());
+        }
+
+        info!("Carrying on from the baseline at {}", marker.lsn);
+        self.db.advance_replication_slot(&self.slot_name, &marker.lsn)?;
+        self.confirmed_lsn = lsn;
+        self.received_lsn = lsn;
+        Ok(())
+    }
 
-        // Simulate receiving changes
+    /// Poll the slot until shutdown is requested or an error stops the listener
+    fn run(&mut self) -> Result<()> {
         loop {
-            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+            // Nothing more is read once shutdown is requested
+            if self.state.shutdown_requested() {
+                self.flush()?;
+                info!("Replication listener stopped");
+                return Ok(());
+            }
+
+            if self.state.take_reload_request() {
+                self.reload();
+            }
 
-            // Check if paused
             if self.state.is_paused() {
                 debug!("Listener is paused, skipping message processing");
             } else {
-                debug!("Listener is active, processing messages");
+                self.poll()?;
+            }
+
+            if self.state.take_flush_request() || self.flush_due() {
+                self.flush()?;
+            }
+            self.state.set_pending(self.pending.len());
+            self.update_lag()?;
+
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Load the configuration again and apply its filters and output settings
+    ///
+    /// The connection, slot and publication are kept, so a broken file only
+    /// logs a warning.
+    fn reload(&mut self) {
+        match self.try_reload() {
+            Ok(()) => info!("Configuration reloaded"),
+            Err(err) => warn!("Keeping the current configuration: {:#}", err),
+        }
+    }
+
+    fn try_reload(&mut self) -> Result<()> {
+        let source = self
+            .config_source
+            .as_ref()
+            .context("The listener was not started with a configuration source")?;
+        let mut config = source.load()?;
+        config.database_url = self.config.database_url.clone();
+        config.validate()?;
+
+        let pgroll_filter = PgrollFilter::new(&config.pgroll_filter)?;
+        let schema_filter = SchemaFilter::new(&config.schema_filter)?;
+
+        self.pgroll_filter = pgroll_filter;
+        self.schema_filter = schema_filter;
+        self.config = config;
+        Ok(())
+    }
+
+    /// Read the transactions committed since the last poll
+    fn poll(&mut self) -> Result<()> {
+        let mut transaction: Vec<DecodedRow> = Vec::new();
+        let mut received: Vec<SchemaChange> = Vec::new();
+        let mut pgroll_migrations_known = false;
+
+        for row in self.db.peek_changes(&self.slot_name, PEEK_LIMIT)? {
+            if !WalMessageParser::is_commit_row(&row.data) {
+                transaction.push(row);
+                continue;
+            }
+
+            // Transactions come out in commit order; those up to the last
+            // commit seen were handled by an earlier poll
+            let rows = std::mem::take(&mut transaction);
+            let Some(commit_lsn) = wal::parse_lsn(&row.lsn) else {
+                continue;
+            };
+            if commit_lsn <= self.received_lsn {
+                continue;
+            }
+            self.received_lsn = commit_lsn;
+            self.state.metrics().set_received_lsn(commit_lsn);
+
+            for row in rows {
+                self.state.metrics().record_message();
+                if let Some(change) = self.process_message(&row)? {
+                    self.state.metrics().record_ddl();
+                    if let Some(rule) = self.schema_filter.excluded(&change) {
+                        debug!("Excluding change ({}): {}", rule, change.details.sql);
+                        continue;
+                    }
+                    // pgroll's own DDL must not reach the model or turn into
+                    // follow-up migrations
+                    if !pgroll_migrations_known {
+                        self.refresh_pgroll_migrations()?;
+                        pgroll_migrations_known = true;
+                    }
+                    if let Some(reason) = self.pgroll_filter.matches(&change) {
+                        debug!("Ignoring pgroll change ({}): {}", reason, change.details.sql);
+                        continue;
+                    }
+                    received.push(change);
+                }
+            }
+        }
+
+        if !received.is_empty() {
+            let received = self.enrich(received);
+            for change in received {
+                self.pending.push(self.model.apply(change));
             }
+            self.last_change_at = Some(Instant::now());
+        }
+
+        // Nothing is waiting to be written, so everything read so far is done with
+        if self.pending.is_empty() {
+            self.confirm()?;
+        }
+
+        Ok(())
+    }
+
+    /// Attach catalog data to the changes read by one poll
+    ///
+    /// The catalog shows the state after every committed change, so a change
+    /// followed by another one to the same relation is left as parsed.
+    fn enrich(&mut self, changes: Vec<SchemaChange>) -> Vec<SchemaChange> {
+        let relations: Vec<Option<String>> = changes.iter().map(catalog::relation_of).collect();
+
+        changes
+            .into_iter()
+            .enumerate()
+            .map(|(i, change)| {
+                let superseded = match &relations[i] {
+                    Some(relation) => relations[i + 1..].iter().flatten().any(|later| later == relation),
+                    None => true,
+                };
+                if superseded {
+                    return change;
+                }
+                match catalog::enrich(self.db.client(), change.clone()) {
+                    Ok(enriched) => enriched,
+                    Err(err) => {
+                        warn!("Catalog lookup failed for {}: {}", change.object_name, err);
+                        change
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Whether pending changes should be written now
+    fn flush_due(&self) -> bool {
+        let Some(last_change_at) = self.last_change_at else {
+            return false;
+        };
+        !self.pending.is_empty()
+            && !self.state.is_paused()
+            && (self.pending.len() >= self.config.replication.batch_size
+                || last_change_at.elapsed() >= Duration::from_secs(self.config.replication.receive_timeout))
+    }
 
-            // In production, this would receive actual replication messages
-            // For now, we just wait for interrupt
+    /// Write pending changes as a migration and release them from the slot
+    pub fn flush(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return self.confirm();
         }
+        // Changes stay in the slot until their migration is written
+        if self.state.is_paused() {
+            warn!("Listener is paused; keeping {} pending changes", self.pending.len());
+            return Ok(());
+        }
+
+        let changes = std::mem::take(&mut self.pending);
+        self.last_change_at = None;
+
+        self.generate_migration(changes)?;
+        self.confirm()
+    }
+
+    /// Advance the slot to the last transaction read
+    fn confirm(&mut self) -> Result<()> {
+        if self.received_lsn > self.confirmed_lsn {
+            self.db
+                .advance_replication_slot(&self.slot_name, &wal::format_lsn(self.received_lsn))?;
+            self.confirmed_lsn = self.received_lsn;
+            self.state.metrics().set_flushed_lsn(self.confirmed_lsn);
+        }
+        Ok(())
+    }
+
+    /// Measure how much WAL the server has written past the flushed position
+    fn update_lag(&mut self) -> Result<()> {
+        if let Some(current) = wal::parse_lsn(&self.db.get_current_lsn()?) {
+            self.state
+                .metrics()
+                .set_lag_bytes(current.saturating_sub(self.confirmed_lsn));
+        }
+        Ok(())
+    }
+
+    /// Learn the migration names pgroll's versioned schemas are derived from
+    fn refresh_pgroll_migrations(&mut self) -> Result<()> {
+        let mut names = self
+            .db
+            .pgroll_migration_names(self.pgroll_filter.state_schema())?;
+        for path in MigrationWriter::sink(&self.config).list()? {
+            if let Some(filename) = path.file_name().and_then(|name| name.to_str()) {
+                let stem = filename.split('.').next().unwrap_or(filename);
+                names.push(stem.to_string());
+            }
+        }
+        self.pgroll_filter.set_migration_names(names);
+        Ok(())
     }
 
     /// Process a replication message
-    fn process_message(&self, message: &str) -> Result<Option<SchemaChange>> {
-        debug!("Processing replication message: {}", message);
-
-        // Parse the message to extract SQL
-        if let Some((change_type, object_name)) = SchemaChangeParser::parse(message) {
-            let schema_change = SchemaChange::new(
-                change_type,
-                "public".to_string(),
-                object_name,
-                message.to_string(),
-            );
+    fn process_message(&mut self, row: &DecodedRow) -> Result<Option<SchemaChange>> {
+        debug!("Processing replication message: {}", row.data);
 
-            info!("Detected schema change: {:?}", schema_change.change_type);
-            return Ok(Some(schema_change));
+        let Some(message) = WalMessageParser::parse_logical_message(&row.data) else {
+            return Ok(None);
+        };
+        if message.prefix != MESSAGE_PREFIX {
+            return Ok(None);
         }
 
-        Ok(None)
+        let ddl = match CapturedDdl::decode(&message.content) {
+            Ok(ddl) => ddl,
+            Err(err) => {
+                warn!("Skipping malformed capture message at {}: {}", row.lsn, err);
+                return Ok(None);
+            }
+        };
+
+        let schema_change = self.decoder.decode(&row.lsn, &row.xid, &ddl);
+        if let Some(schema_change) = &schema_change {
+            info!("Detected schema change: {:?}", schema_change.change_type);
+        }
+        Ok(schema_change)
     }
 
     /// Generate migration from collected changes
@@ -111,11 +430,13 @@ impl ReplicationListener {
 
         // Optimize changes by merging related operations
         let optimized_changes = MigrationOptimizer::optimize(changes);
+        if optimized_changes.is_empty() {
+            info!("The schema changes cancel each other out, no migration written");
+            return Ok(());
+        }
 
-        let migration_name = format!("migration_{}", chrono::Local::now().format("%Y%m%d_%H%M%S"));
-        let migration = MigrationGenerator::generate(migration_name, optimized_changes)?;
-
-        MigrationWriter::write(&migration, &self.config.output_dir)?;
+        MigrationWriter::sink(&self.config).write("migration", &optimized_changes)?;
+        self.state.metrics().record_migration();
 
         Ok(())
     }
@@ -138,8 +459,8 @@ impl ReplicationListener {
     /// Cleanup replication resources
     pub fn cleanup(&mut self) -> Result<()> {
         info!("Cleaning up replication resources...");
-        self.db.drop_replication_slot(&self.slot_name)?;
-        self.db.drop_publication(&self.publication_name)?;
+        CleanupPlan::discover(&mut self.db, &self.slot_name, &self.publication_name, false)?
+            .execute(&mut self.db)?;
         info!("Cleanup complete");
         Ok(())
     }
@@ -147,7 +468,7 @@ impl ReplicationListener {
 
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use crate::schema::SchemaChangeParser;
 
     #[test]
     fn test_schema_change_parser() {
@@ -1,5 +1,7 @@
+use crate::ddl;
 use serde::{ Deserialize, Serialize };
 use std::collections::HashMap;
+use tracing::warn;
 
 /// Represents a detected schema change
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,6 +50,9 @@ pub struct ConstraintInfo {
     pub name: String,
     pub constraint_type: String,
     pub definition: String,
+    /// Constrained columns, in key order; empty for CHECK constraints parsed from SQL
+    #[serde(default)]
+    pub columns: Vec<String>,
 }
 
 #[allow(dead_code)]
@@ -115,7 +120,7 @@ impl SchemaChangeParser {
             } else {
                 Some((ChangeType::AlterTable, extract_table_name(sql)))
             }
-        } else if sql_upper.starts_with("CREATE INDEX") {
+        } else if sql_upper.starts_with("CREATE INDEX") || sql_upper.starts_with("CREATE UNIQUE INDEX") {
             Some((ChangeType::CreateIndex, extract_index_name(sql)))
         } else if sql_upper.starts_with("DROP INDEX") {
             Some((ChangeType::DropIndex, extract_index_name(sql)))
@@ -128,6 +133,26 @@ impl SchemaChangeParser {
             ))
         }
     }
+
+    /// Parse every DDL statement of a SQL script, skipping anything else
+    ///
+    /// Unqualified objects are placed in `default_schema`.
+    pub fn parse_script(script: &str, default_schema: &str) -> Vec<SchemaChange> {
+        let mut changes = Vec::new();
+        for statement in ddl::split_statements(script) {
+            if !ddl::is_ddl_statement(&statement) {
+                warn!("Skipping statement that is not DDL: {}", statement);
+                continue;
+            }
+            let Some((change_type, object_name)) = Self::parse(&statement) else {
+                continue;
+            };
+            let (schema_name, _) = ddl::split_qualified_name(&object_name);
+            let schema_name = schema_name.unwrap_or_else(|| default_schema.to_string());
+            changes.push(SchemaChange::new(change_type, schema_name, object_name, statement));
+        }
+        changes
+    }
 }
 
 #[allow(unused_parens)]
@@ -135,26 +160,37 @@ fn trim_quotes(s: &str) -> &str {
     s.trim_matches(|c| (c == '"' || c == '`'))
 }
 
-#[allow(dead_code)]
-fn extract_table_name(sql: &str) -> String {
+/// Keywords that can sit between `TABLE`/`INDEX` and the object name
+const NAME_PREFIX_KEYWORDS: [&str; 5] = ["IF", "NOT", "EXISTS", "ONLY", "CONCURRENTLY"];
+
+/// Name following `keyword`, skipping `IF [NOT] EXISTS` and similar, and cut
+/// before an opening parenthesis
+fn extract_name_after(sql: &str, keyword: &str) -> String {
     let parts: Vec<&str> = sql.split_whitespace().collect();
-    for (i, part) in parts.iter().enumerate() {
-        if part.to_uppercase() == "TABLE" && i + 1 < parts.len() {
-            return trim_quotes(parts[i + 1]).to_string();
+    let Some(start) = parts.iter().position(|part| part.eq_ignore_ascii_case(keyword)) else {
+        return String::new();
+    };
+    let name = parts[start + 1..]
+        .iter()
+        .find(|part| !NAME_PREFIX_KEYWORDS.iter().any(|k| part.eq_ignore_ascii_case(k)));
+    match name {
+        // `CREATE INDEX ON t (...)` leaves the index unnamed
+        Some(name) if !name.eq_ignore_ascii_case("ON") => {
+            let name = name.split('(').next().unwrap_or(name);
+            trim_quotes(name).to_string()
         }
+        _ => String::new(),
     }
-    String::new()
+}
+
+#[allow(dead_code)]
+fn extract_table_name(sql: &str) -> String {
+    extract_name_after(sql, "TABLE")
 }
 
 #[allow(dead_code)]
 fn extract_index_name(sql: &str) -> String {
-    let parts: Vec<&str> = sql.split_whitespace().collect();
-    for (i, part) in parts.iter().enumerate() {
-        if part.to_uppercase() == "INDEX" && i + 1 < parts.len() {
-            return trim_quotes(parts[i + 1]).to_string();
-        }
-    }
-    String::new()
+    extract_name_after(sql, "INDEX")
 }
 
 #[cfg(test)]
@@ -215,4 +251,32 @@ mod tests {
         let (change_type, _) = result.unwrap();
         assert_eq!(change_type, ChangeType::AddConstraint);
     }
+
+    #[test]
+    fn test_extract_names() {
+        let (_, name) = SchemaChangeParser::parse("CREATE TABLE IF NOT EXISTS orders(id int)").unwrap();
+        assert_eq!(name, "orders");
+        let (_, name) = SchemaChangeParser::parse("ALTER TABLE ONLY \"Users\" ADD COLUMN x int").unwrap();
+        assert_eq!(name, "Users");
+        let (change_type, name) =
+            SchemaChangeParser::parse("CREATE UNIQUE INDEX CONCURRENTLY idx_email ON users (email)").unwrap();
+        assert_eq!(change_type, ChangeType::CreateIndex);
+        assert_eq!(name, "idx_email");
+    }
+
+    #[test]
+    fn test_parse_script() {
+        let script = "BEGIN;\n\
+            CREATE TABLE sales.orders (id int); -- new table\n\
+            INSERT INTO sales.orders VALUES (1);\n\
+            ALTER TABLE users ADD COLUMN note text;\n\
+            COMMIT;";
+        let changes = SchemaChangeParser::parse_script(script, "public");
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].change_type, ChangeType::CreateTable);
+        assert_eq!(changes[0].schema_name, "sales");
+        assert_eq!(changes[1].change_type, ChangeType::AddColumn);
+        assert_eq!(changes[1].schema_name, "public");
+        assert_eq!(changes[1].details.sql, "ALTER TABLE users ADD COLUMN note text");
+    }
 }
@@ -0,0 +1,101 @@
+use crate::state::ListenerState;
+use anyhow::Result;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Process signals the listener reacts to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// SIGINT or SIGTERM: flush pending changes, confirm them and exit
+    Shutdown,
+    /// SIGUSR1: write pending changes now
+    Flush,
+    /// SIGUSR2: pause or continue
+    Toggle,
+    /// SIGHUP: reload filters and output settings
+    Reload,
+}
+
+/// Apply a signal to the listener state
+///
+/// A second shutdown signal exits at once, for a flush that hangs. Pending
+/// changes are still in the slot then, as it is only advanced after a write.
+pub fn handle(state: &ListenerState, signal: Signal) {
+    match signal {
+        Signal::Shutdown if state.shutdown_requested() => {
+            warn!("Second shutdown signal, exiting without flushing");
+            std::process::exit(1);
+        }
+        Signal::Shutdown => {
+            info!("Shutdown requested, flushing pending changes");
+            state.request_shutdown();
+        }
+        Signal::Flush => {
+            info!("Flush requested");
+            state.request_flush();
+        }
+        Signal::Toggle => state.toggle(),
+        Signal::Reload => {
+            info!("Configuration reload requested");
+            state.request_reload();
+        }
+    }
+}
+
+/// Route process signals to `state` until the runtime stops
+#[cfg(unix)]
+pub fn spawn(state: ListenerState) -> Result<JoinHandle<()>> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut user1 = signal(SignalKind::user_defined1())?;
+    let mut user2 = signal(SignalKind::user_defined2())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    Ok(tokio::spawn(async move {
+        loop {
+            let received = tokio::select! {
+                _ = interrupt.recv() => Signal::Shutdown,
+                _ = terminate.recv() => Signal::Shutdown,
+                _ = user1.recv() => Signal::Flush,
+                _ = user2.recv() => Signal::Toggle,
+                _ = hangup.recv() => Signal::Reload,
+            };
+            handle(&state, received);
+        }
+    }))
+}
+
+/// Route Ctrl+C to `state`; the other signals do not exist on this platform
+#[cfg(not(unix))]
+pub fn spawn(state: ListenerState) -> Result<JoinHandle<()>> {
+    Ok(tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            handle(&state, Signal::Shutdown);
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handle() {
+        let state = ListenerState::new();
+
+        handle(&state, Signal::Toggle);
+        assert!(state.is_paused());
+        handle(&state, Signal::Toggle);
+        assert!(!state.is_paused());
+
+        handle(&state, Signal::Flush);
+        assert!(state.take_flush_request());
+        handle(&state, Signal::Reload);
+        assert!(state.take_reload_request());
+
+        handle(&state, Signal::Shutdown);
+        assert!(state.shutdown_requested());
+    }
+}
@@ -0,0 +1,846 @@
+use super::{terminate_statement, MigrationSink};
+use crate::ddl;
+use crate::migration::MigrationGenerator;
+use crate::naming::{self, MigrationNamer};
+use crate::schema::{ChangeType, ColumnInfo, SchemaChange};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::path::PathBuf;
+use tracing::info;
+
+const CHANGES_DIR: &str = "changes";
+const XML_HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<databaseChangeLog
+    xmlns="http://www.liquibase.org/xml/ns/dbchangelog"
+    xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"
+    xsi:schemaLocation="http://www.liquibase.org/xml/ns/dbchangelog http://www.liquibase.org/xml/ns/dbchangelog/dbchangelog-latest.xsd">
+"#;
+const XML_FOOTER: &str = "</databaseChangeLog>\n";
+
+/// Liquibase changelog syntax
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangelogFormat {
+    #[default]
+    Yaml,
+    Xml,
+}
+
+impl ChangelogFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ChangelogFormat::Yaml => "yaml",
+            ChangelogFormat::Xml => "xml",
+        }
+    }
+}
+
+/// A single Liquibase change such as `createTable` or `sql`
+struct Change {
+    kind: &'static str,
+    attributes: Vec<(&'static str, Value)>,
+    columns: Vec<Column>,
+    /// Body of `sql` changes
+    text: Option<String>,
+}
+
+struct Column {
+    attributes: Vec<(&'static str, Value)>,
+    constraints: Vec<(&'static str, Value)>,
+}
+
+/// How a changeSet is undone by `liquibase rollback`
+enum Rollback {
+    /// Liquibase derives the inverse of the changes itself
+    Automatic,
+    Changes(Vec<Change>),
+    Sql(String),
+    /// No inverse is known; the changeSet carries the reason as its comment
+    /// and no rollback, so Liquibase refuses to roll it back
+    Unavailable(String),
+}
+
+struct ChangeSet {
+    id: String,
+    changes: Vec<Change>,
+    rollback: Rollback,
+}
+
+/// Writes each migration as a Liquibase changelog included from a master changelog
+pub struct LiquibaseSink {
+    output_dir: PathBuf,
+    format: ChangelogFormat,
+    author: String,
+    master_changelog: String,
+    namer: MigrationNamer,
+}
+
+impl LiquibaseSink {
+    pub fn new(
+        output_dir: PathBuf,
+        format: ChangelogFormat,
+        author: String,
+        master_changelog: String,
+        namer: MigrationNamer,
+    ) -> Self {
+        Self {
+            output_dir,
+            format,
+            author,
+            master_changelog,
+            namer,
+        }
+    }
+
+    /// One changeSet per schema change, with ids derived from the migration name
+    fn change_sets(base_name: &str, changes: &[SchemaChange]) -> Vec<ChangeSet> {
+        changes
+            .iter()
+            .enumerate()
+            .map(|(i, change)| {
+                let id = format!("{}-{}", base_name, i + 1);
+                match Self::native_changes(change) {
+                    Some(changes) if change.change_type == ChangeType::DropColumn => ChangeSet {
+                        id,
+                        changes,
+                        rollback: Self::recreate_columns(change)
+                            .map(Rollback::Changes)
+                            .unwrap_or_else(|| Self::sql_rollback(change)),
+                    },
+                    Some(changes) => ChangeSet {
+                        id,
+                        changes,
+                        rollback: Rollback::Automatic,
+                    },
+                    None => ChangeSet {
+                        id,
+                        changes: vec![Self::sql_change(&change.details.sql)],
+                        rollback: Self::sql_rollback(change),
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Liquibase changes for a schema change, or `None` when it must be written as raw SQL
+    fn native_changes(change: &SchemaChange) -> Option<Vec<Change>> {
+        let (schema, table) = Self::table_name(change);
+        let sql = &change.details.sql;
+
+        match change.change_type {
+            ChangeType::CreateTable => {
+                let (columns, constraints) = ddl::parse_create_table(sql)?;
+                if constraints.iter().any(|c| c.constraint_type != "foreign_key") {
+                    return None;
+                }
+
+                let mut changes = vec![Change {
+                    kind: "createTable",
+                    attributes: Self::table_attributes("tableName", &schema, &table),
+                    columns: columns.iter().map(Self::column).collect::<Option<_>>()?,
+                    text: None,
+                }];
+                changes.extend(Self::column_foreign_keys(&schema, &table, &columns));
+                for constraint in &constraints {
+                    changes.push(Self::foreign_key_change(
+                        &schema,
+                        &table,
+                        &constraint.name,
+                        &constraint.definition,
+                        ddl::constraint_columns(&constraint.definition),
+                    )?);
+                }
+                Some(changes)
+            }
+            ChangeType::AddColumn => {
+                let columns = ddl::parse_add_columns(sql);
+                if columns.is_empty() {
+                    return None;
+                }
+
+                let mut changes = vec![Change {
+                    kind: "addColumn",
+                    attributes: Self::table_attributes("tableName", &schema, &table),
+                    columns: columns.iter().map(Self::column).collect::<Option<_>>()?,
+                    text: None,
+                }];
+                changes.extend(Self::column_foreign_keys(&schema, &table, &columns));
+                Some(changes)
+            }
+            ChangeType::DropColumn => {
+                let dropped = ddl::dropped_column_names(sql);
+                if dropped.is_empty() {
+                    return None;
+                }
+                Some(
+                    dropped
+                        .into_iter()
+                        .map(|column| {
+                            let mut attributes =
+                                Self::table_attributes("tableName", &schema, &table);
+                            attributes.push(("columnName", Value::from(column)));
+                            Change {
+                                kind: "dropColumn",
+                                attributes,
+                                columns: Vec::new(),
+                                text: None,
+                            }
+                        })
+                        .collect(),
+                )
+            }
+            ChangeType::AlterTable | ChangeType::ModifyColumn => {
+                let (old, new) = ddl::parse_rename_column(sql)?;
+                let mut attributes = Self::table_attributes("tableName", &schema, &table);
+                attributes.push(("oldColumnName", Value::from(old)));
+                attributes.push(("newColumnName", Value::from(new)));
+                Some(vec![Change {
+                    kind: "renameColumn",
+                    attributes,
+                    columns: Vec::new(),
+                    text: None,
+                }])
+            }
+            ChangeType::AddConstraint => {
+                let constraints = ddl::parse_add_constraints(sql);
+                match constraints.as_slice() {
+                    [constraint] if constraint.constraint_type == "foreign_key" => {
+                        Some(vec![Self::foreign_key_change(
+                            &schema,
+                            &table,
+                            &constraint.name,
+                            &constraint.definition,
+                            ddl::constraint_columns(&constraint.definition),
+                        )?])
+                    }
+                    _ => None,
+                }
+            }
+            ChangeType::CreateIndex => {
+                let index = ddl::parse_create_index(sql)?;
+                let (index_schema, index_table) = ddl::split_qualified_name(&index.table);
+                let index_schema = index_schema.unwrap_or_else(|| change.schema_name.clone());
+
+                let mut attributes = vec![("indexName", Value::from(index.name))];
+                attributes.extend(Self::table_attributes(
+                    "tableName",
+                    &index_schema,
+                    &index_table,
+                ));
+                if index.unique {
+                    attributes.push(("unique", Value::from(true)));
+                }
+
+                Some(vec![Change {
+                    kind: "createIndex",
+                    attributes,
+                    columns: index
+                        .columns
+                        .into_iter()
+                        .map(|name| Column {
+                            attributes: vec![("name", Value::from(name))],
+                            constraints: Vec::new(),
+                        })
+                        .collect(),
+                    text: None,
+                }])
+            }
+            _ => None,
+        }
+    }
+
+    /// `addColumn` restoring the dropped columns from the model, or `None` when
+    /// the definitions are unknown or dropped table constraints must be recreated too
+    fn recreate_columns(change: &SchemaChange) -> Option<Vec<Change>> {
+        let columns = change
+            .details
+            .columns
+            .as_ref()
+            .filter(|columns| !columns.is_empty())?;
+        if change.details.constraints.iter().flatten().next().is_some() {
+            return None;
+        }
+
+        let (schema, table) = Self::table_name(change);
+        let mut changes = vec![Change {
+            kind: "addColumn",
+            attributes: Self::table_attributes("tableName", &schema, &table),
+            columns: columns.iter().map(Self::column).collect::<Option<_>>()?,
+            text: None,
+        }];
+        changes.extend(Self::column_foreign_keys(&schema, &table, columns));
+        Some(changes)
+    }
+
+    /// Rollback from `MigrationGenerator::rollback_sql`, unless it is only a comment
+    fn sql_rollback(change: &SchemaChange) -> Rollback {
+        let sql = MigrationGenerator::rollback_sql(change);
+        if sql.starts_with("--") {
+            Rollback::Unavailable(sql)
+        } else {
+            Rollback::Sql(sql)
+        }
+    }
+
+    /// Schema and table a change applies to, preferring an explicit qualification
+    fn table_name(change: &SchemaChange) -> (String, String) {
+        let (schema, table) = ddl::split_qualified_name(&change.object_name);
+        (schema.unwrap_or_else(|| change.schema_name.clone()), table)
+    }
+
+    fn table_attributes(
+        key: &'static str,
+        schema: &str,
+        table: &str,
+    ) -> Vec<(&'static str, Value)> {
+        vec![
+            (key, Value::from(table)),
+            ("schemaName", Value::from(schema)),
+        ]
+    }
+
+    /// Map a parsed column, or `None` if it carries constraints Liquibase cannot express here
+    fn column(column: &ColumnInfo) -> Option<Column> {
+        let mut attributes = vec![
+            ("name", Value::from(column.name.clone())),
+            ("type", Value::from(column.data_type.clone())),
+        ];
+        if let Some(default) = &column.default {
+            attributes.push(("defaultValueComputed", Value::from(default.clone())));
+        }
+
+        let mut constraints = Vec::new();
+        for constraint in &column.constraints {
+            let upper = constraint.to_uppercase();
+            if upper == "PRIMARY KEY" {
+                constraints.push(("primaryKey", Value::from(true)));
+            } else if upper == "UNIQUE" {
+                constraints.push(("unique", Value::from(true)));
+            } else if !upper.starts_with("REFERENCES") {
+                return None;
+            }
+        }
+        if !column.nullable {
+            constraints.push(("nullable", Value::from(false)));
+        }
+
+        Some(Column {
+            attributes,
+            constraints,
+        })
+    }
+
+    /// `addForeignKeyConstraint` changes for inline `REFERENCES` column constraints
+    fn column_foreign_keys(schema: &str, table: &str, columns: &[ColumnInfo]) -> Vec<Change> {
+        columns
+            .iter()
+            .flat_map(|column| {
+                column
+                    .constraints
+                    .iter()
+                    .filter(|c| c.to_uppercase().starts_with("REFERENCES"))
+                    .filter_map(move |definition| {
+                        Self::foreign_key_change(
+                            schema,
+                            table,
+                            &format!("{}_{}_fkey", table, column.name),
+                            definition,
+                            vec![column.name.clone()],
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    fn foreign_key_change(
+        schema: &str,
+        table: &str,
+        name: &str,
+        definition: &str,
+        columns: Vec<String>,
+    ) -> Option<Change> {
+        let reference = ddl::foreign_key_reference(definition)?;
+        let (referenced_schema, referenced_table) = ddl::split_qualified_name(&reference.table);
+
+        let mut attributes = vec![
+            ("constraintName", Value::from(name)),
+            ("baseTableName", Value::from(table)),
+            ("baseTableSchemaName", Value::from(schema)),
+            ("baseColumnNames", Value::from(columns.join(", "))),
+            ("referencedTableName", Value::from(referenced_table)),
+            (
+                "referencedTableSchemaName",
+                Value::from(referenced_schema.unwrap_or_else(|| schema.to_string())),
+            ),
+        ];
+        if !reference.columns.is_empty() {
+            attributes.push((
+                "referencedColumnNames",
+                Value::from(reference.columns.join(", ")),
+            ));
+        }
+        if let Some(on_delete) = reference.on_delete {
+            attributes.push(("onDelete", Value::from(on_delete)));
+        }
+
+        Some(Change {
+            kind: "addForeignKeyConstraint",
+            attributes,
+            columns: Vec::new(),
+            text: None,
+        })
+    }
+
+    fn sql_change(sql: &str) -> Change {
+        Change {
+            kind: "sql",
+            attributes: Vec::new(),
+            columns: Vec::new(),
+            text: Some(terminate_statement(sql)),
+        }
+    }
+
+    fn render(&self, change_sets: &[ChangeSet]) -> Result<String> {
+        match self.format {
+            ChangelogFormat::Yaml => self.render_yaml(change_sets),
+            ChangelogFormat::Xml => Ok(self.render_xml(change_sets)),
+        }
+    }
+
+    fn render_yaml(&self, change_sets: &[ChangeSet]) -> Result<String> {
+        fn mapping(entries: &[(&'static str, Value)]) -> Mapping {
+            entries
+                .iter()
+                .map(|(key, value)| (Value::from(*key), value.clone()))
+                .collect()
+        }
+
+        fn sql_list(sql: &str) -> Value {
+            let mut body = Mapping::new();
+            body.insert(Value::from("sql"), Value::from(sql));
+            let mut change = Mapping::new();
+            change.insert(Value::from("sql"), Value::Mapping(body));
+            Value::Sequence(vec![Value::Mapping(change)])
+        }
+
+        fn change_list(changes: &[Change]) -> Value {
+            let changes = changes
+                .iter()
+                .map(|change| {
+                    let mut body = mapping(&change.attributes);
+                    if let Some(text) = &change.text {
+                        body.insert(Value::from("sql"), Value::from(text.clone()));
+                    }
+                    if !change.columns.is_empty() {
+                        let columns = change
+                            .columns
+                            .iter()
+                            .map(|column| {
+                                let mut definition = mapping(&column.attributes);
+                                if !column.constraints.is_empty() {
+                                    definition.insert(
+                                        Value::from("constraints"),
+                                        Value::Mapping(mapping(&column.constraints)),
+                                    );
+                                }
+                                let mut wrapper = Mapping::new();
+                                wrapper.insert(Value::from("column"), Value::Mapping(definition));
+                                Value::Mapping(wrapper)
+                            })
+                            .collect();
+                        body.insert(Value::from("columns"), Value::Sequence(columns));
+                    }
+                    let mut wrapper = Mapping::new();
+                    wrapper.insert(Value::from(change.kind), Value::Mapping(body));
+                    Value::Mapping(wrapper)
+                })
+                .collect();
+            Value::Sequence(changes)
+        }
+
+        let entries: Vec<Value> = change_sets
+            .iter()
+            .map(|change_set| {
+                let mut body = Mapping::new();
+                body.insert(Value::from("id"), Value::from(change_set.id.clone()));
+                body.insert(Value::from("author"), Value::from(self.author.clone()));
+                if let Rollback::Unavailable(reason) = &change_set.rollback {
+                    body.insert(Value::from("comment"), Value::from(uncomment(reason)));
+                }
+                body.insert(Value::from("changes"), change_list(&change_set.changes));
+                match &change_set.rollback {
+                    Rollback::Changes(changes) => {
+                        body.insert(Value::from("rollback"), change_list(changes));
+                    }
+                    Rollback::Sql(sql) => {
+                        body.insert(Value::from("rollback"), sql_list(sql));
+                    }
+                    Rollback::Automatic | Rollback::Unavailable(_) => {}
+                }
+
+                let mut wrapper = Mapping::new();
+                wrapper.insert(Value::from("changeSet"), Value::Mapping(body));
+                Value::Mapping(wrapper)
+            })
+            .collect();
+
+        let mut root = Mapping::new();
+        root.insert(Value::from("databaseChangeLog"), Value::Sequence(entries));
+        Ok(serde_yaml::to_string(&root)?)
+    }
+
+    fn render_xml(&self, change_sets: &[ChangeSet]) -> String {
+        fn attributes(entries: &[(&'static str, Value)]) -> String {
+            entries
+                .iter()
+                .map(|(key, value)| format!(" {}=\"{}\"", key, escape_xml(&scalar(value))))
+                .collect()
+        }
+
+        fn push_changes(xml: &mut String, changes: &[Change], indent: &str) {
+            for change in changes {
+                let open = format!(
+                    "{}<{}{}",
+                    indent,
+                    change.kind,
+                    attributes(&change.attributes)
+                );
+                if let Some(text) = &change.text {
+                    xml.push_str(&format!(
+                        "{}>{}</{}>\n",
+                        open,
+                        escape_xml(text),
+                        change.kind
+                    ));
+                } else if change.columns.is_empty() {
+                    xml.push_str(&format!("{}/>\n", open));
+                } else {
+                    xml.push_str(&format!("{}>\n", open));
+                    for column in &change.columns {
+                        if column.constraints.is_empty() {
+                            xml.push_str(&format!(
+                                "{}    <column{}/>\n",
+                                indent,
+                                attributes(&column.attributes)
+                            ));
+                        } else {
+                            xml.push_str(&format!(
+                                "{0}    <column{1}>\n{0}        <constraints{2}/>\n{0}    </column>\n",
+                                indent,
+                                attributes(&column.attributes),
+                                attributes(&column.constraints)
+                            ));
+                        }
+                    }
+                    xml.push_str(&format!("{}</{}>\n", indent, change.kind));
+                }
+            }
+        }
+
+        let mut xml = String::from(XML_HEADER);
+        for change_set in change_sets {
+            xml.push_str(&format!(
+                "    <changeSet id=\"{}\" author=\"{}\">\n",
+                escape_xml(&change_set.id),
+                escape_xml(&self.author)
+            ));
+            if let Rollback::Unavailable(reason) = &change_set.rollback {
+                xml.push_str(&format!(
+                    "        <comment>{}</comment>\n",
+                    escape_xml(&uncomment(reason))
+                ));
+            }
+
+            push_changes(&mut xml, &change_set.changes, "        ");
+
+            match &change_set.rollback {
+                Rollback::Changes(changes) => {
+                    xml.push_str("        <rollback>\n");
+                    push_changes(&mut xml, changes, "            ");
+                    xml.push_str("        </rollback>\n");
+                }
+                Rollback::Sql(sql) => {
+                    xml.push_str(&format!(
+                        "        <rollback>\n            <sql>{}</sql>\n        </rollback>\n",
+                        escape_xml(sql)
+                    ));
+                }
+                Rollback::Automatic | Rollback::Unavailable(_) => {}
+            }
+            xml.push_str("    </changeSet>\n");
+        }
+        xml.push_str(XML_FOOTER);
+        xml
+    }
+
+    /// Add an include for `relative_path` to the master changelog, creating it if needed
+    fn append_to_master(&self, relative_path: &str) -> Result<PathBuf> {
+        let master_path = self.output_dir.join(&self.master_changelog);
+        let existing = std::fs::read_to_string(&master_path).ok();
+
+        let content = match self.format {
+            ChangelogFormat::Yaml => {
+                let mut content = existing.unwrap_or_else(|| "databaseChangeLog:\n".to_string());
+                if !content.ends_with('\n') {
+                    content.push('\n');
+                }
+                content.push_str(&format!(
+                    "- include:\n    file: {}\n    relativeToChangelogFile: true\n",
+                    relative_path
+                ));
+                content
+            }
+            ChangelogFormat::Xml => {
+                let existing = existing.unwrap_or_else(|| format!("{}{}", XML_HEADER, XML_FOOTER));
+                let include = format!(
+                    "    <include file=\"{}\" relativeToChangelogFile=\"true\"/>\n",
+                    escape_xml(relative_path)
+                );
+                match existing.rfind("</databaseChangeLog>") {
+                    Some(idx) => format!("{}{}{}", &existing[..idx], include, &existing[idx..]),
+                    None => anyhow::bail!(
+                        "Master changelog {} has no closing </databaseChangeLog>",
+                        master_path.display()
+                    ),
+                }
+            }
+        };
+
+        naming::replace_atomic(&master_path, &content)?;
+        Ok(master_path)
+    }
+}
+
+impl MigrationSink for LiquibaseSink {
+    fn write(&self, name: &str, changes: &[SchemaChange]) -> Result<Vec<PathBuf>> {
+        let changes_dir = self.output_dir.join(CHANGES_DIR);
+        std::fs::create_dir_all(&changes_dir)?;
+
+        let (filename, filepath) = naming::with_fresh_name(|| {
+            let base_name = self.namer.render(name, changes, &self.list()?)?;
+            let filename = format!("{}.{}", base_name, self.format.extension());
+            let filepath = changes_dir.join(&filename);
+
+            let content = self.render(&Self::change_sets(&base_name, changes))?;
+            naming::write_atomic(&filepath, &content)?;
+            Ok((filename, filepath))
+        })?;
+        let master_path = self.append_to_master(&format!("{}/{}", CHANGES_DIR, filename))?;
+
+        info!("Liquibase changelog written to: {}", filepath.display());
+        Ok(vec![filepath, master_path])
+    }
+
+    fn list(&self) -> Result<Vec<PathBuf>> {
+        let mut changelogs = Vec::new();
+        let changes_dir = self.output_dir.join(CHANGES_DIR);
+
+        if !changes_dir.exists() {
+            return Ok(changelogs);
+        }
+
+        for entry in std::fs::read_dir(&changes_dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|ext| ext == self.format.extension())
+            {
+                changelogs.push(path);
+            }
+        }
+
+        changelogs.sort();
+        Ok(changelogs)
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Text of a SQL comment without its `--` markers
+fn uncomment(comment: &str) -> String {
+    comment
+        .lines()
+        .map(|line| line.trim_start_matches("--").trim())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes() -> Vec<SchemaChange> {
+        vec![
+            SchemaChange::new(
+                ChangeType::CreateTable,
+                "public".to_string(),
+                "orders".to_string(),
+                "CREATE TABLE orders (id SERIAL PRIMARY KEY, user_id int NOT NULL REFERENCES users(id))"
+                    .to_string(),
+            ),
+            SchemaChange::new(
+                ChangeType::AlterTable,
+                "public".to_string(),
+                "orders".to_string(),
+                "ALTER TABLE orders RENAME COLUMN user_id TO customer_id".to_string(),
+            ),
+            SchemaChange::new(
+                ChangeType::AddConstraint,
+                "public".to_string(),
+                "orders".to_string(),
+                "ALTER TABLE orders ADD CONSTRAINT ck_id CHECK (id > 0)".to_string(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_yaml_change_sets() {
+        let sink = LiquibaseSink::new(
+            PathBuf::new(),
+            ChangelogFormat::Yaml,
+            "dba".to_string(),
+            "db.changelog-master.yaml".to_string(),
+            MigrationNamer::new("{seq}_{name}".to_string()),
+        );
+        let yaml = sink
+            .render(&LiquibaseSink::change_sets("0001_m", &changes()))
+            .unwrap();
+        let parsed: Value = serde_yaml::from_str(&yaml).unwrap();
+        let sets = parsed["databaseChangeLog"].as_sequence().unwrap();
+
+        assert_eq!(sets.len(), 3);
+        assert_eq!(sets[0]["changeSet"]["id"], Value::from("0001_m-1"));
+        assert_eq!(sets[0]["changeSet"]["author"], Value::from("dba"));
+
+        let create = &sets[0]["changeSet"]["changes"][0]["createTable"];
+        assert_eq!(create["tableName"], Value::from("orders"));
+        assert_eq!(
+            create["columns"][0]["column"]["constraints"]["primaryKey"],
+            Value::from(true)
+        );
+        let foreign_key = &sets[0]["changeSet"]["changes"][1]["addForeignKeyConstraint"];
+        assert_eq!(foreign_key["referencedTableName"], Value::from("users"));
+        assert_eq!(
+            foreign_key["constraintName"],
+            Value::from("orders_user_id_fkey")
+        );
+
+        let rename = &sets[1]["changeSet"]["changes"][0]["renameColumn"];
+        assert_eq!(rename["newColumnName"], Value::from("customer_id"));
+
+        let sql = &sets[2]["changeSet"];
+        assert!(sql["changes"][0]["sql"]["sql"]
+            .as_str()
+            .unwrap()
+            .contains("CHECK (id > 0)"));
+        assert_eq!(
+            sql["rollback"][0]["sql"]["sql"],
+            Value::from("ALTER TABLE orders DROP CONSTRAINT IF EXISTS ck_id")
+        );
+    }
+
+    #[test]
+    fn test_rollbacks() {
+        let sink = LiquibaseSink::new(
+            PathBuf::new(),
+            ChangelogFormat::Yaml,
+            "dba".to_string(),
+            "db.changelog-master.yaml".to_string(),
+            MigrationNamer::new("{seq}_{name}".to_string()),
+        );
+        let drop = |sql: &str| {
+            SchemaChange::new(
+                ChangeType::DropColumn,
+                "public".to_string(),
+                "orders".to_string(),
+                sql.to_string(),
+            )
+        };
+        let changes = vec![
+            drop("ALTER TABLE orders DROP COLUMN note").with_columns(vec![ColumnInfo {
+                name: "note".to_string(),
+                data_type: "text".to_string(),
+                nullable: false,
+                default: Some("''::text".to_string()),
+                constraints: Vec::new(),
+            }]),
+            drop("ALTER TABLE orders DROP COLUMN legacy"),
+            SchemaChange::new(
+                ChangeType::Other("CREATE FUNCTION".to_string()),
+                "public".to_string(),
+                "touch".to_string(),
+                "CREATE FUNCTION touch() RETURNS void LANGUAGE sql AS 'SELECT 1'".to_string(),
+            ),
+        ];
+        let yaml = sink
+            .render(&LiquibaseSink::change_sets("0001_m", &changes))
+            .unwrap();
+        let parsed: Value = serde_yaml::from_str(&yaml).unwrap();
+        let sets = parsed["databaseChangeLog"].as_sequence().unwrap();
+
+        let restored = &sets[0]["changeSet"]["rollback"][0]["addColumn"];
+        assert_eq!(restored["tableName"], Value::from("orders"));
+        let column = &restored["columns"][0]["column"];
+        assert_eq!(column["name"], Value::from("note"));
+        assert_eq!(column["defaultValueComputed"], Value::from("''::text"));
+        assert_eq!(column["constraints"]["nullable"], Value::from(false));
+
+        for set in &sets[1..] {
+            let set = &set["changeSet"];
+            assert!(set.get("rollback").is_none(), "{:?}", set);
+            assert!(set["comment"]
+                .as_str()
+                .unwrap()
+                .starts_with("Rollback for: "));
+        }
+
+        let xml = sink.render_xml(&LiquibaseSink::change_sets("0001_m", &changes));
+        assert!(xml.contains(
+            "        <rollback>\n            <addColumn tableName=\"orders\" schemaName=\"public\">\n"
+        ));
+        assert!(xml.contains(
+            "        <comment>Rollback for: ALTER TABLE orders DROP COLUMN legacy</comment>\n"
+        ));
+        assert_eq!(xml.matches("<rollback>").count(), 1);
+    }
+
+    #[test]
+    fn test_xml_appends_to_master() {
+        let dir = std::env::temp_dir().join(format!("repligrate-liquibase-{}", std::process::id()));
+        let sink = LiquibaseSink::new(
+            dir.clone(),
+            ChangelogFormat::Xml,
+            "repligrate".to_string(),
+            "db.changelog-master.xml".to_string(),
+            MigrationNamer::new("{seq}_{name}".to_string()),
+        );
+
+        let first = sink.write("first", &changes()).unwrap();
+        let changelog = std::fs::read_to_string(&first[0]).unwrap();
+        sink.append_to_master("changes/second.xml").unwrap();
+        let master = std::fs::read_to_string(&first[1]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(changelog.contains("<createTable tableName=\"orders\" schemaName=\"public\">"));
+        assert!(changelog.contains("<constraints primaryKey=\"true\" nullable=\"false\"/>"));
+        assert!(changelog.contains("<sql>ALTER TABLE orders ADD CONSTRAINT ck_id CHECK (id &gt; 0);</sql>"));
+        assert!(first[0].ends_with("changes/0001_first.xml"));
+        assert!(master.find("0001_first.xml").unwrap() < master.find("second.xml").unwrap());
+        assert!(master.trim_end().ends_with("</databaseChangeLog>"));
+    }
+}
@@ -0,0 +1,88 @@
+mod liquibase;
+mod pgroll;
+mod sql;
+mod sqitch;
+
+pub use self::liquibase::{ChangelogFormat, LiquibaseSink};
+pub use self::pgroll::PgrollSink;
+pub use self::sqitch::SqitchSink;
+pub use self::sql::SqlSink;
+
+use crate::config::Config;
+use crate::naming::MigrationNamer;
+use crate::schema::SchemaChange;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Migration tool that generated migrations are written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputBackend {
+    /// pgroll JSON/YAML migrations
+    #[default]
+    Pgroll,
+    /// Numbered `NNNN_name.up.sql` / `NNNN_name.down.sql` pairs
+    Sql,
+    /// Liquibase changelogs included from a master changelog
+    Liquibase,
+    /// A Sqitch project with deploy/revert/verify scripts
+    Sqitch,
+}
+
+impl std::str::FromStr for OutputBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "pgroll" => Ok(OutputBackend::Pgroll),
+            "sql" => Ok(OutputBackend::Sql),
+            "liquibase" => Ok(OutputBackend::Liquibase),
+            "sqitch" => Ok(OutputBackend::Sqitch),
+            _ => Err(anyhow::anyhow!("Unknown output backend: {}", s)),
+        }
+    }
+}
+
+/// Destination for migrations generated from an optimized list of schema changes
+pub trait MigrationSink {
+    /// Write a migration and return the files it produced; `name` fills the
+    /// `{name}` placeholder of the naming template
+    fn write(&self, name: &str, changes: &[SchemaChange]) -> Result<Vec<PathBuf>>;
+
+    /// List the migrations this backend has written, in apply order
+    fn list(&self) -> Result<Vec<PathBuf>>;
+}
+
+/// Build the sink selected by the configuration
+pub fn from_config(config: &Config) -> Box<dyn MigrationSink> {
+    let namer = MigrationNamer::new(config.naming.template.clone());
+    match config.output_backend {
+        OutputBackend::Pgroll => Box::new(PgrollSink::new(
+            config.output_dir.clone(),
+            config.output_format,
+            config.pgroll_version,
+            namer,
+        )),
+        OutputBackend::Sql => Box::new(SqlSink::new(config.output_dir.clone(), namer)),
+        OutputBackend::Liquibase => Box::new(LiquibaseSink::new(
+            config.output_dir.clone(),
+            config.liquibase.format,
+            config.liquibase.author.clone(),
+            config.liquibase.master_changelog(),
+            namer,
+        )),
+        OutputBackend::Sqitch => Box::new(SqitchSink::new(
+            config.output_dir.clone(),
+            config.sqitch.project.clone(),
+            config.sqitch.planner_name.clone(),
+            config.sqitch.planner_email.clone(),
+            namer,
+        )),
+    }
+}
+
+/// Terminate a statement with exactly one semicolon
+fn terminate_statement(sql: &str) -> String {
+    format!("{};", sql.trim().trim_end_matches(';').trim_end())
+}
@@ -0,0 +1,47 @@
+use super::MigrationSink;
+use crate::migration::{MigrationGenerator, MigrationWriter, OutputFormat};
+use crate::naming::{self, MigrationNamer};
+use crate::pgroll::PgrollVersion;
+use crate::schema::SchemaChange;
+use anyhow::Result;
+use std::path::PathBuf;
+
+/// Writes pgroll migrations through `MigrationGenerator`
+pub struct PgrollSink {
+    output_dir: PathBuf,
+    format: OutputFormat,
+    version: PgrollVersion,
+    namer: MigrationNamer,
+}
+
+impl PgrollSink {
+    pub fn new(
+        output_dir: PathBuf,
+        format: OutputFormat,
+        version: PgrollVersion,
+        namer: MigrationNamer,
+    ) -> Self {
+        Self {
+            output_dir,
+            format,
+            version,
+            namer,
+        }
+    }
+}
+
+impl MigrationSink for PgrollSink {
+    fn write(&self, name: &str, changes: &[SchemaChange]) -> Result<Vec<PathBuf>> {
+        let filepath = naming::with_fresh_name(|| {
+            // pgroll identifies migrations by name, so it matches the file name
+            let name = self.namer.render(name, changes, &self.list()?)?;
+            let migration = MigrationGenerator::generate(name, changes.to_vec(), self.version)?;
+            MigrationWriter::write(&migration, &self.output_dir, self.format)
+        })?;
+        Ok(vec![filepath])
+    }
+
+    fn list(&self) -> Result<Vec<PathBuf>> {
+        MigrationWriter::list_migrations(&self.output_dir)
+    }
+}
@@ -0,0 +1,528 @@
+use super::{terminate_statement, MigrationSink};
+use crate::ddl;
+use crate::migration::MigrationGenerator;
+use crate::naming::{self, MigrationNamer};
+use crate::schema::{ChangeType, SchemaChange, SchemaChangeParser};
+use anyhow::{bail, Result};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+const PLAN_FILE: &str = "sqitch.plan";
+const CONF_FILE: &str = "sqitch.conf";
+
+/// Maintains a Sqitch project: deploy/revert/verify scripts plus `sqitch.plan` entries
+pub struct SqitchSink {
+    project_dir: PathBuf,
+    project: String,
+    planner_name: String,
+    planner_email: String,
+    namer: MigrationNamer,
+}
+
+impl SqitchSink {
+    pub fn new(
+        project_dir: PathBuf,
+        project: String,
+        planner_name: String,
+        planner_email: String,
+        namer: MigrationNamer,
+    ) -> Self {
+        Self {
+            project_dir,
+            project,
+            planner_name,
+            planner_email,
+            namer,
+        }
+    }
+
+    /// Create `sqitch.conf`, `sqitch.plan` and the script directories if missing
+    fn init_project(&self) -> Result<()> {
+        for dir in ["deploy", "revert", "verify"] {
+            std::fs::create_dir_all(self.project_dir.join(dir))?;
+        }
+
+        Self::create_file(
+            &self.project_dir.join(CONF_FILE),
+            "[core]\n\tengine = pg\n\tplan_file = sqitch.plan\n\ttop_dir = .\n",
+        )?;
+        Self::create_file(
+            &self.project_dir.join(PLAN_FILE),
+            &format!("%syntax-version=1.0.0\n%project={}\n\n", self.project),
+        )?;
+
+        Ok(())
+    }
+
+    /// Write `content` to `path` unless it exists, also when another writer
+    /// creates it first
+    fn create_file(path: &Path, content: &str) -> Result<()> {
+        if path.exists() {
+            return Ok(());
+        }
+        match naming::write_atomic(path, content) {
+            Err(_) if path.exists() => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Change names in plan order
+    fn planned_changes(&self) -> Result<Vec<String>> {
+        let plan_path = self.project_dir.join(PLAN_FILE);
+        if !plan_path.exists() {
+            return Ok(Vec::new());
+        }
+
+        Ok(std::fs::read_to_string(plan_path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| {
+                !line.is_empty()
+                    && !line.starts_with('%')
+                    && !line.starts_with('#')
+                    && !line.starts_with('@')
+            })
+            .filter_map(|line| line.split_whitespace().next())
+            .map(|name| name.trim_start_matches(['+', '-']).to_string())
+            .collect())
+    }
+
+    /// Tables created by a planned change's deploy script, from its
+    /// `-- creates:` lines, or parsed from scripts without them
+    fn tables_created_by(&self, change: &str) -> Vec<String> {
+        let path = self.project_dir.join("deploy").join(format!("{}.sql", change));
+        let Ok(script) = std::fs::read_to_string(path) else {
+            return Vec::new();
+        };
+
+        let recorded: Vec<String> = script
+            .lines()
+            .filter_map(|line| line.strip_prefix(CREATES_HEADER))
+            .map(|table| table.trim().to_string())
+            .collect();
+        if !recorded.is_empty() {
+            return recorded;
+        }
+        ddl::split_statements(&script)
+            .iter()
+            .filter_map(|statement| match SchemaChangeParser::parse(statement) {
+                Some((ChangeType::CreateTable, table)) => Some(normalize_table(&table, "public")),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Earlier changes that created the tables these changes build on or reference
+    fn dependencies(&self, changes: &[SchemaChange]) -> Result<Vec<String>> {
+        let created_here: BTreeSet<String> = changes
+            .iter()
+            .filter(|change| change.change_type == ChangeType::CreateTable)
+            .map(|change| normalize_table(&change.object_name, &change.schema_name))
+            .collect();
+
+        let needed: BTreeSet<String> = changes
+            .iter()
+            .flat_map(referenced_tables)
+            .filter(|table| !created_here.contains(table))
+            .collect();
+        if needed.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // The latest change creating a table wins, in case it was dropped and recreated
+        let mut dependencies = Vec::new();
+        let planned = self.planned_changes()?;
+        for table in &needed {
+            if let Some(change) = planned
+                .iter()
+                .rev()
+                .find(|change| self.tables_created_by(change).contains(table))
+            {
+                if !dependencies.contains(change) {
+                    dependencies.push(change.clone());
+                }
+            }
+        }
+
+        Ok(dependencies)
+    }
+
+    fn render_deploy(&self, name: &str, dependencies: &[String], changes: &[SchemaChange]) -> String {
+        let mut script = format!("-- Deploy {}:{} to pg\n", self.project, name);
+        for dependency in dependencies {
+            script.push_str(&format!("-- requires: {}\n", dependency));
+        }
+        // Unqualified names in the statements resolve to the captured schema
+        for change in changes
+            .iter()
+            .filter(|change| change.change_type == ChangeType::CreateTable)
+        {
+            script.push_str(&format!(
+                "{}{}\n",
+                CREATES_HEADER,
+                normalize_table(&change.object_name, &change.schema_name)
+            ));
+        }
+        script.push_str("\nBEGIN;\n\n");
+        for change in changes {
+            script.push_str(&terminate_statement(&change.details.sql));
+            script.push_str("\n\n");
+        }
+        script.push_str("COMMIT;\n");
+        script
+    }
+
+    fn render_revert(&self, name: &str, changes: &[SchemaChange]) -> String {
+        let mut script = format!("-- Revert {}:{} from pg\n\nBEGIN;\n\n", self.project, name);
+        for change in changes.iter().rev() {
+            let rollback = MigrationGenerator::rollback_sql(change);
+            if rollback.starts_with("--") {
+                script.push_str(&rollback);
+            } else {
+                script.push_str(&terminate_statement(&rollback));
+            }
+            script.push_str("\n\n");
+        }
+        script.push_str("COMMIT;\n");
+        script
+    }
+
+    /// Queries failing with a division by zero when a created object is missing
+    fn render_verify(&self, name: &str, changes: &[SchemaChange]) -> String {
+        let mut script = format!("-- Verify {}:{} on pg\n\nBEGIN;\n\n", self.project, name);
+        for change in changes {
+            for check in verify_queries(change) {
+                script.push_str(&check);
+                script.push_str("\n\n");
+            }
+        }
+        script.push_str("ROLLBACK;\n");
+        script
+    }
+}
+
+impl MigrationSink for SqitchSink {
+    fn write(&self, name: &str, changes: &[SchemaChange]) -> Result<Vec<PathBuf>> {
+        self.init_project()?;
+
+        let dependencies = self.dependencies(changes)?;
+        let (change_name, [deploy, revert, verify]) = naming::with_fresh_name(|| {
+            // Scripts another writer has not planned yet still take their names
+            let mut existing = self.list()?;
+            for entry in std::fs::read_dir(self.project_dir.join("deploy"))? {
+                existing.push(entry?.path());
+            }
+            let change_name = self.namer.render(name, changes, &existing)?;
+            let name = change_name.as_str();
+            if self.planned_changes()?.iter().any(|planned| planned == name) {
+                bail!("Sqitch change {} is already in {}", name, PLAN_FILE);
+            }
+
+            let script_path = |dir: &str| self.project_dir.join(dir).join(format!("{}.sql", name));
+            let deploy = script_path("deploy");
+            let revert = script_path("revert");
+            let verify = script_path("verify");
+            naming::write_all_atomic(&[
+                (deploy.clone(), self.render_deploy(name, &dependencies, changes)),
+                (revert.clone(), self.render_revert(name, changes)),
+                (verify.clone(), self.render_verify(name, changes)),
+            ])?;
+            Ok((change_name, [deploy, revert, verify]))
+        })?;
+        let name = change_name.as_str();
+
+        let plan_path = self.project_dir.join(PLAN_FILE);
+        let mut plan = std::fs::read_to_string(&plan_path)?;
+        if !plan.ends_with('\n') {
+            plan.push('\n');
+        }
+        let requires = if dependencies.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", dependencies.join(" "))
+        };
+        plan.push_str(&format!(
+            "{}{} {} {} <{}> # Generated by repligrate\n",
+            name,
+            requires,
+            chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+            self.planner_name,
+            self.planner_email
+        ));
+        if let Err(err) = naming::replace_atomic(&plan_path, &plan) {
+            // Scripts missing from the plan would block the name for good
+            for script in [&deploy, &revert, &verify] {
+                let _ = std::fs::remove_file(script);
+            }
+            return Err(err);
+        }
+
+        info!("Sqitch change {} added to {}", name, plan_path.display());
+        Ok(vec![deploy, revert, verify, plan_path])
+    }
+
+    fn list(&self) -> Result<Vec<PathBuf>> {
+        Ok(self
+            .planned_changes()?
+            .iter()
+            .map(|change| self.project_dir.join("deploy").join(format!("{}.sql", change)))
+            .filter(|path| path.exists())
+            .collect())
+    }
+}
+
+/// Deploy script line naming a table the change creates
+const CREATES_HEADER: &str = "-- creates: ";
+
+/// `schema.table`, defaulting to `default_schema`
+fn normalize_table(name: &str, default_schema: &str) -> String {
+    let (schema, table) = ddl::split_qualified_name(name);
+    format!("{}.{}", schema.as_deref().unwrap_or(default_schema), table)
+}
+
+/// Tables a change needs to exist before it can be deployed
+fn referenced_tables(change: &SchemaChange) -> Vec<String> {
+    let sql = &change.details.sql;
+    let normalize_table = |name: &str| normalize_table(name, &change.schema_name);
+    let mut tables = Vec::new();
+
+    match change.change_type {
+        ChangeType::CreateTable => {
+            if let Some((columns, constraints)) = ddl::parse_create_table(sql) {
+                let definitions = columns
+                    .iter()
+                    .flat_map(|column| column.constraints.iter().cloned())
+                    .chain(constraints.into_iter().map(|c| c.definition));
+                for definition in definitions {
+                    if let Some(reference) = ddl::foreign_key_reference(&definition) {
+                        tables.push(normalize_table(&reference.table));
+                    }
+                }
+            }
+        }
+        ChangeType::CreateIndex => {
+            if let Some(index) = ddl::parse_create_index(sql) {
+                tables.push(normalize_table(&index.table));
+            }
+        }
+        ChangeType::DropTable | ChangeType::DropIndex => {}
+        _ => {
+            tables.push(normalize_table(&change.object_name));
+            for constraint in ddl::parse_add_constraints(sql) {
+                if let Some(reference) = ddl::foreign_key_reference(&constraint.definition) {
+                    tables.push(normalize_table(&reference.table));
+                }
+            }
+        }
+    }
+
+    tables
+}
+
+fn literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn verify_queries(change: &SchemaChange) -> Vec<String> {
+    let table = normalize_table(&change.object_name, &change.schema_name);
+    let (schema, relation) = table
+        .split_once('.')
+        .unwrap_or((&change.schema_name, &table));
+
+    let relation_exists = |schema: &str, name: &str, kinds: &str| {
+        format!(
+            "SELECT 1/COUNT(*) FROM pg_catalog.pg_class c\n  \
+             JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace\n  \
+             WHERE n.nspname = {} AND c.relname = {} AND c.relkind IN ({});",
+            literal(schema),
+            literal(name),
+            kinds
+        )
+    };
+    let regclass = format!(
+        "(SELECT c.oid FROM pg_catalog.pg_class c JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace \
+         WHERE n.nspname = {} AND c.relname = {})",
+        literal(schema),
+        literal(relation)
+    );
+
+    match change.change_type {
+        ChangeType::CreateTable => vec![relation_exists(schema, relation, "'r', 'p'")],
+        ChangeType::AddColumn => ddl::added_column_names(&change.details.sql)
+            .iter()
+            .map(|column| {
+                format!(
+                    "SELECT 1/COUNT(*) FROM pg_catalog.pg_attribute\n  \
+                     WHERE attrelid = {} AND attname = {} AND NOT attisdropped;",
+                    regclass,
+                    literal(column)
+                )
+            })
+            .collect(),
+        ChangeType::AddConstraint => ddl::parse_add_constraints(&change.details.sql)
+            .iter()
+            .map(|constraint| {
+                format!(
+                    "SELECT 1/COUNT(*) FROM pg_catalog.pg_constraint\n  \
+                     WHERE conrelid = {} AND conname = {};",
+                    regclass,
+                    literal(&constraint.name)
+                )
+            })
+            .collect(),
+        ChangeType::CreateIndex => {
+            let (index_schema, _) = ddl::parse_create_index(&change.details.sql)
+                .map(|index| ddl::split_qualified_name(&index.table))
+                .unwrap_or((None, String::new()));
+            let (_, index) = ddl::split_qualified_name(&change.object_name);
+            vec![relation_exists(
+                &index_schema.unwrap_or_else(|| change.schema_name.clone()),
+                &index,
+                "'i', 'I'",
+            )]
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn sink(dir: &Path) -> SqitchSink {
+        SqitchSink::new(
+            dir.to_path_buf(),
+            "shop".to_string(),
+            "Repli Grate".to_string(),
+            "repligrate@example.com".to_string(),
+            MigrationNamer::new("{name}".to_string()),
+        )
+    }
+
+    #[test]
+    fn test_dependencies_follow_foreign_keys() {
+        let dir = std::env::temp_dir().join(format!("repligrate-sqitch-{}", std::process::id()));
+        let sink = sink(&dir);
+
+        sink.write(
+            "users",
+            &[SchemaChange::new(
+                ChangeType::CreateTable,
+                "public".to_string(),
+                "users".to_string(),
+                "CREATE TABLE users (id SERIAL PRIMARY KEY)".to_string(),
+            )],
+        )
+        .unwrap();
+        let written = sink
+            .write(
+                "orders",
+                &[SchemaChange::new(
+                    ChangeType::CreateTable,
+                    "public".to_string(),
+                    "orders".to_string(),
+                    "CREATE TABLE orders (id SERIAL PRIMARY KEY, user_id int REFERENCES users(id))"
+                        .to_string(),
+                )],
+            )
+            .unwrap();
+
+        let deploy = std::fs::read_to_string(&written[0]).unwrap();
+        let verify = std::fs::read_to_string(&written[2]).unwrap();
+        let plan = std::fs::read_to_string(&written[3]).unwrap();
+        let duplicate = sink.write("orders", &[]);
+        let listed = sink.list().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(plan.starts_with("%syntax-version=1.0.0\n%project=shop\n"));
+        assert!(plan.contains("\nusers 20"));
+        assert!(plan.contains("\norders [users] 20"));
+        assert!(plan.contains("Repli Grate <repligrate@example.com>"));
+        assert!(deploy.contains("-- requires: users\n"));
+        assert!(verify.contains("c.relname = 'orders' AND c.relkind IN ('r', 'p')"));
+        assert!(verify.trim_end().ends_with("ROLLBACK;"));
+        assert!(duplicate.is_err());
+        assert_eq!(listed.len(), 2);
+    }
+
+    #[test]
+    fn test_unqualified_names_use_the_captured_schema() {
+        let dir =
+            std::env::temp_dir().join(format!("repligrate-sqitch-app-{}", std::process::id()));
+        let sink = sink(&dir);
+        let create = |table: &str, sql: &str| {
+            SchemaChange::new(
+                ChangeType::CreateTable,
+                "app".to_string(),
+                table.to_string(),
+                sql.to_string(),
+            )
+        };
+
+        sink.write(
+            "users",
+            &[create("users", "CREATE TABLE users (id int PRIMARY KEY)")],
+        )
+        .unwrap();
+        // A table of the same name in another schema is not a dependency
+        sink.write(
+            "public_orders",
+            &[create(
+                "public.orders",
+                "CREATE TABLE public.orders (id int)",
+            )],
+        )
+        .unwrap();
+        let written = sink
+            .write(
+                "orders",
+                &[
+                    create(
+                        "orders",
+                        "CREATE TABLE orders (id int, user_id int REFERENCES users(id))",
+                    ),
+                    SchemaChange::new(
+                        ChangeType::AddColumn,
+                        "app".to_string(),
+                        "orders".to_string(),
+                        "ALTER TABLE orders ADD COLUMN note text".to_string(),
+                    ),
+                ],
+            )
+            .unwrap();
+
+        let deploy = std::fs::read_to_string(&written[0]).unwrap();
+        let verify = std::fs::read_to_string(&written[2]).unwrap();
+        let plan = std::fs::read_to_string(&written[3]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(deploy.contains("-- requires: users\n-- creates: app.orders\n"));
+        assert!(plan.contains("\norders [users] 20"));
+        assert!(verify.contains("n.nspname = 'app' AND c.relname = 'orders'"));
+        assert!(!verify.contains("'public'"));
+    }
+
+    #[test]
+    fn test_multi_line_rollback_comment_stays_commented() {
+        let sink = sink(Path::new("unused"));
+        let revert = sink.render_revert(
+            "add_one",
+            &[SchemaChange::new(
+                ChangeType::Other("CREATE".to_string()),
+                "public".to_string(),
+                String::new(),
+                "CREATE FUNCTION add_one(i int) RETURNS int AS $$\nSELECT i + 1\n$$ LANGUAGE sql"
+                    .to_string(),
+            )],
+        );
+
+        let body: Vec<&str> = revert
+            .lines()
+            .filter(|line| !line.is_empty() && *line != "BEGIN;" && *line != "COMMIT;")
+            .collect();
+        assert!(body.iter().all(|line| line.starts_with("--")), "{}", revert);
+        assert!(revert.contains("-- SELECT i + 1\n"));
+    }
+}
@@ -0,0 +1,151 @@
+use super::{terminate_statement, MigrationSink};
+use crate::migration::MigrationGenerator;
+use crate::naming::{self, MigrationNamer};
+use crate::schema::SchemaChange;
+use anyhow::Result;
+use std::path::PathBuf;
+use tracing::info;
+
+/// Writes numbered up/down SQL pairs, as read by golang-migrate, sqlx and Diesel
+pub struct SqlSink {
+    output_dir: PathBuf,
+    namer: MigrationNamer,
+}
+
+impl SqlSink {
+    pub fn new(output_dir: PathBuf, namer: MigrationNamer) -> Self {
+        Self { output_dir, namer }
+    }
+
+    fn render_up(changes: &[SchemaChange]) -> String {
+        let statements: Vec<String> = changes
+            .iter()
+            .map(|change| terminate_statement(&change.details.sql))
+            .collect();
+        format!("{}\n", statements.join("\n\n"))
+    }
+
+    /// Rollbacks in reverse order, so the last change is undone first
+    fn render_down(changes: &[SchemaChange]) -> String {
+        let statements: Vec<String> = changes
+            .iter()
+            .rev()
+            .map(|change| {
+                let rollback = MigrationGenerator::rollback_sql(change);
+                if rollback.starts_with("--") {
+                    rollback
+                } else {
+                    terminate_statement(&rollback)
+                }
+            })
+            .collect();
+        format!("{}\n", statements.join("\n\n"))
+    }
+}
+
+impl MigrationSink for SqlSink {
+    fn write(&self, name: &str, changes: &[SchemaChange]) -> Result<Vec<PathBuf>> {
+        std::fs::create_dir_all(&self.output_dir)?;
+
+        let (up_path, down_path) = naming::with_fresh_name(|| {
+            let base = self.namer.render(name, changes, &self.list()?)?;
+            let up_path = self.output_dir.join(format!("{}.up.sql", base));
+            let down_path = self.output_dir.join(format!("{}.down.sql", base));
+            naming::write_all_atomic(&[
+                (up_path.clone(), Self::render_up(changes)),
+                (down_path.clone(), Self::render_down(changes)),
+            ])?;
+            Ok((up_path, down_path))
+        })?;
+
+        info!("Migration written to: {}", up_path.display());
+        Ok(vec![up_path, down_path])
+    }
+
+    fn list(&self) -> Result<Vec<PathBuf>> {
+        let mut migrations = Vec::new();
+
+        if !self.output_dir.exists() {
+            return Ok(migrations);
+        }
+
+        for entry in std::fs::read_dir(&self.output_dir)? {
+            let path = entry?.path();
+            if path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.ends_with(".up.sql"))
+            {
+                migrations.push(path);
+            }
+        }
+
+        migrations.sort();
+        Ok(migrations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::ChangeType;
+
+    #[test]
+    fn test_numbered_up_down_pairs() {
+        let dir = std::env::temp_dir().join(format!("repligrate-sql-sink-{}", std::process::id()));
+        let sink = SqlSink::new(dir.clone(), MigrationNamer::new("{seq}_{name}".to_string()));
+        let changes = vec![
+            SchemaChange::new(
+                ChangeType::CreateTable,
+                "public".to_string(),
+                "users".to_string(),
+                "CREATE TABLE users (id SERIAL PRIMARY KEY)".to_string(),
+            ),
+            SchemaChange::new(
+                ChangeType::AddColumn,
+                "public".to_string(),
+                "users".to_string(),
+                "ALTER TABLE users ADD COLUMN email VARCHAR(255);".to_string(),
+            ),
+        ];
+
+        let first = sink.write("create_users", &changes).unwrap();
+        let second = sink.write("more", &changes[1..]).unwrap();
+
+        let up = std::fs::read_to_string(&first[0]).unwrap();
+        let down = std::fs::read_to_string(&first[1]).unwrap();
+        let listed = sink.list().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(first[0].ends_with("0001_create_users.up.sql"));
+        assert!(first[1].ends_with("0001_create_users.down.sql"));
+        assert!(second[0].ends_with("0002_more.up.sql"));
+        assert_eq!(listed.len(), 2);
+
+        assert_eq!(
+            up,
+            "CREATE TABLE users (id SERIAL PRIMARY KEY);\n\n\
+             ALTER TABLE users ADD COLUMN email VARCHAR(255);\n"
+        );
+        assert_eq!(
+            down,
+            "ALTER TABLE users DROP COLUMN IF EXISTS email;\n\n\
+             DROP TABLE IF EXISTS users;\n"
+        );
+    }
+
+    #[test]
+    fn test_multi_line_rollback_comment_stays_commented() {
+        let changes = vec![SchemaChange::new(
+            ChangeType::Other("CREATE".to_string()),
+            "public".to_string(),
+            String::new(),
+            "CREATE VIEW active_users AS\n  SELECT id\n  FROM users".to_string(),
+        )];
+
+        assert_eq!(
+            SqlSink::render_down(&changes),
+            "-- Rollback for: CREATE VIEW active_users AS\n--   SELECT id\n--   FROM users\n"
+        );
+    }
+}
@@ -0,0 +1,404 @@
+use crate::capture;
+use crate::ident::quote_ident;
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::BytesMut;
+use postgres::config::{Host, SslMode};
+use postgres::fallible_iterator::FallibleIterator;
+use postgres_protocol::authentication::{self, sasl};
+use postgres_protocol::message::backend::{ErrorResponseBody, Message};
+use postgres_protocol::message::frontend;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use tracing::{debug, info};
+
+/// Query identifying the server a session is on, compared between the main
+/// and the replication connection
+pub const SERVER_QUERY: &str = "SELECT extract(epoch FROM pg_postmaster_start_time())::text";
+
+trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+/// Connection speaking the streaming replication protocol
+///
+/// The `postgres` client has no replication mode, so this implements the
+/// little of the protocol needed to create a slot: startup, authentication
+/// and simple queries.
+pub struct ReplicationConnection {
+    stream: Box<dyn Stream>,
+    buffer: BytesMut,
+}
+
+/// A slot created together with a snapshot of the database at its start
+///
+/// The snapshot can be imported with `SET TRANSACTION SNAPSHOT` for as long
+/// as this is kept.
+pub struct ExportedSlot {
+    pub slot_name: String,
+    /// First position the slot decodes from
+    pub consistent_point: String,
+    pub snapshot_name: String,
+    _connection: ReplicationConnection,
+}
+
+#[allow(dead_code)]
+impl ReplicationConnection {
+    /// Open a replication connection to the server of `config` whose
+    /// `SERVER_QUERY` returns `server`
+    ///
+    /// Hosts are tried in the order the main connection tries them. TLS is not
+    /// supported, so `sslmode=require` is refused.
+    pub fn connect(config: &postgres::Config, server: &str) -> Result<Self> {
+        if !matches!(config.get_ssl_mode(), SslMode::Disable | SslMode::Prefer) {
+            bail!("The replication connection does not support TLS; use sslmode=disable or prefer to create the slot");
+        }
+
+        let mut errors = Vec::new();
+        for target in targets(config)? {
+            match Self::connect_to(config, &target, server) {
+                Ok(connection) => return Ok(connection),
+                Err(err) => errors.push(format!("{}: {:#}", target, err)),
+            }
+        }
+        bail!(
+            "No replication connection to the server: {}",
+            errors.join("; ")
+        )
+    }
+
+    fn connect_to(config: &postgres::Config, target: &Target, server: &str) -> Result<Self> {
+        let stream: Box<dyn Stream> = match target {
+            Target::Tcp(host, port) => {
+                Box::new(tcp_connect(host, *port, config.get_connect_timeout())?)
+            }
+            #[cfg(unix)]
+            Target::Unix(path) => Box::new(std::os::unix::net::UnixStream::connect(path)?),
+            #[cfg(not(unix))]
+            Target::Unix(_) => bail!("Unix sockets are not supported"),
+        };
+
+        let user = match config.get_user() {
+            Some(user) => user.to_string(),
+            None => std::env::var("USER").context("The database URL has no user")?,
+        };
+        let database = config.get_dbname().unwrap_or(&user).to_string();
+
+        let mut connection = Self {
+            stream,
+            buffer: BytesMut::new(),
+        };
+        connection.send(|buf| {
+            frontend::startup_message(
+                [
+                    ("user", user.as_str()),
+                    ("database", database.as_str()),
+                    ("replication", "database"),
+                    ("application_name", capture::APPLICATION_NAME),
+                ],
+                buf,
+            )
+        })?;
+        connection.authenticate(&user, config.get_password())?;
+
+        let reached = connection.simple_query(SERVER_QUERY)?;
+        if reached
+            .first()
+            .and_then(|row| row.first())
+            .cloned()
+            .flatten()
+            .as_deref()
+            != Some(server)
+        {
+            bail!("not the server the main connection is on");
+        }
+        debug!("Opened a replication connection to {}", database);
+        Ok(connection)
+    }
+
+    /// Create a logical slot exporting a snapshot of the database as of the
+    /// slot's consistent point
+    pub fn create_slot(mut self, slot_name: &str) -> Result<ExportedSlot> {
+        let rows = self.simple_query(&create_slot_command(slot_name))?;
+        let row = rows
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("CREATE_REPLICATION_SLOT returned no row"))?;
+        let column = |index: usize| -> Result<String> {
+            row.get(index)
+                .cloned()
+                .flatten()
+                .ok_or_else(|| anyhow!("CREATE_REPLICATION_SLOT returned no column {}", index))
+        };
+
+        let slot = ExportedSlot {
+            slot_name: column(0)?,
+            consistent_point: column(1)?,
+            snapshot_name: column(2)?,
+            _connection: self,
+        };
+        info!(
+            "Created replication slot {} at {} with snapshot {}",
+            slot.slot_name, slot.consistent_point, slot.snapshot_name
+        );
+        Ok(slot)
+    }
+
+    /// Run a query, returning its rows as text
+    fn simple_query(&mut self, sql: &str) -> Result<Vec<Vec<Option<String>>>> {
+        self.send(|buf| frontend::query(sql, buf))?;
+
+        let mut rows = Vec::new();
+        let mut error = None;
+        loop {
+            match self.receive()? {
+                Message::DataRow(body) => {
+                    let row = body
+                        .ranges()
+                        .map(|range| {
+                            Ok(range.map(|range| {
+                                String::from_utf8_lossy(&body.buffer()[range]).into_owned()
+                            }))
+                        })
+                        .collect()?;
+                    rows.push(row);
+                }
+                Message::ErrorResponse(body) => error = Some(server_error(&body)?),
+                Message::ReadyForQuery(_) => break,
+                _ => {}
+            }
+        }
+
+        match error {
+            Some(error) => Err(error),
+            None => Ok(rows),
+        }
+    }
+
+    fn authenticate(&mut self, user: &str, password: Option<&[u8]>) -> Result<()> {
+        let password = || password.ok_or_else(|| anyhow!("The server asked for a password"));
+        let mut scram = None;
+        loop {
+            match self.receive()? {
+                Message::AuthenticationCleartextPassword => {
+                    let password = password()?;
+                    self.send(|buf| frontend::password_message(password, buf))?;
+                }
+                Message::AuthenticationMd5Password(body) => {
+                    let hash = authentication::md5_hash(user.as_bytes(), password()?, body.salt());
+                    self.send(|buf| frontend::password_message(hash.as_bytes(), buf))?;
+                }
+                Message::AuthenticationSasl(body) => {
+                    let mechanisms: Vec<&str> = body.mechanisms().collect()?;
+                    if !mechanisms.contains(&sasl::SCRAM_SHA_256) {
+                        bail!("Unsupported authentication mechanisms {:?}", mechanisms);
+                    }
+                    let exchange =
+                        sasl::ScramSha256::new(password()?, sasl::ChannelBinding::unsupported());
+                    self.send(|buf| {
+                        frontend::sasl_initial_response(
+                            sasl::SCRAM_SHA_256,
+                            exchange.message(),
+                            buf,
+                        )
+                    })?;
+                    scram = Some(exchange);
+                }
+                Message::AuthenticationSaslContinue(body) => {
+                    let exchange = scram
+                        .as_mut()
+                        .ok_or_else(|| anyhow!("Unexpected SASL message"))?;
+                    exchange.update(body.data())?;
+                    let message = exchange.message().to_vec();
+                    self.send(|buf| frontend::sasl_response(&message, buf))?;
+                }
+                Message::AuthenticationSaslFinal(body) => {
+                    scram
+                        .as_mut()
+                        .ok_or_else(|| anyhow!("Unexpected SASL message"))?
+                        .finish(body.data())?;
+                }
+                Message::ErrorResponse(body) => return Err(server_error(&body)?),
+                Message::ReadyForQuery(_) => return Ok(()),
+                _ => {}
+            }
+        }
+    }
+
+    fn send(&mut self, build: impl FnOnce(&mut BytesMut) -> io::Result<()>) -> Result<()> {
+        let mut buf = BytesMut::new();
+        build(&mut buf)?;
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Message> {
+        loop {
+            if let Some(message) = Message::parse(&mut self.buffer)? {
+                return Ok(message);
+            }
+            let mut chunk = [0; 8192];
+            let read = self.stream.read(&mut chunk)?;
+            if read == 0 {
+                bail!("The server closed the replication connection");
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+impl Drop for ReplicationConnection {
+    fn drop(&mut self) {
+        let _ = self.send(|buf| {
+            frontend::terminate(buf);
+            Ok(())
+        });
+    }
+}
+
+/// Where the replication connection can go
+#[derive(Debug, PartialEq)]
+enum Target {
+    Tcp(String, u16),
+    Unix(PathBuf),
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Target::Tcp(host, port) => write!(f, "{}:{}", host, port),
+            Target::Unix(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// The hosts of `config` in the order the `postgres` client tries them,
+/// each with its port, preferring `hostaddr` over `host`
+fn targets(config: &postgres::Config) -> Result<Vec<Target>> {
+    let hosts = config.get_hosts();
+    let hostaddrs = config.get_hostaddrs();
+    let ports = config.get_ports();
+    let count = hosts.len().max(hostaddrs.len());
+    if count == 0 {
+        bail!("The database URL has no host");
+    }
+    if ports.len() > 1 && ports.len() != count {
+        bail!(
+            "The database URL has {} ports for {} hosts",
+            ports.len(),
+            count
+        );
+    }
+
+    (0..count)
+        .map(|i| {
+            let port = ports
+                .get(i)
+                .or_else(|| ports.first())
+                .copied()
+                .unwrap_or(5432);
+            if let Some(addr) = hostaddrs.get(i) {
+                return Ok(Target::Tcp(addr.to_string(), port));
+            }
+            match &hosts[i] {
+                Host::Tcp(host) => Ok(Target::Tcp(host.clone(), port)),
+                #[cfg(unix)]
+                Host::Unix(dir) => Ok(Target::Unix(dir.join(format!(".s.PGSQL.{}", port)))),
+            }
+        })
+        .collect()
+}
+
+/// Connect to the first address `host` resolves to that accepts
+fn tcp_connect(host: &str, port: u16, timeout: Option<&std::time::Duration>) -> Result<TcpStream> {
+    let mut last_error = None;
+    for addr in (host, port).to_socket_addrs()? {
+        let stream = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&addr, *timeout),
+            None => TcpStream::connect(addr),
+        };
+        match stream {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_error = Some(err),
+        }
+    }
+    Err(match last_error {
+        Some(err) => err.into(),
+        None => anyhow!("{} resolves to no address", host),
+    })
+}
+
+/// Command creating a test_decoding slot that exports its snapshot
+fn create_slot_command(slot_name: &str) -> String {
+    format!(
+        "CREATE_REPLICATION_SLOT {} LOGICAL test_decoding EXPORT_SNAPSHOT",
+        quote_ident(slot_name)
+    )
+}
+
+/// The error an `ErrorResponse` reports
+fn server_error(body: &ErrorResponseBody) -> Result<anyhow::Error> {
+    let mut severity = String::new();
+    let mut message = String::new();
+    let mut fields = body.fields();
+    while let Some(field) = fields.next()? {
+        let value = String::from_utf8_lossy(field.value_bytes()).into_owned();
+        match field.type_() {
+            b'S' => severity = value,
+            b'M' => message = value,
+            _ => {}
+        }
+    }
+    Ok(anyhow!("{}: {}", severity, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_slot_command() {
+        assert_eq!(
+            create_slot_command("repligrate_slot"),
+            "CREATE_REPLICATION_SLOT \"repligrate_slot\" LOGICAL test_decoding EXPORT_SNAPSHOT"
+        );
+    }
+
+    #[test]
+    fn test_targets_follow_the_host_order() {
+        let config: postgres::Config = "host=primary,standby port=5433,5434 user=u"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            targets(&config).unwrap(),
+            [
+                Target::Tcp("primary".to_string(), 5433),
+                Target::Tcp("standby".to_string(), 5434)
+            ]
+        );
+
+        let config: postgres::Config = "postgres://u@a,b:6000/db".parse().unwrap();
+        let names: Vec<String> = targets(&config)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(names, ["a:5432", "b:6000"]);
+
+        let config: postgres::Config = "host=db.internal hostaddr=10.0.0.5 user=u".parse().unwrap();
+        assert_eq!(
+            targets(&config).unwrap(),
+            [Target::Tcp("10.0.0.5".to_string(), 5432)]
+        );
+
+        let config: postgres::Config = "host=a,b,c port=1,2 user=u".parse().unwrap();
+        assert!(targets(&config).is_err());
+    }
+
+    #[test]
+    fn test_tls_is_refused() {
+        let config: postgres::Config = "host=localhost user=u sslmode=require".parse().unwrap();
+        let err = ReplicationConnection::connect(&config, "0").err().unwrap();
+        assert!(err.to_string().contains("does not support TLS"));
+    }
+}
@@ -0,0 +1,105 @@
+use crate::db::DbConnection;
+use crate::filter::SchemaFilter;
+use crate::loader;
+use crate::model::SchemaModel;
+use anyhow::{bail, Context, Result};
+use postgres::GenericClient;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Version of the snapshot document, bumped on incompatible changes
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Schema of a database as a stable JSON document
+///
+/// Everything is keyed and sorted by name, except columns, which keep their
+/// table order, so two snapshots of the same schema are byte for byte equal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    #[serde(flatten)]
+    pub model: SchemaModel,
+}
+
+#[allow(dead_code)]
+impl Snapshot {
+    /// Snapshot the schemas and tables `filter` keeps
+    pub fn capture(client: &mut impl GenericClient, filter: &SchemaFilter) -> Result<Self> {
+        let mut model = SchemaModel::load(client)?;
+        model.retain(filter);
+        Ok(Self::new(model))
+    }
+
+    /// Snapshot of a `postgres://` URL, of the schema a directory of pgroll
+    /// migrations builds, or one read from a file
+    pub fn open(source: &str, filter: &SchemaFilter) -> Result<Self> {
+        if source.starts_with("postgres://") || source.starts_with("postgresql://") {
+            let mut db = DbConnection::new(source)?;
+            return Self::capture(db.client(), filter);
+        }
+        if Path::new(source).is_dir() {
+            let mut model = loader::load(&PathBuf::from(source))?;
+            model.retain(filter);
+            return Ok(Self::new(model));
+        }
+        let mut snapshot = Self::read(Path::new(source))?;
+        snapshot.model.retain(filter);
+        Ok(snapshot)
+    }
+
+    pub fn new(mut model: SchemaModel) -> Self {
+        model.normalize();
+        Self {
+            version: SNAPSHOT_VERSION,
+            model,
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        let snapshot: Self = serde_json::from_str(json)?;
+        if snapshot.version > SNAPSHOT_VERSION {
+            bail!(
+                "Snapshot version {} is newer than the supported version {}",
+                snapshot.version,
+                SNAPSHOT_VERSION
+            );
+        }
+        Ok(snapshot)
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read snapshot {}", path.display()))?;
+        Self::from_json(&json).with_context(|| format!("Invalid snapshot {}", path.display()))
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(format!("{}\n", serde_json::to_string_pretty(self)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{ChangeType, SchemaChange};
+
+    #[test]
+    fn test_round_trip_is_stable() {
+        let mut model = SchemaModel::default();
+        model.apply(SchemaChange::new(
+            ChangeType::CreateTable,
+            "public".to_string(),
+            "users".to_string(),
+            "CREATE TABLE users (id int, email text, CONSTRAINT users_pkey PRIMARY KEY (id), CONSTRAINT b_unique UNIQUE (email))"
+                .to_string(),
+        ));
+
+        let json = Snapshot::new(model).to_json().unwrap();
+        assert!(json.starts_with("{\n  \"version\": 1,\n  \"schemas\": {"));
+        let table = &Snapshot::from_json(&json).unwrap().model.schemas["public"].tables["users"];
+        assert_eq!(table.constraints[0].name, "b_unique");
+        assert_eq!(Snapshot::from_json(&json).unwrap().to_json().unwrap(), json);
+
+        assert!(Snapshot::from_json("{\"version\": 2, \"schemas\": {}}").is_err());
+    }
+}
@@ -1,11 +1,20 @@
-use std::sync::atomic::{AtomicBool, Ordering};
+use crate::metrics::ListenerMetrics;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use tracing::info;
 
-/// Manages the pause/continue state of the replication listener
+/// Manages the pause/continue state of the replication listener, and the
+/// requests other threads make to it
 #[derive(Clone)]
 pub struct ListenerState {
     paused: Arc<AtomicBool>,
+    pending: Arc<AtomicUsize>,
+    flush_requested: Arc<AtomicBool>,
+    shutdown_requested: Arc<AtomicBool>,
+    reload_requested: Arc<AtomicBool>,
+    ready: Arc<AtomicBool>,
+    metrics: ListenerMetrics,
 }
 
 #[allow(dead_code)]
@@ -14,6 +23,12 @@ impl ListenerState {
     pub fn new() -> Self {
         Self {
             paused: Arc::new(AtomicBool::new(false)),
+            pending: Arc::new(AtomicUsize::new(0)),
+            flush_requested: Arc::new(AtomicBool::new(false)),
+            shutdown_requested: Arc::new(AtomicBool::new(false)),
+            reload_requested: Arc::new(AtomicBool::new(false)),
+            ready: Arc::new(AtomicBool::new(false)),
+            metrics: ListenerMetrics::new(),
         }
     }
 
@@ -44,6 +59,70 @@ impl ListenerState {
         }
     }
 
+    /// Record how many captured changes are waiting to be written
+    pub fn set_pending(&self, count: usize) {
+        self.pending.store(count, Ordering::SeqCst);
+    }
+
+    /// Number of captured changes waiting to be written
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    /// Ask the listener to write pending changes without waiting for the timeout
+    pub fn request_flush(&self) {
+        self.flush_requested.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether a flush was requested, clearing the request
+    pub fn take_flush_request(&self) -> bool {
+        self.flush_requested.swap(false, Ordering::SeqCst)
+    }
+
+    /// Ask the listener to write pending changes and stop
+    pub fn request_shutdown(&self) {
+        self.shutdown_requested.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the listener should stop
+    pub fn shutdown_requested(&self) -> bool {
+        self.shutdown_requested.load(Ordering::SeqCst)
+    }
+
+    /// Ask the listener to reload its configuration
+    pub fn request_reload(&self) {
+        self.reload_requested.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether a reload was requested, clearing the request
+    pub fn take_reload_request(&self) -> bool {
+        self.reload_requested.swap(false, Ordering::SeqCst)
+    }
+
+    /// Mark the slot, publication and capture as set up
+    pub fn set_ready(&self) {
+        self.ready.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the listener is set up and not shutting down
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst) && !self.shutdown_requested()
+    }
+
+    /// Counters of the replication pipeline
+    pub fn metrics(&self) -> &ListenerMetrics {
+        &self.metrics
+    }
+
+    /// Snapshot of the state for other processes
+    pub fn snapshot(&self) -> ListenerStatus {
+        ListenerStatus {
+            pid: std::process::id(),
+            state: self.status().to_string(),
+            pending_changes: self.pending(),
+        }
+    }
+
     /// Get the current state as a string
     pub fn status(&self) -> &'static str {
         if self.is_paused() {
@@ -60,6 +139,14 @@ impl Default for ListenerState {
     }
 }
 
+/// Listener state as reported to other processes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListenerStatus {
+    pub pid: u32,
+    pub state: String,
+    pub pending_changes: usize,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -101,6 +188,27 @@ mod tests {
         assert!(!state.is_paused());
     }
 
+    #[test]
+    fn test_requests() {
+        let state = ListenerState::new();
+        let other = state.clone();
+
+        other.request_flush();
+        assert!(state.take_flush_request());
+        assert!(!state.take_flush_request());
+
+        other.request_reload();
+        assert!(state.take_reload_request());
+        assert!(!state.take_reload_request());
+
+        other.set_ready();
+        assert!(state.is_ready());
+
+        other.request_shutdown();
+        assert!(state.shutdown_requested());
+        assert!(!state.is_ready());
+    }
+
     #[test]
     fn test_clone_shares_state() {
         let state1 = ListenerState::new();
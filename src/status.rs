@@ -0,0 +1,180 @@
+use crate::capture;
+use crate::control::{self, ControlRequest};
+use crate::db::{DbConnection, EventTriggerInfo, PublicationInfo, SlotInfo};
+use crate::state::ListenerStatus;
+use anyhow::Result;
+use serde::Serialize;
+
+/// Output format of the status command
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum StatusFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Everything `repligrate status` reports
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusReport {
+    pub slot_name: String,
+    pub slot: Option<SlotInfo>,
+    pub publication_name: String,
+    pub publication: Option<PublicationInfo>,
+    pub event_trigger: Option<EventTriggerInfo>,
+    pub listener: Option<ListenerStatus>,
+}
+
+impl StatusReport {
+    /// Gather the state of the server-side objects and of the running listener
+    pub fn collect(db: &mut DbConnection, slot_name: &str, publication_name: &str) -> Result<Self> {
+        // No answer on the control socket means no listener is running
+        let listener = control::send(slot_name, ControlRequest::Status).ok();
+
+        Ok(Self {
+            slot_name: slot_name.to_string(),
+            slot: db.get_slot_info(slot_name)?,
+            publication_name: publication_name.to_string(),
+            publication: db.get_publication_info(publication_name)?,
+            event_trigger: db.get_event_trigger(capture::EVENT_TRIGGER)?,
+            listener,
+        })
+    }
+
+    pub fn render(&self, format: StatusFormat) -> Result<String> {
+        match format {
+            StatusFormat::Text => Ok(self.to_text()),
+            StatusFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+        }
+    }
+
+    fn to_text(&self) -> String {
+        let mut rows: Vec<(String, String)> = Vec::new();
+        let mut row = |key: &str, value: String| rows.push((key.to_string(), value));
+        let or_none = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+
+        match &self.slot {
+            Some(slot) => {
+                row("Slot", slot.slot_name.clone());
+                row("  plugin", or_none(slot.plugin.clone()));
+                row(
+                    "  active",
+                    match slot.active_pid {
+                        Some(pid) => format!("yes (pid {})", pid),
+                        None => "no".to_string(),
+                    },
+                );
+                row("  confirmed_flush_lsn", slot.confirmed_flush_lsn.clone());
+                row("  restart_lsn", or_none(slot.restart_lsn.clone()));
+                row(
+                    "  retained WAL",
+                    or_none(slot.retained_wal_bytes.map(format_bytes)),
+                );
+            }
+            None => row("Slot", format!("{} (not found)", self.slot_name)),
+        }
+
+        match &self.publication {
+            Some(publication) => {
+                row("Publication", publication.name.clone());
+                let members = if publication.all_tables {
+                    "all tables".to_string()
+                } else {
+                    let mut members: Vec<String> = publication
+                        .schemas
+                        .iter()
+                        .map(|schema| format!("schema {}", schema))
+                        .collect();
+                    members.push(format!("{} table(s)", publication.tables.len()));
+                    members.join(", ")
+                };
+                row("  covers", members);
+            }
+            None => row("Publication", format!("{} (not found)", self.publication_name)),
+        }
+
+        row(
+            "Event trigger",
+            match &self.event_trigger {
+                Some(trigger) if trigger.enabled => format!("{} on {}", trigger.name, trigger.event),
+                Some(trigger) => format!("{} (disabled)", trigger.name),
+                None => "not installed".to_string(),
+            },
+        );
+
+        match &self.listener {
+            Some(listener) => {
+                row("Listener", format!("{} (pid {})", listener.state, listener.pid));
+                row("  pending changes", listener.pending_changes.to_string());
+            }
+            None => row("Listener", "not running".to_string()),
+        }
+
+        let width = rows.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+        rows.iter()
+            .map(|(key, value)| format!("{:width$}  {}\n", key, value, width = width))
+            .collect()
+    }
+}
+
+/// Human-readable byte count, such as `16.0 MiB`
+fn format_bytes(bytes: i64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value.abs() >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_report() {
+        let report = StatusReport {
+            slot_name: "repligrate_slot".to_string(),
+            slot: Some(SlotInfo {
+                slot_name: "repligrate_slot".to_string(),
+                slot_type: "logical".to_string(),
+                datoid: Some(5),
+                confirmed_flush_lsn: "0/19D1D70".to_string(),
+                plugin: Some("test_decoding".to_string()),
+                active: false,
+                active_pid: None,
+                restart_lsn: Some("0/19D1A50".to_string()),
+                retained_wal_bytes: Some(3 * 1024 * 1024),
+            }),
+            publication_name: "repligrate_pub".to_string(),
+            publication: None,
+            event_trigger: None,
+            listener: None,
+        };
+
+        let text = report.render(StatusFormat::Text).unwrap();
+        let value = |key: &str| {
+            let line = text.lines().find(|line| line.trim_start().starts_with(key)).unwrap();
+            line.trim_start()[key.len()..].trim().to_string()
+        };
+        assert_eq!(value("retained WAL"), "3.0 MiB");
+        assert_eq!(value("Publication"), "repligrate_pub (not found)");
+        assert_eq!(value("Listener"), "not running");
+
+        let json: serde_json::Value =
+            serde_json::from_str(&report.render(StatusFormat::Json).unwrap()).unwrap();
+        assert_eq!(json["slot"]["plugin"], "test_decoding");
+        assert!(json["listener"].is_null());
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+    }
+}
@@ -1,6 +1,13 @@
 use anyhow::Result;
+use lazy_static::lazy_static;
+use regex::Regex;
 use tracing::debug;
 
+lazy_static! {
+    static ref LOGICAL_MESSAGE_RE: Regex =
+        Regex::new(r"(?s)^message: transactional: ([01]) prefix: (.*?), sz: \d+ content:(.*)$").unwrap();
+}
+
 /// Represents a WAL (Write-Ahead Log) message
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
@@ -11,6 +18,14 @@ pub struct WalMessage {
     pub is_commit: bool,
 }
 
+/// Message emitted with `pg_logical_emit_message()`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogicalMessage {
+    pub transactional: bool,
+    pub prefix: String,
+    pub content: String,
+}
+
 /// Parser for WAL messages
 pub struct WalMessageParser;
 
@@ -57,6 +72,21 @@ impl WalMessageParser {
         None
     }
 
+    /// Parse a logical message as rendered by the `test_decoding` plugin
+    pub fn parse_logical_message(data: &str) -> Option<LogicalMessage> {
+        let captures = LOGICAL_MESSAGE_RE.captures(data)?;
+        Some(LogicalMessage {
+            transactional: &captures[1] == "1",
+            prefix: captures[2].to_string(),
+            content: captures[3].to_string(),
+        })
+    }
+
+    /// Whether a `test_decoding` row is the end of a transaction
+    pub fn is_commit_row(data: &str) -> bool {
+        data.starts_with("COMMIT")
+    }
+
     /// Extract SQL statement from WAL message
     pub fn extract_sql(message: &str) -> Option<String> {
         // Look for common SQL keywords
@@ -100,6 +130,19 @@ impl WalMessageParser {
     }
 }
 
+/// Parse an LSN such as `0/16B3748` into its 64-bit position
+pub fn parse_lsn(lsn: &str) -> Option<u64> {
+    let (high, low) = lsn.split_once('/')?;
+    let high = u32::from_str_radix(high, 16).ok()?;
+    let low = u32::from_str_radix(low, 16).ok()?;
+    Some((u64::from(high) << 32) | u64::from(low))
+}
+
+/// Format a 64-bit WAL position the way PostgreSQL prints LSNs
+pub fn format_lsn(lsn: u64) -> String {
+    format!("{:X}/{:X}", lsn >> 32, lsn & 0xFFFF_FFFF)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -111,6 +154,26 @@ mod tests {
         assert_eq!(lsn, Some("0/12345678".to_string()));
     }
 
+    #[test]
+    fn test_lsn_round_trip() {
+        assert_eq!(parse_lsn("0/16B3748"), Some(0x16B3748));
+        assert_eq!(parse_lsn("1/0"), Some(1 << 32));
+        assert_eq!(format_lsn(0x1_016B_3748), "1/16B3748");
+        assert_eq!(parse_lsn("nope"), None);
+    }
+
+    #[test]
+    fn test_parse_logical_message() {
+        let message = WalMessageParser::parse_logical_message(
+            "message: transactional: 1 prefix: repligrate, sz: 21 content:{\"query\": \"a, b\"}",
+        )
+        .unwrap();
+        assert!(message.transactional);
+        assert_eq!(message.prefix, "repligrate");
+        assert_eq!(message.content, "{\"query\": \"a, b\"}");
+        assert!(WalMessageParser::parse_logical_message("table public.t: INSERT: id[integer]:1").is_none());
+    }
+
     #[test]
     fn test_is_ddl() {
         assert!(WalMessageParser::is_ddl("CREATE TABLE users (id INT)"));